@@ -2,5 +2,5 @@
 
 # Builds webassembly binary and generates wrapper JS code to run it
 
-cargo build --release
+cargo build --release --lib
 wasm-bindgen --target bundler --out-dir packages/worker/src/wasm target/wasm32-unknown-unknown/release/light_simulation.wasm
\ No newline at end of file
@@ -40,6 +40,8 @@ export async function startRender(preview = false) {
       ...settings,
       maxBounceCount: 16,
       samplesPerPixel: 1,
+      adaptiveThreshold: 0,
+      denoise: true,
       width: Math.min(settings.width / 4, 160),
       height: Math.min(settings.height / 4, 90),
     };
@@ -9,8 +9,11 @@ export type MessageFromWorker =
   | { type: "lastframe"; image: ImageBitmap; stats: RenderStats }
   | { type: "error"; error: string };
 
-export type MessageToWorker = {
-  type: "renderRequest";
-  objects: SceneObject[];
-  settings: RenderSettings;
-};
+export type MessageToWorker =
+  | {
+      type: "renderRequest";
+      objects: SceneObject[];
+      settings: RenderSettings;
+    }
+  /** Stop the current render, without starting a new one */
+  | { type: "cancel" };
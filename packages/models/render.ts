@@ -7,6 +7,13 @@ export interface RenderSettings {
   height: number;
   maxBounceCount: number;
   samplesPerPixel: number;
+  /**
+   * Relative error below which pixels stop receiving samples,
+   * 0 disables adaptive sampling
+   */
+  adaptiveThreshold: number;
+  /** Filter out the noise of the image, meant for low sample count previews */
+  denoise: boolean;
 }
 
 export interface RenderStats {
@@ -33,4 +40,6 @@ export const getDefaultSettings = (): RenderSettings => ({
   height: 1080 / 3,
   maxBounceCount: 128,
   samplesPerPixel: 10,
+  adaptiveThreshold: 0.02,
+  denoise: false,
 });
@@ -0,0 +1,342 @@
+//! Native command line front end of the renderer
+// Sockets, processes and files are only there natively, browsers use the wasm module
+#![cfg_attr(target_arch = "wasm32", no_main)]
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::{
+    env, fs,
+    net::TcpListener,
+    path::Path,
+    process::{Command, ExitCode},
+    time::{Duration, Instant},
+};
+
+use light_simulation::{
+    farm::{self, FarmSettings},
+    raytrace::{
+        DenoiseSettings, ExrPrecision, ImageChannel, ImageLayer, Render, Tile, write_exr, write_hdr,
+    },
+    scene_file::SceneFile,
+};
+
+/// How often the progress is saved with `--checkpoint`
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+const USAGE: &str = "\
+Usage:
+  lightsim render <scene> -o <output.exr|output.hdr> [options]
+  lightsim merge <scene> <checkpoint>... -o <output.exr|output.hdr> [options]
+  lightsim coordinate <scene> -o <output.exr|output.hdr> [options]
+  lightsim worker <address>
+
+Options:
+  -o, --output <path>   Where to write the image, format is chosen by the extension
+  -s, --samples <n>     Override the sample count of the scene file
+      --half            Write 16-bit floats instead of 32-bit ones into EXR files
+      --aovs            Render auxiliary buffers, written as extra EXR layers
+      --denoise         Filter out the noise. Also renders the auxiliary buffers
+      --crop <x,y,w,h>  Only render the pixels inside of the rectangle
+      --checkpoint <path>
+                        Save the progress into the file every few seconds, and
+                        continue from it, if it already exists
+
+Coordinator options:
+      --listen <address>
+                        Where workers connect to, 127.0.0.1:7878 by default
+      --workers <n>     Spawn this many local worker processes
+      --job-samples <n> Samples per pixel rendered by a worker at a time
+      --deadline <secs> Give up if the render isn't done after this many seconds";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let result = match args.first().map(String::as_str) {
+        Some("render") => render(&args[1..]),
+        Some("merge") => merge(&args[1..]),
+        Some("coordinate") => coordinate(&args[1..]),
+        Some("worker") => worker(&args[1..]),
+        Some("help" | "-h" | "--help") => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        _ => Err(USAGE.to_string()),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Options shared by the commands
+#[derive(Default)]
+struct Options {
+    positional: Vec<String>,
+    output: Option<String>,
+    samples: Option<usize>,
+    half: bool,
+    aovs: bool,
+    denoise: bool,
+    crop: Option<Tile>,
+    checkpoint: Option<String>,
+    listen: Option<String>,
+    workers: usize,
+    job_samples: Option<usize>,
+    deadline: Option<Duration>,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{arg} expects a value"))
+        };
+
+        match arg.as_str() {
+            "-o" | "--output" => options.output = Some(value()?),
+            "-s" | "--samples" => options.samples = Some(parse_count(&value()?)?),
+            "--listen" => options.listen = Some(value()?),
+            "--workers" => options.workers = parse_count(&value()?)?,
+            "--job-samples" => options.job_samples = Some(parse_count(&value()?)?),
+            "--deadline" => {
+                let seconds = value()?;
+                let deadline = seconds
+                    .parse()
+                    .ok()
+                    .and_then(|s| Duration::try_from_secs_f64(s).ok())
+                    .ok_or_else(|| format!("invalid deadline \"{seconds}\""))?;
+                options.deadline = Some(deadline);
+            }
+            "--half" => options.half = true,
+            "--aovs" => options.aovs = true,
+            "--denoise" => options.denoise = true,
+            "--checkpoint" => options.checkpoint = Some(value()?),
+            "--crop" => {
+                let crop = value()?;
+                let values: Vec<usize> = crop
+                    .split(',')
+                    .map(|v| v.trim().parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| format!("invalid crop rectangle \"{crop}\""))?;
+                let [x, y, width, height] = values[..] else {
+                    return Err(format!("crop rectangle \"{crop}\" expects 4 values"));
+                };
+                options.crop = Some(Tile::new(x, y, width, height));
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {arg}")),
+            _ => options.positional.push(arg.clone()),
+        }
+    }
+
+    Ok(options)
+}
+
+fn parse_count(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid count \"{value}\""))
+}
+
+fn load_scene(path: &str) -> Result<SceneFile, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    SceneFile::parse(&text).map_err(|e| format!("{path}: {e}"))
+}
+
+fn write_image(render: &Render, path: &str, options: &Options) -> Result<(), String> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    let width = render.config.width;
+    let height = render.config.height;
+    let denoised = options
+        .denoise
+        .then(|| render.denoised(&DenoiseSettings::default()));
+
+    let bytes = match extension.as_deref() {
+        Some("exr") => {
+            let precision = if options.half {
+                ExrPrecision::Half
+            } else {
+                ExrPrecision::Float
+            };
+
+            let mut layers = render.layers();
+            if let Some(denoised) = denoised {
+                let channel = |name: &str, component: usize| ImageChannel {
+                    name: name.to_string(),
+                    samples: denoised.iter().map(|p| p[component] as f32).collect(),
+                };
+
+                layers.push(ImageLayer {
+                    name: "denoised".to_string(),
+                    channels: vec![channel("R", 0), channel("G", 1), channel("B", 2)],
+                });
+            }
+
+            write_exr(width, height, &layers, precision).map_err(|e| e.to_string())?
+        }
+        Some("hdr") => match denoised {
+            Some(denoised) => write_hdr(width, height, &denoised),
+            None => render.to_hdr(),
+        },
+        _ => return Err(format!("{path}: unsupported image format")),
+    };
+
+    fs::write(path, bytes).map_err(|e| format!("{path}: {e}"))
+}
+
+/// Replace the checkpoint file in one go, so that it's never left half written
+fn write_checkpoint(checkpoint: &[u8], path: &str) -> Result<(), String> {
+    let temporary = format!("{path}.tmp");
+    fs::write(&temporary, checkpoint).map_err(|e| format!("{temporary}: {e}"))?;
+    fs::rename(&temporary, path).map_err(|e| format!("{path}: {e}"))
+}
+
+fn render(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let [scene_path] = &options.positional[..] else {
+        return Err(USAGE.to_string());
+    };
+    let output = options.output.as_deref().ok_or(USAGE)?;
+
+    let mut file = load_scene(scene_path)?;
+    if let Some(samples) = options.samples {
+        file.config.samples_per_pixel = samples;
+    }
+    file.config.aovs |= options.aovs || options.denoise;
+    if options.crop.is_some() {
+        file.config.crop = options.crop;
+    }
+
+    let mut scene = file.build();
+    if let Some(path) = &options.checkpoint
+        && Path::new(path).exists()
+    {
+        let checkpoint = fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+        scene
+            .resume(&checkpoint)
+            .map_err(|e| format!("{path}: {e}"))?;
+    }
+
+    let mut last_checkpoint = Instant::now();
+    while !scene.is_finished() {
+        scene.sample();
+        eprint!("\r{:.1}%", scene.render.convergence() * 100.);
+
+        if let Some(path) = &options.checkpoint
+            && last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL
+        {
+            write_checkpoint(&scene.checkpoint(), path)?;
+            last_checkpoint = Instant::now();
+        }
+    }
+    eprintln!();
+
+    if let Some(path) = &options.checkpoint {
+        write_checkpoint(&scene.checkpoint(), path)?;
+    }
+
+    write_image(&scene.render, output, &options)
+}
+
+/// Combine checkpoints of the same scene, rendered separately, into one image
+fn merge(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let [scene_path, checkpoints @ ..] = &options.positional[..] else {
+        return Err(USAGE.to_string());
+    };
+    if checkpoints.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    let output = options.output.as_deref().ok_or(USAGE)?;
+
+    let mut scene_file = load_scene(scene_path)?;
+    scene_file.config.aovs |= options.aovs || options.denoise;
+    let mut scene = scene_file.build();
+
+    for (index, path) in checkpoints.iter().enumerate() {
+        let checkpoint = fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+        let result = if index == 0 {
+            scene.resume(&checkpoint)
+        } else {
+            scene.merge_checkpoint(&checkpoint)
+        };
+        result.map_err(|e| format!("{path}: {e}"))?;
+    }
+
+    write_image(&scene.render, output, &options)
+}
+
+/// Render a scene on worker processes, possibly running on other machines
+fn coordinate(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let [scene_path] = &options.positional[..] else {
+        return Err(USAGE.to_string());
+    };
+    let output = options.output.as_deref().ok_or(USAGE)?;
+    let scene = fs::read_to_string(scene_path).map_err(|e| format!("{scene_path}: {e}"))?;
+
+    let address = options.listen.as_deref().unwrap_or("127.0.0.1:7878");
+    let listener = TcpListener::bind(address).map_err(|e| format!("{address}: {e}"))?;
+    let address = listener.local_addr().map_err(|e| e.to_string())?;
+    eprintln!("listening on {address}");
+
+    let executable = env::current_exe().map_err(|e| e.to_string())?;
+    let mut workers = Vec::new();
+    for _ in 0..options.workers {
+        let worker = Command::new(&executable)
+            .args(["worker", &address.to_string()])
+            .spawn()
+            .map_err(|e| format!("failed to spawn a worker: {e}"))?;
+        workers.push(worker);
+    }
+
+    let defaults = FarmSettings::default();
+    let settings = FarmSettings {
+        samples_per_job: options.job_samples.unwrap_or(defaults.samples_per_job),
+        aovs: options.aovs || options.denoise,
+        samples: options.samples,
+        deadline: options.deadline,
+        ..defaults
+    };
+    let mut reported = None;
+    let result = farm::coordinate(listener, &scene, settings, |finished, total| {
+        if reported != Some(finished) {
+            eprint!("\r{finished}/{total} jobs");
+            reported = Some(finished);
+        }
+        // Without any workers left, the coordinator would wait for them forever
+        farm::check_workers(&mut workers)
+    });
+    eprintln!();
+
+    for mut worker in workers {
+        // Without a coordinator, the workers would wait for jobs forever
+        if result.is_err() {
+            let _ = worker.kill();
+        }
+        let _ = worker.wait();
+    }
+
+    let scene = result.map_err(|e| format!("{scene_path}: {e}"))?;
+    write_image(&scene.render, output, &options)
+}
+
+/// Render jobs handed out by a coordinator
+fn worker(args: &[String]) -> Result<(), String> {
+    let [address] = args else {
+        return Err(USAGE.to_string());
+    };
+
+    let jobs = farm::work(address.as_str()).map_err(|e| format!("{address}: {e}"))?;
+    eprintln!("worker finished {jobs} jobs");
+    Ok(())
+}
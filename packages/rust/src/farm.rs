@@ -0,0 +1,569 @@
+//! A coordinator splits the samples of a scene into jobs, and hands them out
+//! to any number of workers connected over TCP. Workers render their share of
+//! the samples, and send back a checkpoint, which the coordinator merges into
+//! the final render. Jobs of workers that disconnect, or take too long, are
+//! handed out again, as are jobs whose results can't be merged, a few times.
+//!
+//! Every message is framed as a kind byte, followed by the little endian
+//! `u64` length of the payload, and the payload itself:
+//!
+//! | Kind | Direction              | Payload                                           |
+//! |------|------------------------|---------------------------------------------------|
+//! | 0    | worker → coordinator   | ready for a job, empty                            |
+//! | 1    | coordinator → worker   | job: `u64` id, `u64` samples, `u8` AOVs, scene file |
+//! | 2    | worker → coordinator   | result: `u64` job id, checkpoint                  |
+//! | 3    | coordinator → worker   | no work left, empty                               |
+
+use std::{
+    collections::VecDeque,
+    fmt, io,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    process::Child,
+    sync::{Arc, Condvar, Mutex, mpsc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    raytrace::{CheckpointError, Scene},
+    scene_file::{ParseError, SceneFile},
+};
+
+/// Error which stops the coordinator or a worker
+#[derive(Debug)]
+pub enum FarmError {
+    Io(io::Error),
+    Scene(ParseError),
+    /// The other side sent something that doesn't follow the protocol
+    Protocol(String),
+    /// The result of a job couldn't be merged, every time it was rendered
+    Rejected {
+        job: u64,
+        error: CheckpointError,
+    },
+    /// Not all jobs were done before [FarmSettings::deadline]
+    DeadlineExceeded {
+        finished: usize,
+        total: usize,
+    },
+    /// Every local worker exited, so nobody is left to render the pending jobs
+    WorkersExited,
+}
+
+impl fmt::Display for FarmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FarmError::Io(error) => write!(f, "{error}"),
+            FarmError::Scene(error) => write!(f, "scene: {error}"),
+            FarmError::Protocol(message) => write!(f, "protocol error: {message}"),
+            FarmError::Rejected { job, error } => {
+                write!(f, "the results of job {job} kept being rejected: {error}")
+            }
+            FarmError::DeadlineExceeded { finished, total } => {
+                write!(f, "only {finished} of {total} jobs were done in time")
+            }
+            FarmError::WorkersExited => write!(f, "every worker exited before the render was done"),
+        }
+    }
+}
+
+impl std::error::Error for FarmError {}
+
+impl From<io::Error> for FarmError {
+    fn from(error: io::Error) -> Self {
+        FarmError::Io(error)
+    }
+}
+
+impl From<ParseError> for FarmError {
+    fn from(error: ParseError) -> Self {
+        FarmError::Scene(error)
+    }
+}
+
+/// Messages exchanged between the coordinator and the workers
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Message {
+    Ready,
+    Job(Job),
+    Result { id: u64, checkpoint: Vec<u8> },
+    Done,
+}
+
+/// Share of the samples of a scene, rendered by one worker
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Job {
+    pub id: u64,
+    pub samples: usize,
+    pub aovs: bool,
+    pub scene: String,
+}
+
+impl Message {
+    pub(crate) fn write(&self, stream: &mut impl Write) -> io::Result<()> {
+        let (kind, payload) = match self {
+            Message::Ready => (0, Vec::new()),
+            Message::Job(job) => {
+                let mut payload = Vec::with_capacity(17 + job.scene.len());
+                payload.extend_from_slice(&job.id.to_le_bytes());
+                payload.extend_from_slice(&(job.samples as u64).to_le_bytes());
+                payload.push(job.aovs as u8);
+                payload.extend_from_slice(job.scene.as_bytes());
+                (1, payload)
+            }
+            Message::Result { id, checkpoint } => {
+                let mut payload = Vec::with_capacity(8 + checkpoint.len());
+                payload.extend_from_slice(&id.to_le_bytes());
+                payload.extend_from_slice(checkpoint);
+                (2, payload)
+            }
+            Message::Done => (3, Vec::new()),
+        };
+
+        stream.write_all(&[kind])?;
+        stream.write_all(&(payload.len() as u64).to_le_bytes())?;
+        stream.write_all(&payload)?;
+        stream.flush()
+    }
+
+    pub(crate) fn read(stream: &mut impl Read) -> Result<Self, FarmError> {
+        let mut header = [0; 9];
+        stream.read_exact(&mut header)?;
+        if header[0] > 3 {
+            return Err(FarmError::Protocol(format!(
+                "unknown message kind {}",
+                header[0]
+            )));
+        }
+
+        // Don't trust the length enough to allocate all of it up front
+        let length = u64::from_le_bytes(header[1..].try_into().unwrap());
+        let mut payload = Vec::new();
+        stream.take(length).read_to_end(&mut payload)?;
+        if payload.len() as u64 != length {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        let malformed = || FarmError::Protocol(format!("malformed message of kind {}", header[0]));
+        let u64_at = |offset: usize| -> Result<u64, FarmError> {
+            let bytes = payload.get(offset..offset + 8).ok_or_else(malformed)?;
+            Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+        };
+
+        match header[0] {
+            0 => Ok(Message::Ready),
+            1 => Ok(Message::Job(Job {
+                id: u64_at(0)?,
+                samples: u64_at(8)? as usize,
+                aovs: *payload.get(16).ok_or_else(malformed)? != 0,
+                scene: String::from_utf8(payload[17..].to_vec()).map_err(|_| malformed())?,
+            })),
+            2 => Ok(Message::Result {
+                id: u64_at(0)?,
+                checkpoint: payload[8..].to_vec(),
+            }),
+            _ => Ok(Message::Done),
+        }
+    }
+}
+
+/// How the coordinator splits up the work
+#[derive(Clone, Copy, Debug)]
+pub struct FarmSettings {
+    /// Samples per pixel rendered by a single job. Smaller jobs spread the work
+    /// more evenly, but every job sends back a checkpoint of the whole image
+    pub samples_per_job: usize,
+    /// A worker which doesn't answer for this long is considered lost
+    pub timeout: Duration,
+    /// How often a job is handed out before giving up on it, if its results
+    /// can't be merged, like those of workers running another build
+    pub max_attempts: usize,
+    /// Give up on the whole render after this long
+    pub deadline: Option<Duration>,
+    /// Render the AOVs as well
+    pub aovs: bool,
+    /// Override the sample count of the scene file
+    pub samples: Option<usize>,
+}
+
+impl Default for FarmSettings {
+    fn default() -> Self {
+        Self {
+            samples_per_job: 4,
+            timeout: Duration::from_secs(600),
+            max_attempts: 3,
+            deadline: None,
+            aovs: false,
+            samples: None,
+        }
+    }
+}
+
+/// Jobs waiting for a worker, shared by the connection threads
+struct Queue {
+    pending: VecDeque<Job>,
+    finished: bool,
+}
+
+/// Render the scene described by the `scene` file on the workers connecting
+/// to the `listener`, until all of its samples are done.
+///
+/// `progress` is called with the number of finished and total jobs every time
+/// the coordinator checks for results, and stops it by returning an error, like
+/// [check_workers] does. Returns the scene with the merged render, or an error
+/// once a job was rejected [FarmSettings::max_attempts] times, or the
+/// [FarmSettings::deadline] has passed
+pub fn coordinate(
+    listener: TcpListener,
+    scene: &str,
+    settings: FarmSettings,
+    mut progress: impl FnMut(usize, usize) -> Result<(), FarmError>,
+) -> Result<Scene, FarmError> {
+    let mut file = SceneFile::parse(scene)?;
+    file.config.aovs = settings.aovs;
+    if let Some(samples) = settings.samples {
+        file.config.samples_per_pixel = samples;
+    }
+    let mut result = file.build();
+
+    // Split the samples into jobs
+    let samples_per_job = settings.samples_per_job.max(1);
+    let mut pending = VecDeque::new();
+    let mut remaining = file.config.samples_per_pixel;
+    while remaining > 0 {
+        let samples = remaining.min(samples_per_job);
+        pending.push_back(Job {
+            id: pending.len() as u64,
+            samples,
+            aovs: settings.aovs,
+            scene: scene.to_string(),
+        });
+        remaining -= samples;
+    }
+    let total = pending.len();
+
+    let queue = Arc::new((
+        Mutex::new(Queue {
+            pending,
+            finished: false,
+        }),
+        Condvar::new(),
+    ));
+    let (results, finished_jobs) = mpsc::channel();
+
+    // Accept workers without blocking, so that results can be merged in between
+    listener.set_nonblocking(true)?;
+    let started = Instant::now();
+    let mut connections = Vec::new();
+    let mut attempts = vec![0; total];
+    let mut finished = 0;
+
+    let outcome = 'coordinating: loop {
+        // Once every job is done, there's nothing left to stop
+        let stopped = progress(finished, total);
+        if finished == total {
+            break Ok(());
+        }
+        if let Err(error) = stopped {
+            break Err(error);
+        }
+        if settings
+            .deadline
+            .is_some_and(|deadline| started.elapsed() > deadline)
+        {
+            break Err(FarmError::DeadlineExceeded { finished, total });
+        }
+
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(settings.timeout))?;
+
+                let queue = queue.clone();
+                let results = results.clone();
+                connections.push(thread::spawn(move || {
+                    serve_worker(stream, &queue, &results)
+                }));
+            }
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {}
+            Err(error) => break Err(error.into()),
+        }
+
+        while let Ok((job, checkpoint)) = finished_jobs.try_recv() {
+            match result.merge_checkpoint(&checkpoint) {
+                Ok(()) => finished += 1,
+                // A worker running a different build of the renderer, try again elsewhere,
+                // but not forever, the same worker may well pick the job up again
+                Err(error) => {
+                    attempts[job.id as usize] += 1;
+                    if attempts[job.id as usize] >= settings.max_attempts {
+                        break 'coordinating Err(FarmError::Rejected { job: job.id, error });
+                    }
+
+                    let (queue, available) = &*queue;
+                    queue.lock().unwrap().pending.push_back(job);
+                    available.notify_one();
+                }
+            }
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    // Tell the workers to stop
+    let (state, available) = &*queue;
+    state.lock().unwrap().finished = true;
+    available.notify_all();
+    // Workers still rendering a job would hold up a failed render until they time out
+    if outcome.is_ok() {
+        for connection in connections {
+            let _ = connection.join();
+        }
+    }
+
+    outcome.map(|()| result)
+}
+
+/// Fail once all of the local `workers` started for [coordinate] have exited,
+/// since the jobs they dropped would be pending forever. Meant to be called
+/// from its `progress` callback, and never fails without any local workers
+pub fn check_workers(workers: &mut [Child]) -> Result<(), FarmError> {
+    let running = workers
+        .iter_mut()
+        .any(|worker| matches!(worker.try_wait(), Ok(None)));
+
+    if workers.is_empty() || running {
+        Ok(())
+    } else {
+        Err(FarmError::WorkersExited)
+    }
+}
+
+/// Hand out jobs to a single worker, until there are none left, or the worker is lost
+fn serve_worker(
+    mut stream: TcpStream,
+    queue: &(Mutex<Queue>, Condvar),
+    results: &mpsc::Sender<(Job, Vec<u8>)>,
+) {
+    let (state, available) = queue;
+
+    loop {
+        if !matches!(Message::read(&mut stream), Ok(Message::Ready)) {
+            return;
+        }
+
+        // Wait for a job, failed jobs of other workers may still come back
+        let job = {
+            let mut state = state.lock().unwrap();
+            loop {
+                if state.finished {
+                    let _ = Message::Done.write(&mut stream);
+                    return;
+                }
+                if let Some(job) = state.pending.pop_front() {
+                    break job;
+                }
+                state = available.wait(state).unwrap();
+            }
+        };
+
+        let id = job.id;
+        let checkpoint = Message::Job(job.clone())
+            .write(&mut stream)
+            .map_err(FarmError::from)
+            .and_then(|()| Message::read(&mut stream));
+
+        match checkpoint {
+            Ok(Message::Result {
+                id: result,
+                checkpoint,
+            }) if result == id => {
+                let _ = results.send((job, checkpoint));
+            }
+            // The worker is gone, or misbehaves, so someone else has to do the job
+            _ => {
+                state.lock().unwrap().pending.push_back(job);
+                available.notify_one();
+                return;
+            }
+        }
+    }
+}
+
+/// Connect to a coordinator and render jobs, until it has no work left.
+/// Returns the number of jobs rendered
+pub fn work(address: impl ToSocketAddrs) -> Result<usize, FarmError> {
+    let mut stream = TcpStream::connect(address)?;
+    let mut jobs = 0;
+
+    loop {
+        Message::Ready.write(&mut stream)?;
+
+        let job = match Message::read(&mut stream)? {
+            Message::Job(job) => job,
+            Message::Done => return Ok(jobs),
+            message => {
+                return Err(FarmError::Protocol(format!(
+                    "expected a job, got {message:?}"
+                )));
+            }
+        };
+
+        let mut file = SceneFile::parse(&job.scene)?;
+        file.config.samples_per_pixel = job.samples;
+        file.config.aovs = job.aovs;
+
+        let mut scene = file.build();
+        while !scene.is_finished() {
+            scene.sample();
+        }
+
+        Message::Result {
+            id: job.id,
+            checkpoint: scene.checkpoint(),
+        }
+        .write(&mut stream)?;
+        jobs += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENE: &str =
+        "resolution 8 4\nsamples 5\ncamera 0 0 0 10 0 0\nsphere 10 0 0 3 255 255 255 1\n";
+
+    #[test]
+    // Every sample must be rendered exactly once, even if a worker disappears with a job
+    fn render_farm() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let workers = thread::spawn(move || {
+            // This worker takes a job and disappears, someone else has to render it
+            let mut stream = TcpStream::connect(address).unwrap();
+            Message::Ready.write(&mut stream).unwrap();
+            assert!(matches!(Message::read(&mut stream), Ok(Message::Job(_))));
+            drop(stream);
+
+            let workers: Vec<_> = (0..2)
+                .map(|_| thread::spawn(move || work(address).unwrap()))
+                .collect();
+            workers
+                .into_iter()
+                .map(|w| w.join().unwrap())
+                .sum::<usize>()
+        });
+
+        let settings = FarmSettings {
+            samples_per_job: 2,
+            timeout: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let mut reported = (0, 0);
+        let scene = coordinate(listener, SCENE, settings, |finished, total| {
+            reported = (finished, total);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(reported, (3, 3));
+        assert_eq!(workers.join().unwrap(), 3);
+        assert_eq!(scene.render.sample_count(), 5);
+        for y in 0..4 {
+            for x in 0..8 {
+                assert_eq!(scene.render.pixel_samples(x, y), 5);
+            }
+        }
+    }
+
+    #[test]
+    // Jobs whose results keep being rejected, and renders past their deadline,
+    // must fail instead of running forever
+    fn render_farm_failures() {
+        let settings = FarmSettings {
+            samples_per_job: 2,
+            timeout: Duration::from_secs(10),
+            max_attempts: 2,
+            ..Default::default()
+        };
+
+        // This worker sends back garbage for every job it gets, until there are none left
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let worker = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            let mut jobs = 0;
+            loop {
+                Message::Ready.write(&mut stream).unwrap();
+                let Ok(Message::Job(job)) = Message::read(&mut stream) else {
+                    return jobs;
+                };
+                jobs += 1;
+                let result = Message::Result {
+                    id: job.id,
+                    checkpoint: b"not a checkpoint".to_vec(),
+                };
+                result.write(&mut stream).unwrap();
+            }
+        });
+
+        let result = coordinate(listener, SCENE, settings, |_, _| Ok(()));
+        assert!(matches!(
+            result,
+            Err(FarmError::Rejected {
+                job: 0,
+                error: CheckpointError::Malformed
+            })
+        ));
+        assert!(worker.join().unwrap() >= 2);
+
+        // Without any workers the render never finishes
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let settings = FarmSettings {
+            deadline: Some(Duration::from_millis(50)),
+            ..settings
+        };
+        let result = coordinate(listener, SCENE, settings, |_, _| Ok(()));
+        assert!(matches!(
+            result,
+            Err(FarmError::DeadlineExceeded {
+                finished: 0,
+                total: 3
+            })
+        ));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    // Once all of the local workers are gone, the coordinator must fail rather than
+    // wait for them forever
+    fn lost_workers() {
+        use std::process::Command;
+
+        let mut workers: Vec<Child> = (0..2)
+            .map(|_| Command::new("sleep").arg("60").spawn().unwrap())
+            .collect();
+        assert!(check_workers(&mut workers).is_ok());
+        for worker in &mut workers {
+            worker.kill().unwrap();
+            worker.wait().unwrap();
+        }
+
+        // The deadline only keeps the test from hanging if it fails
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let settings = FarmSettings {
+            deadline: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let result = coordinate(listener, SCENE, settings, |_, _| {
+            check_workers(&mut workers)
+        });
+        assert!(matches!(result, Err(FarmError::WorkersExited)));
+
+        // Without local workers, the coordinator waits for remote ones
+        assert!(check_workers(&mut []).is_ok());
+    }
+}
@@ -1,4 +1,16 @@
-mod raytrace;
+/// The ray tracing library itself
+pub mod raytrace;
 
 /// Functions exported to wasm for interacting with the [raytrace] library
 pub mod wasm;
+
+/// Plain text scene description, used by the command line renderer
+pub mod scene_file;
+
+/// Wavefront MTL material libraries, read into principled materials
+pub mod mtl;
+
+/// Distributed rendering on worker processes, coordinated over TCP.
+/// Only works natively, since browsers can't open sockets
+#[cfg(not(target_arch = "wasm32"))]
+pub mod farm;
@@ -0,0 +1,142 @@
+//! Statements of MTL files, which [Principled] has a parameter for, are read,
+//! as is the illumination model, telling whether light passes through. Others,
+//! like texture maps or the dissolve, are skipped:
+//!
+//! ```text
+//! newmtl brushed_gold
+//! Kd 1 0.78 0.34
+//! # physically based extension: roughness, metallic, sheen and clearcoat
+//! Pr 0.3
+//! Pm 1
+//! ```
+
+use nalgebra::Vector3;
+
+use crate::{
+    raytrace::{MtlMaterial, Principled},
+    scene_file::{ParseError, parse_args},
+};
+
+/// Read the materials of an MTL file, in the order they are defined
+pub fn parse_mtl(text: &str) -> Result<Vec<MtlMaterial>, ParseError> {
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let error = |message: String| ParseError {
+            line: index + 1,
+            message,
+        };
+
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let Some(keyword) = words.next() else {
+            continue;
+        };
+        let args: Vec<&str> = words.collect();
+
+        if keyword == "newmtl" {
+            let [name] = args[..] else {
+                return Err(error("expected a material name".to_string()));
+            };
+            materials.push(MtlMaterial::new(name));
+            continue;
+        }
+
+        let known = [
+            "Kd", "Ks", "Ke", "Ns", "Ni", "illum", "Pr", "Pm", "Ps", "Pc",
+        ];
+        if !known.contains(&keyword) {
+            continue;
+        }
+        let Some(material) = materials.last_mut() else {
+            return Err(error(format!("\"{keyword}\" before any \"newmtl\"")));
+        };
+
+        let color = |args: &[&str]| parse_args::<f64, 3>(args).map(Vector3::from);
+        match keyword {
+            "Kd" => material.diffuse = color(&args).map_err(error)?,
+            "Ks" => material.specular = color(&args).map_err(error)?,
+            "Ke" => material.emission = color(&args).map_err(error)?,
+            "illum" => {
+                let [model] = parse_args(&args).map_err(error)?;
+                material.illum = Some(model);
+            }
+            _ => {
+                let [value]: [f64; 1] = parse_args(&args).map_err(error)?;
+                match keyword {
+                    "Ns" => material.shininess = value,
+                    "Ni" => material.ior = Some(value),
+                    "Pr" => material.roughness = Some(value),
+                    "Pm" => material.metallic = value,
+                    "Ps" => material.sheen = value,
+                    _ => material.clearcoat = value,
+                }
+            }
+        }
+    }
+
+    Ok(materials)
+}
+
+/// Read the materials of an MTL file, and turn them into [Principled] materials by name
+pub fn parse_mtl_principled(text: &str) -> Result<Vec<(String, Principled)>, ParseError> {
+    Ok(parse_mtl(text)?
+        .iter()
+        .map(|material| (material.name.clone(), material.into()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raytrace::TextureCoordinates;
+
+    #[test]
+    // Materials are read with and without the physically based extension, only
+    // transmit light for the illumination models with refraction, and errors
+    // point at their line
+    fn mtl_materials() {
+        let text = "
+            # exported
+            newmtl glass
+            Kd 1 1 1
+            Ns 0
+            Ni 1.5
+            Tr 1
+            illum 7
+            map_Kd ignored.png
+
+            newmtl brushed_gold
+            Kd 1 0.78 0.34
+            Pr 0.3
+            Pm 1
+            Ke 0 0 0
+
+            # only partly there, rather than transparent
+            newmtl faded
+            d 0.5
+            Tr 0.5
+        ";
+        let materials = parse_mtl_principled(text).unwrap();
+        assert_eq!(materials.len(), 3);
+        let (name, glass) = &materials[0];
+        assert_eq!(name, "glass");
+        assert_eq!(glass.transmission, 1.);
+        assert!((glass.ior() - 1.5).abs() < 1e-9);
+        assert!((glass.roughness - 1.).abs() < 1e-9);
+        let (name, gold) = &materials[1];
+        assert_eq!(name, "brushed_gold");
+        assert_eq!((gold.roughness, gold.metallic), (0.3, 1.));
+        assert_eq!(
+            gold.base_color.evaluate(&TextureCoordinates::default()),
+            Vector3::new(1., 0.78, 0.34)
+        );
+        assert_eq!(gold.transmission, 0.);
+        assert_eq!(materials[2].1.transmission, 0.);
+        assert!(parse_mtl("newmtl a\nillum 0.5").is_err());
+
+        let error = parse_mtl("\nKd 1 1 1\nnewmtl late").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(parse_mtl("newmtl a\nNs high").is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use nalgebra::Vector3;
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// Arbitrary output variables: auxiliary buffers rendered next to the image,
+/// used for compositing and denoising
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aov {
+    /// Color of the first surface hit by the camera ray
+    Albedo,
+    /// World space shading normal of the first hit
+    Normal,
+    /// World space position of the first hit
+    Position,
+    /// Distance from the camera to the first hit, 0 where nothing was hit
+    Depth,
+    /// Index of the first object hit, -1 where nothing was hit
+    ObjectId,
+    /// Light emitted by the surfaces seen by the camera, and light
+    /// reaching them straight from an emitter
+    Direct,
+    /// Light that bounced off at least one more surface
+    Indirect,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 7] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Position,
+        Aov::Depth,
+        Aov::ObjectId,
+        Aov::Direct,
+        Aov::Indirect,
+    ];
+
+    /// Name of the layer, as written into image files
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Position => "position",
+            Aov::Depth => "depth",
+            Aov::ObjectId => "object",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+        }
+    }
+
+    /// Names of the channels stored in the layer
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Albedo | Aov::Direct | Aov::Indirect => &["R", "G", "B"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Depth => &["Z"],
+            Aov::ObjectId => &["ID"],
+        }
+    }
+}
+
+/// Everything a single camera path found out about the scene. Light is given
+/// in linear sRGB, or CIE XYZ with [super::Config::spectral] enabled
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PathSample {
+    /// See [Aov::Direct]
+    pub direct: Vector3<f64>,
+    /// See [Aov::Indirect]
+    pub indirect: Vector3<f64>,
+    pub albedo: Vector3<f64>,
+    pub normal: Vector3<f64>,
+    pub position: Vector3<f64>,
+    pub depth: f64,
+    /// Index of the first object hit by the path
+    pub object: Option<usize>,
+}
+
+impl PathSample {
+    /// Total light carried by the path
+    pub fn radiance(&self) -> Vector3<f64> {
+        self.direct + self.indirect
+    }
+
+    /// Value of the given AOV, as a 3-component vector. Single channel
+    /// AOVs are stored in the first component
+    pub fn aov(&self, aov: Aov) -> Vector3<f64> {
+        match aov {
+            Aov::Albedo => self.albedo,
+            Aov::Normal => self.normal,
+            Aov::Position => self.position,
+            Aov::Depth => Vector3::new(self.depth, 0., 0.),
+            Aov::ObjectId => Vector3::new(self.object.map_or(-1., |i| i as f64), 0., 0.),
+            Aov::Direct => self.direct,
+            Aov::Indirect => self.indirect,
+        }
+    }
+}
@@ -1,12 +1,9 @@
 use nalgebra::{Vector2, Vector3};
-use rand::prelude::*;
 
 use super::ray::Ray;
 
 #[derive(Debug, Clone)]
 pub struct Camera {
-    // Resolution of the camera in pixels
-    resolution: Vector2<usize>,
     position: Vector3<f64>,
     look_at: Vector3<f64>,
     up: Vector3<f64>,
@@ -15,9 +12,6 @@ pub struct Camera {
     pub(super) projection_screen_u: Vector3<f64>,
     pub(super) projection_screen_v: Vector3<f64>,
     projection_screen_center: Vector3<f64>,
-    // Size of one horizontal / vertical pixel in world units
-    delta_u: f64,
-    delta_v: f64,
 
     // Length from the camera position to the projection screen
     length: f64,
@@ -30,7 +24,6 @@ pub struct Camera {
 impl Default for Camera {
     fn default() -> Self {
         let mut camera = Camera {
-            resolution: Vector2::new(16, 9),
             position: Vector3::new(0., 0., 0.),
             look_at: Vector3::new(0., 0., 10.),
             up: Vector3::new(0., 1., 0.),
@@ -43,8 +36,6 @@ impl Default for Camera {
             projection_screen_u: Vector3::default(),
             projection_screen_v: Vector3::default(),
             projection_screen_center: Vector3::default(),
-            delta_u: 0.,
-            delta_v: 0.,
         };
         camera.update_geometry();
 
@@ -59,7 +50,6 @@ impl Camera {
         looking_at: Vector3<f64>,
     ) -> Camera {
         let mut camera = Camera {
-            resolution,
             aspect_ratio: resolution.x as f64 / resolution.y as f64,
             position,
             look_at: looking_at,
@@ -97,22 +87,14 @@ impl Camera {
         // Modify U and V vectors to match the aspect ratio of the screen
         self.projection_screen_u *= self.horizontal_size;
         self.projection_screen_v *= self.horizontal_size / self.aspect_ratio;
-
-        self.delta_u = self.horizontal_size / self.resolution.x as f64;
-        self.delta_v = (self.horizontal_size / self.aspect_ratio) / self.resolution.y as f64;
     }
 
-    /// Generate a ray going out the camera
+    /// Generate a ray going out the camera through the specified point
+    /// on the projection screen.
+    ///
+    /// Anti-aliasing is not done here: the caller is expected to pick the
+    /// sample position within a pixel, see [super::Scene::sample]
     pub fn generate_ray(&self, proj_screen_x: f64, proj_screen_y: f64) -> Ray {
-        let mut rng = rand::rng();
-
-        // Translate the ray origin by a tiny bit
-        // to simulate how real world cameras work, and to introduce anti-aliasing
-        let proj_screen_x =
-            proj_screen_x + rng.random_range(-self.delta_u * 2.0..=self.delta_u * 2.0);
-        let proj_screen_y =
-            proj_screen_y + rng.random_range(-self.delta_v * 2.0..=self.delta_v * 2.0);
-
         // Compute the location of the screen point in world coordinates
         let screen_world_coordinate = self.projection_screen_center
             + (self.projection_screen_u * proj_screen_x)
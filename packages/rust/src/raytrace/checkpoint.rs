@@ -0,0 +1,156 @@
+use std::fmt;
+
+use nalgebra::Vector3;
+
+/// Every checkpoint starts with these bytes
+pub const CHECKPOINT_MAGIC: &[u8; 4] = b"LSCK";
+/// Incremented whenever the layout of checkpoints changes
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+/// Reasons why a checkpoint can't be loaded, or renders can't be merged
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckpointError {
+    /// The data is not a checkpoint, or has been cut off
+    Malformed,
+    /// The checkpoint was written by another version of the renderer
+    UnsupportedVersion(u32),
+    /// The checkpoint was taken from a different scene
+    SceneMismatch,
+    /// The checkpoint or merged render has a different resolution than the render
+    ResolutionMismatch {
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Malformed => write!(f, "malformed checkpoint"),
+            CheckpointError::UnsupportedVersion(version) => {
+                write!(f, "unsupported checkpoint version {version}")
+            }
+            CheckpointError::SceneMismatch => {
+                write!(f, "checkpoint was taken from a different scene")
+            }
+            CheckpointError::ResolutionMismatch { expected, found } => write!(
+                f,
+                "checkpoint resolution {}x{} doesn't match {}x{}",
+                found.0, found.1, expected.0, expected.1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+/// 64-bit FNV-1a hash, fed through [fmt::Write] so that anything
+/// implementing [fmt::Debug] can be hashed without allocating
+pub(super) struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+impl fmt::Write for Fnv1a {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Appends little endian values to a checkpoint
+pub(super) struct CheckpointWriter {
+    pub bytes: Vec<u8>,
+}
+
+impl CheckpointWriter {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            bytes: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn f64(&mut self, value: f64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn vector(&mut self, value: Vector3<f64>) {
+        for component in value.iter() {
+            self.f64(*component);
+        }
+    }
+}
+
+/// Reads back the values written by [CheckpointWriter]
+pub(super) struct CheckpointReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> CheckpointReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], CheckpointError> {
+        let (value, rest) = self
+            .bytes
+            .split_first_chunk::<N>()
+            .ok_or(CheckpointError::Malformed)?;
+        self.bytes = rest;
+        Ok(*value)
+    }
+
+    pub fn magic(&mut self) -> Result<(), CheckpointError> {
+        match &self.take::<4>()? {
+            magic if magic == CHECKPOINT_MAGIC => Ok(()),
+            _ => Err(CheckpointError::Malformed),
+        }
+    }
+
+    pub fn u32(&mut self) -> Result<u32, CheckpointError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, CheckpointError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    pub fn f64(&mut self) -> Result<f64, CheckpointError> {
+        Ok(f64::from_le_bytes(self.take()?))
+    }
+
+    pub fn vector(&mut self) -> Result<Vector3<f64>, CheckpointError> {
+        Ok(Vector3::new(self.f64()?, self.f64()?, self.f64()?))
+    }
+
+    /// Make sure nothing is left over after reading the whole checkpoint
+    pub fn finish(&self) -> Result<(), CheckpointError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(CheckpointError::Malformed)
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use nalgebra::{Matrix3, Vector3};
+
+use super::luminance;
+
+/// Lumens per watt at the peak of the eye's sensitivity, which converts the
+/// Y of [cie_xyz] weighted radiometric quantities into photometric ones
+pub const LUMINOUS_EFFICACY: f64 = 683.;
+
+/// Shortest and longest wavelengths in nanometers which contribute to a color
+pub const VISIBLE_RANGE: (f64, f64) = (360., 830.);
+
+/// CIE 1931 2° color matching functions at the wavelength, given in nanometers.
+///
+/// Uses the multi-lobe fit by Wyman, Sloan & Shirley 2013, which is within
+/// about 1% of the tabulated functions
+pub fn cie_xyz(wavelength: f64) -> Vector3<f64> {
+    // Gaussian with a different spread on either side of its mean
+    let lobe = |mean: f64, below: f64, above: f64| {
+        let spread = if wavelength < mean { below } else { above };
+        let t = (wavelength - mean) / spread;
+        (-0.5 * t * t).exp()
+    };
+
+    Vector3::new(
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    )
+}
+
+/// Tristimulus values of a spectrum, given per nanometer, integrated over the [VISIBLE_RANGE]
+pub fn spectrum_to_xyz(spectrum: impl Fn(f64) -> f64) -> Vector3<f64> {
+    let (first, last) = VISIBLE_RANGE;
+    let step = 1.;
+
+    let mut xyz = Vector3::zeros();
+    let mut wavelength = first;
+    while wavelength <= last {
+        xyz += cie_xyz(wavelength) * spectrum(wavelength) * step;
+        wavelength += step;
+    }
+
+    xyz
+}
+
+/// Convert CIE XYZ to linear sRGB, with the D65 white point
+pub fn xyz_to_linear_srgb(xyz: Vector3<f64>) -> Vector3<f64> {
+    #[rustfmt::skip]
+    let matrix = Matrix3::new(
+        3.2404542, -1.5371385, -0.4985314,
+        -0.9692660, 1.8760108, 0.0415560,
+        0.0556434, -0.2040259, 1.0572252,
+    );
+
+    matrix * xyz
+}
+
+/// Convert a chromaticity `x`, `y` and luminance `Y` to CIE XYZ
+pub fn xyy_to_xyz(x: f64, y: f64, luminance: f64) -> Vector3<f64> {
+    if y <= 0. {
+        return Vector3::zeros();
+    }
+
+    Vector3::new(x * luminance / y, luminance, (1. - x - y) * luminance / y)
+}
+
+/// Spectral radiance of a black body by Planck's law, in watts per square meter,
+/// steradian and nanometer of wavelength. The wavelength is given in nanometers
+pub fn planck(wavelength: f64, temperature: f64) -> f64 {
+    // Planck constant, speed of light and Boltzmann constant
+    const H: f64 = 6.62607015e-34;
+    const C: f64 = 299792458.;
+    const K: f64 = 1.380649e-23;
+
+    let wavelength = wavelength * 1e-9;
+    let radiance = 2. * H * C * C
+        / (wavelength.powi(5) * ((H * C / (wavelength * K * temperature)).exp() - 1.));
+    // Per meter of wavelength to per nanometer
+    radiance * 1e-9
+}
+
+/// Linear sRGB color of the light emitted by a black body at the temperature,
+/// given in kelvin, scaled to a luminance of 1
+pub fn blackbody(temperature: f64) -> Vector3<f64> {
+    let xyz = spectrum_to_xyz(|wavelength| planck(wavelength, temperature));
+    if xyz.y <= 0. {
+        return Vector3::zeros();
+    }
+
+    // Colors outside of the sRGB gamut are clipped, then brought back to the luminance
+    let rgb = xyz_to_linear_srgb(xyz / xyz.y).map(|c| c.max(0.));
+    rgb / luminance(rgb)
+}
@@ -1,5 +1,7 @@
 use wasm_bindgen::prelude::wasm_bindgen;
 
+use super::{Filter, FilterKind, Tile};
+
 /// [Config] stores some common graphical parameters used in the simulation
 #[derive(Clone, Debug)]
 #[wasm_bindgen]
@@ -12,6 +14,35 @@ pub struct Config {
     pub max_bounce_count: usize,
     /// How many samples (iterations) to compute for each pixel on the screen
     pub samples_per_pixel: usize,
+    /// Pixel reconstruction filter used to splat samples onto the screen
+    pub filter: FilterKind,
+    /// Radius of the reconstruction filter, in pixels
+    pub filter_radius: f64,
+    /// Whether to render auxiliary buffers ([super::Aov]s) next to the image
+    pub aovs: bool,
+    /// Relative error below which a pixel counts as converged, and stops
+    /// receiving samples. 0 disables adaptive sampling
+    pub adaptive_threshold: f64,
+    /// Samples every pixel receives before its error estimate is trusted
+    pub adaptive_min_samples: usize,
+    /// Maximum brightness of a single sample, brighter samples are scaled down.
+    /// Removes fireflies at the cost of some energy. 0 disables the clamping
+    pub clamp_radiance: f64,
+    /// Only clamp light that bounced off more than one surface, leaving
+    /// direct lighting intact
+    pub clamp_indirect_only: bool,
+    /// Pixels brighter than the average of their neighbours by more than this
+    /// many standard deviations are darkened when reading the render out.
+    /// 0 disables the outlier rejection
+    pub outlier_rejection: f64,
+    /// Size of the square tiles rendered by [super::Scene::sample_tiles], in pixels
+    pub tile_size: usize,
+    /// Trace the spectrum at a few sampled wavelengths per path, instead of
+    /// red, green and blue. The render then accumulates CIE XYZ
+    pub spectral: bool,
+    /// Only pixels inside of this rectangle are rendered, see [Config::set_crop]
+    #[wasm_bindgen(skip)]
+    pub crop: Option<Tile>,
 }
 
 #[wasm_bindgen]
@@ -28,6 +59,55 @@ impl Config {
             height,
             max_bounce_count,
             samples_per_pixel,
+            filter: Filter::default().kind,
+            filter_radius: Filter::default().radius,
+            aovs: false,
+            adaptive_threshold: 0.,
+            adaptive_min_samples: 8,
+            clamp_radiance: 0.,
+            clamp_indirect_only: false,
+            outlier_rejection: 0.,
+            tile_size: 32,
+            spectral: false,
+            crop: None,
+        }
+    }
+
+    /// Restrict rendering to a rectangle of the screen. Pixels outside of it
+    /// are neither traced, nor receive any samples
+    pub fn set_crop(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        self.crop = Some(Tile::new(x, y, width, height));
+    }
+
+    /// Render the whole screen again
+    pub fn clear_crop(&mut self) {
+        self.crop = None;
+    }
+}
+
+impl Config {
+    /// Reconstruction filter described by this config
+    pub fn filter(&self) -> Filter {
+        Filter::new(self.filter, self.filter_radius)
+    }
+
+    /// Part of the screen that gets rendered: the crop rectangle
+    /// (limited to the screen), or the whole screen
+    pub fn region(&self) -> Tile {
+        let screen = Tile::new(0, 0, self.width, self.height);
+
+        match self.crop {
+            Some(crop) => {
+                let x = crop.x.min(self.width);
+                let y = crop.y.min(self.height);
+                Tile::new(
+                    x,
+                    y,
+                    crop.width.min(self.width - x),
+                    crop.height.min(self.height - y),
+                )
+            }
+            None => screen,
         }
     }
 }
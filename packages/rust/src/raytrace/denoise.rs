@@ -0,0 +1,184 @@
+use nalgebra::Vector3;
+
+use super::luminance;
+
+/// Parameters of the edge-avoiding à-trous wavelet denoiser.
+///
+/// Every `sigma` controls how quickly the filter stops blurring across
+/// differences in the corresponding buffer: smaller values keep more edges,
+/// larger values remove more noise
+#[derive(Clone, Copy, Debug)]
+pub struct DenoiseSettings {
+    /// Number of filter passes. Each pass doubles the filter footprint,
+    /// so 5 passes cover a 125 pixels wide area
+    pub iterations: usize,
+    pub sigma_color: f64,
+    pub sigma_normal: f64,
+    pub sigma_albedo: f64,
+    /// Relative to the depth of the pixel being filtered
+    pub sigma_depth: f64,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_color: 1.,
+            sigma_normal: 0.1,
+            sigma_albedo: 0.1,
+            sigma_depth: 0.1,
+        }
+    }
+}
+
+/// Feature buffers guiding the denoiser, all stored row by row
+#[derive(Clone, Copy, Default)]
+pub struct DenoiseGuides<'a> {
+    pub albedo: Option<&'a [Vector3<f64>]>,
+    pub normal: Option<&'a [Vector3<f64>]>,
+    /// Depth is stored in the first component, like in [super::Aov::Depth]
+    pub depth: Option<&'a [Vector3<f64>]>,
+}
+
+// B3 spline, the smoothing kernel of the à-trous transform
+const KERNEL: [f64; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+/// Denoise linear colors of an image with an edge-avoiding à-trous wavelet
+/// filter (Dammertz et al. 2010).
+///
+/// The color is divided by the albedo before filtering, so that textures
+/// and object colors stay sharp, while only the lighting gets smoothed out
+pub fn denoise(
+    width: usize,
+    height: usize,
+    color: &[Vector3<f64>],
+    guides: DenoiseGuides,
+    settings: &DenoiseSettings,
+) -> Vec<Vector3<f64>> {
+    const EPSILON: f64 = 1e-3;
+
+    // Separate the lighting from the surface color
+    let mut lighting: Vec<Vector3<f64>> = match guides.albedo {
+        Some(albedo) => color
+            .iter()
+            .zip(albedo)
+            .map(|(c, a)| c.component_div(&a.map(|a| a.max(EPSILON))))
+            .collect(),
+        None => color.to_vec(),
+    };
+    let mut filtered = lighting.clone();
+
+    for iteration in 0..settings.iterations {
+        let step = 1 << iteration as isize;
+        // Noise gets lower with every pass, so colors are compared more strictly
+        let sigma_color = settings.sigma_color / (1 << iteration) as f64;
+
+        for y in 0..height {
+            for x in 0..width {
+                let p = y * width + x;
+                let mut sum = Vector3::zeros();
+                let mut weight_sum = 0.;
+
+                for (j, ky) in KERNEL.iter().enumerate() {
+                    for (i, kx) in KERNEL.iter().enumerate() {
+                        let qx = x as isize + (i as isize - 2) * step;
+                        let qy = y as isize + (j as isize - 2) * step;
+                        if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                            continue;
+                        }
+                        let q = qy as usize * width + qx as usize;
+
+                        let mut exponent = (lighting[p] - lighting[q]).norm_squared()
+                            / (sigma_color * sigma_color);
+                        if let Some(normal) = guides.normal {
+                            exponent +=
+                                (1. - normal[p].dot(&normal[q])).max(0.) / settings.sigma_normal;
+                        }
+                        if let Some(albedo) = guides.albedo {
+                            exponent += (albedo[p] - albedo[q]).norm_squared()
+                                / (settings.sigma_albedo * settings.sigma_albedo);
+                        }
+                        if let Some(depth) = guides.depth {
+                            let difference =
+                                (depth[p].x - depth[q].x).abs() / depth[p].x.max(EPSILON);
+                            exponent += difference * difference
+                                / (settings.sigma_depth * settings.sigma_depth);
+                        }
+
+                        let weight = kx * ky * (-exponent).exp();
+                        sum += lighting[q] * weight;
+                        weight_sum += weight;
+                    }
+                }
+
+                // The center tap always has a positive weight, so the sum is never zero
+                filtered[p] = sum / weight_sum;
+            }
+        }
+
+        std::mem::swap(&mut lighting, &mut filtered);
+    }
+
+    // Put the surface colors back
+    match guides.albedo {
+        Some(albedo) => lighting
+            .iter()
+            .zip(albedo)
+            .map(|(l, a)| l.component_mul(&a.map(|a| a.max(EPSILON))))
+            .collect(),
+        None => lighting,
+    }
+}
+
+/// Darken isolated pixels which are much brighter than their surroundings (fireflies).
+///
+/// A pixel is an outlier when its luminance exceeds the mean luminance of its
+/// 8 neighbours by more than `deviations` standard deviations. Outliers are
+/// scaled down to that limit, keeping their hue
+pub fn reject_outliers(
+    width: usize,
+    height: usize,
+    pixels: &[Vector3<f64>],
+    deviations: f64,
+) -> Vec<Vector3<f64>> {
+    let mut result = pixels.to_vec();
+
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0.;
+            let mut sum_squares = 0.;
+            let mut count = 0.;
+
+            for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+                for nx in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+                    if nx == x && ny == y {
+                        continue;
+                    }
+
+                    let l = luminance(pixels[ny * width + nx]);
+                    sum += l;
+                    sum_squares += l * l;
+                    count += 1.;
+                }
+            }
+
+            if count == 0. {
+                continue;
+            }
+
+            let mean = sum / count;
+            let deviation = (sum_squares / count - mean * mean).max(0.).sqrt();
+            // Don't touch pixels in a completely flat neighbourhood
+            // just because they differ a tiny bit
+            let limit = mean + deviations * deviation.max(mean * 0.1);
+
+            let index = y * width + x;
+            let l = luminance(pixels[index]);
+            if l > limit && l > 0. {
+                result[index] = pixels[index] * (limit / l);
+            }
+        }
+    }
+
+    result
+}
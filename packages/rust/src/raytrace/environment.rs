@@ -0,0 +1,302 @@
+use std::{f64::consts::PI, fmt};
+
+use nalgebra::{Vector2, Vector3};
+
+use super::{
+    Fnv1a, Light, LightSample, Ray, Sky, luminance, read_exr, read_hdr, to_world, uniform_cone,
+    uniform_sphere,
+};
+
+/// Light arriving from infinitely far away, seen by rays which miss every object.
+///
+/// The Y axis points up, towards the top of the sky
+#[derive(Clone, Debug)]
+pub enum Environment {
+    /// The same radiance from every direction
+    Constant(Vector3<f64>),
+    /// Blend from the `bottom` radiance straight down to the `top` radiance straight up
+    Gradient {
+        bottom: Vector3<f64>,
+        top: Vector3<f64>,
+    },
+    /// Panorama around the scene, see [EnvironmentMap]
+    Map(EnvironmentMap),
+    /// Daylight, see [Sky]
+    Sky(Sky),
+}
+
+impl Environment {
+    /// Radiance arriving from the direction, which doesn't need to be normalized
+    pub fn radiance(&self, direction: Vector3<f64>) -> Vector3<f64> {
+        match self {
+            Environment::Constant(radiance) => *radiance,
+            Environment::Gradient { bottom, top } => {
+                let t = (direction.normalize().y + 1.) / 2.;
+                bottom.lerp(top, t)
+            }
+            Environment::Map(map) => map.radiance(direction),
+            Environment::Sky(sky) => sky.radiance(direction),
+        }
+    }
+}
+
+impl Light for Environment {
+    fn sample_li(&self, _point: Vector3<f64>, u: Vector2<f64>) -> Option<LightSample> {
+        let (direction, pdf) = match self {
+            Environment::Map(map) => map.sample(u)?,
+            // Only the upper half of the sky is lit
+            Environment::Sky(_) => (to_world(uniform_cone(u, 0.), Vector3::y()), 1. / (2. * PI)),
+            // Smooth environments are sampled uniformly
+            _ => (uniform_sphere(u), 1. / (4. * PI)),
+        };
+
+        Some(LightSample {
+            direction,
+            distance: f64::INFINITY,
+            radiance: self.radiance(direction),
+            pdf,
+        })
+    }
+
+    fn pdf_li(&self, _point: Vector3<f64>, direction: Vector3<f64>) -> f64 {
+        match self {
+            Environment::Map(map) => map.pdf(direction),
+            Environment::Sky(_) if direction.y <= 0. => 0.,
+            Environment::Sky(_) => 1. / (2. * PI),
+            _ => 1. / (4. * PI),
+        }
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<(f64, Vector3<f64>)> {
+        Some((f64::INFINITY, self.radiance(ray.direction)))
+    }
+}
+
+/// Reasons why an image can't be loaded
+#[derive(Debug)]
+pub enum ImageError {
+    Hdr(String),
+    Exr(exr::error::Error),
+    /// Invalid PNG or JPEG image
+    Decode(image::ImageError),
+    /// The bytes are in none of the formats the image can be loaded from
+    UnknownFormat,
+    /// The image has no pixels, too many of them, or not as many as its resolution
+    InvalidSize {
+        width: usize,
+        height: usize,
+    },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Hdr(message) => write!(f, "invalid HDR image: {message}"),
+            ImageError::Exr(error) => write!(f, "invalid EXR image: {error}"),
+            ImageError::Decode(error) => write!(f, "invalid image: {error}"),
+            ImageError::UnknownFormat => write!(f, "unknown image format"),
+            ImageError::InvalidSize { width, height } => {
+                write!(f, "invalid image size {width}x{height}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+/// Equirectangular (latitude-longitude) panorama of the surroundings.
+///
+/// The top row looks straight up, the bottom row straight down, and the columns
+/// go around the Y axis, starting from +X towards +Z. Bright regions, like the sun,
+/// are found by importance sampling the image, instead of waiting for paths to hit them
+#[derive(Clone)]
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vector3<f64>>,
+    // Distribution of picking a row, and of picking a column within every row
+    rows: Distribution,
+    columns: Vec<Distribution>,
+    // Fingerprint of the pixels, so that the map can be told apart in [super::Scene::hash]
+    checksum: u64,
+}
+
+impl EnvironmentMap {
+    /// Create a map from linear colors stored row by row, starting from the top.
+    /// Fails unless there are `width` times `height` pixels, and at least one
+    pub fn new(width: usize, height: usize, pixels: Vec<Vector3<f64>>) -> Result<Self, ImageError> {
+        if width == 0 || height == 0 || width.checked_mul(height) != Some(pixels.len()) {
+            return Err(ImageError::InvalidSize { width, height });
+        }
+
+        // Rows close to the poles cover a smaller solid angle, so they are
+        // sampled less often. Pixels get picked in proportion to their luminance
+        let columns: Vec<Distribution> = (0..height)
+            .map(|y| {
+                let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+                Distribution::new(
+                    pixels[y * width..(y + 1) * width]
+                        .iter()
+                        .map(|&p| luminance(p).max(0.) * sin_theta),
+                )
+            })
+            .collect();
+        let rows = Distribution::new(columns.iter().map(|c| c.total));
+
+        let mut hasher = Fnv1a::new();
+        for component in pixels.iter().flat_map(|p| p.iter()) {
+            hasher.write_bytes(&component.to_le_bytes());
+        }
+
+        Ok(Self {
+            width,
+            height,
+            pixels,
+            rows,
+            columns,
+            checksum: hasher.finish(),
+        })
+    }
+
+    /// Decode a Radiance HDR or OpenEXR image, telling them apart by their contents
+    pub fn load(bytes: &[u8]) -> Result<Self, ImageError> {
+        let (width, height, pixels) = if bytes.starts_with(b"#?") {
+            read_hdr(bytes)?
+        } else if bytes.starts_with(&[0x76, 0x2f, 0x31, 0x01]) {
+            read_exr(bytes).map_err(ImageError::Exr)?
+        } else {
+            return Err(ImageError::UnknownFormat);
+        };
+
+        Self::new(width, height, pixels)
+    }
+
+    /// Copy of the map, with the brightness multiplied by the `strength`
+    pub fn scaled(&self, strength: f64) -> Self {
+        let pixels = self.pixels.iter().map(|p| p * strength).collect();
+        // The size is the same as that of this map
+        Self::new(self.width, self.height, pixels).unwrap()
+    }
+
+    /// Position on the map, from 0 to 1 in both axes, seen in the direction
+    fn direction_to_uv(direction: Vector3<f64>) -> Vector2<f64> {
+        let direction = direction.normalize();
+        let phi = direction.z.atan2(direction.x);
+
+        Vector2::new(
+            (phi / (2. * PI)).rem_euclid(1.),
+            direction.y.clamp(-1., 1.).acos() / PI,
+        )
+    }
+
+    fn uv_to_direction(uv: Vector2<f64>) -> Vector3<f64> {
+        let phi = 2. * PI * uv.x;
+        let theta = PI * uv.y;
+
+        Vector3::new(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            theta.sin() * phi.sin(),
+        )
+    }
+
+    fn pixel_index(&self, uv: Vector2<f64>) -> (usize, usize) {
+        (
+            ((uv.x * self.width as f64) as usize).min(self.width - 1),
+            ((uv.y * self.height as f64) as usize).min(self.height - 1),
+        )
+    }
+
+    pub fn radiance(&self, direction: Vector3<f64>) -> Vector3<f64> {
+        let (x, y) = self.pixel_index(Self::direction_to_uv(direction));
+        self.pixels[y * self.width + x]
+    }
+
+    /// Pick a direction with a density proportional to the brightness of the map,
+    /// returning it with the density per unit solid angle
+    fn sample(&self, u: Vector2<f64>) -> Option<(Vector3<f64>, f64)> {
+        let (y, v) = self.rows.sample(u.y)?;
+        let (x, u) = self.columns[y].sample(u.x)?;
+        let uv = Vector2::new(
+            (x as f64 + u) / self.width as f64,
+            (y as f64 + v) / self.height as f64,
+        );
+
+        let direction = Self::uv_to_direction(uv);
+        let pdf = self.pdf(direction);
+        (pdf > 0.).then_some((direction, pdf))
+    }
+
+    fn pdf(&self, direction: Vector3<f64>) -> f64 {
+        let uv = Self::direction_to_uv(direction);
+        let (x, y) = self.pixel_index(uv);
+        let sin_theta = (PI * uv.y).sin();
+        if sin_theta <= 0. || self.rows.total <= 0. {
+            return 0.;
+        }
+
+        // Density on the image, from 0 to 1 in both axes, is the relative weight of the pixel
+        // times the pixel count. The map covers 2π by π radians, squeezed by sin θ
+        let weight = self.columns[y].weights[x] / self.rows.total;
+        let image_pdf = weight * (self.width * self.height) as f64;
+        image_pdf / (2. * PI * PI * sin_theta)
+    }
+}
+
+impl fmt::Debug for EnvironmentMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnvironmentMap")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("checksum", &self.checksum)
+            .finish()
+    }
+}
+
+/// Piecewise constant distribution over a list of non negative weights
+#[derive(Clone, Debug)]
+struct Distribution {
+    weights: Vec<f64>,
+    // Running sums of the weights, starting with 0
+    cdf: Vec<f64>,
+    total: f64,
+}
+
+impl Distribution {
+    fn new(weights: impl Iterator<Item = f64>) -> Self {
+        let weights: Vec<f64> = weights.collect();
+        let mut cdf = Vec::with_capacity(weights.len() + 1);
+        let mut total = 0.;
+
+        cdf.push(0.);
+        for weight in &weights {
+            total += weight;
+            cdf.push(total);
+        }
+
+        Self {
+            weights,
+            cdf,
+            total,
+        }
+    }
+
+    /// Pick an index in proportion to its weight, returning it with the
+    /// position of `u` inside of the picked bucket, from 0 to 1
+    fn sample(&self, u: f64) -> Option<(usize, f64)> {
+        if self.total <= 0. {
+            return None;
+        }
+
+        let target = u * self.total;
+        // Last bucket whose running sum doesn't exceed the target, skipping empty buckets
+        let index = self
+            .cdf
+            .partition_point(|&sum| sum <= target)
+            .clamp(1, self.weights.len())
+            - 1;
+        let offset = (target - self.cdf[index]) / self.weights[index];
+
+        Some((index, offset.clamp(0., 1.)))
+    }
+}
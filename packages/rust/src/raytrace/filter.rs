@@ -0,0 +1,91 @@
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// Shape of the pixel reconstruction filter
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterKind {
+    /// Every sample inside the radius has the same weight
+    Box,
+    /// Weight falls off linearly towards the edge of the radius
+    Tent,
+    /// Gaussian bell, shifted down so that it reaches zero at the radius
+    Gaussian,
+    /// Mitchell-Netravali cubic (B = C = 1/3). Sharper than the Gaussian,
+    /// but has negative lobes
+    Mitchell,
+}
+
+/// [Filter] decides how much a sample contributes to the pixels around it.
+///
+/// Samples are splatted into every pixel whose center lies within `radius`
+/// pixels of the sample, weighted by [Filter::evaluate].
+#[derive(Clone, Copy, Debug)]
+pub struct Filter {
+    pub kind: FilterKind,
+    /// Radius of the filter in pixels
+    pub radius: f64,
+}
+
+/// Smallest radius a filter can have, at which every sample still reaches
+/// the pixel it falls into
+pub(crate) const MIN_RADIUS: f64 = 0.5;
+
+impl Filter {
+    /// Filter of the kind, with the radius raised to at least half a pixel.
+    /// Smaller radii would leave samples without any pixel to land in, and
+    /// divide by zero in the tent filter
+    pub fn new(kind: FilterKind, radius: f64) -> Self {
+        Self {
+            kind,
+            radius: radius.max(MIN_RADIUS),
+        }
+    }
+
+    /// Weight of a sample located `dx`, `dy` pixels away from a pixel center
+    pub fn evaluate(&self, dx: f64, dy: f64) -> f64 {
+        // All filters are separable, so the 2D weight is a product of two 1D weights
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, d: f64) -> f64 {
+        let d = d.abs();
+        if d > self.radius {
+            return 0.;
+        }
+
+        match self.kind {
+            FilterKind::Box => 1.,
+            FilterKind::Tent => 1. - d / self.radius,
+            FilterKind::Gaussian => {
+                // Standard deviation of half a pixel, same as most offline renderers use
+                const ALPHA: f64 = 2.;
+                ((-ALPHA * d * d).exp() - (-ALPHA * self.radius * self.radius).exp()).max(0.)
+            }
+            FilterKind::Mitchell => {
+                const B: f64 = 1. / 3.;
+                const C: f64 = 1. / 3.;
+
+                // The cubic is defined on [-2, 2], so remap the distance to that range
+                let x = 2. * d / self.radius;
+                let value = if x < 1. {
+                    (12. - 9. * B - 6. * C) * x.powi(3)
+                        + (-18. + 12. * B + 6. * C) * x.powi(2)
+                        + (6. - 2. * B)
+                } else {
+                    (-B - 6. * C) * x.powi(3)
+                        + (6. * B + 30. * C) * x.powi(2)
+                        + (-12. * B - 48. * C) * x
+                        + (8. * B + 24. * C)
+                };
+
+                value / 6.
+            }
+        }
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::new(FilterKind::Gaussian, 1.5)
+    }
+}
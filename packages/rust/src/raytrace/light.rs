@@ -0,0 +1,532 @@
+use std::f64::consts::PI;
+use std::fmt::Debug;
+
+use nalgebra::{Vector2, Vector3};
+
+use super::{Ray, concentric_disk, orthonormal_basis, to_world, uniform_cone};
+
+/// Point on a light, picked as seen from a point in the scene
+#[derive(Clone, Copy, Debug)]
+pub struct LightSample {
+    /// Unit vector from the lit point towards the light
+    pub direction: Vector3<f64>,
+    /// Distance to the sampled point, infinite for lights which are infinitely far away
+    pub distance: f64,
+    /// Radiance arriving at the lit point from the light, ignoring anything in between
+    pub radiance: Vector3<f64>,
+    /// Probability density of picking the direction, per unit solid angle
+    pub pdf: f64,
+}
+
+/// Source of light which can be sampled directly, instead of waiting for
+/// a path to bump into it by chance
+pub trait Light: Debug {
+    /// Pick a direction towards the light from `point`, using the random numbers `u`.
+    /// Returns [None] when the light doesn't illuminate the point at all
+    fn sample_li(&self, point: Vector3<f64>, u: Vector2<f64>) -> Option<LightSample>;
+
+    /// Probability density, per unit solid angle, of [Light::sample_li] picking
+    /// the `direction` from `point`
+    fn pdf_li(&self, point: Vector3<f64>, direction: Vector3<f64>) -> f64;
+
+    /// Distance along the ray to the light, and the radiance it emits back towards
+    /// the ray origin. Lights without any geometry can't be hit
+    fn intersect(&self, _ray: &Ray) -> Option<(f64, Vector3<f64>)> {
+        None
+    }
+
+    /// Whether the light comes from a single point or direction. Such lights can
+    /// only be reached through [Light::sample_li], and their samples have a pdf of 1
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+
+/// Sphere emitting the same radiance everywhere on its outside
+#[derive(Clone, Copy, Debug)]
+pub struct SphereLight {
+    pub center: Vector3<f64>,
+    pub radius: f64,
+    pub radiance: Vector3<f64>,
+}
+
+impl SphereLight {
+    pub fn new(center: Vector3<f64>, radius: f64, radiance: Vector3<f64>) -> Self {
+        Self {
+            center,
+            radius,
+            radiance,
+        }
+    }
+
+    /// Cosine of the half angle of the cone the sphere covers, as seen from `point`.
+    /// [None] when the point is inside of the sphere
+    fn cone(&self, point: Vector3<f64>) -> Option<f64> {
+        let sin_max_squared = self.radius * self.radius / (self.center - point).norm_squared();
+        (sin_max_squared < 1.).then(|| (1. - sin_max_squared).sqrt())
+    }
+}
+
+impl Light for SphereLight {
+    // Only the cone of directions actually covered by the sphere is sampled,
+    // which wastes no samples on its back side, unlike picking points on its surface
+    fn sample_li(&self, point: Vector3<f64>, u: Vector2<f64>) -> Option<LightSample> {
+        let cos_max = self.cone(point)?;
+        let to_center = self.center - point;
+        let center_distance = to_center.norm();
+
+        let local = uniform_cone(u, cos_max);
+        let direction = to_world(local, to_center / center_distance);
+
+        // Distance to the near side of the sphere along the direction
+        let cos_theta = local.z;
+        let sin_theta_squared = 1. - cos_theta * cos_theta;
+        let distance = center_distance * cos_theta
+            - (self.radius * self.radius - center_distance * center_distance * sin_theta_squared)
+                .max(0.)
+                .sqrt();
+
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.radiance,
+            pdf: cone_pdf(cos_max),
+        })
+    }
+
+    fn pdf_li(&self, point: Vector3<f64>, direction: Vector3<f64>) -> f64 {
+        let Some(cos_max) = self.cone(point) else {
+            return 0.;
+        };
+
+        let to_center = (self.center - point).normalize();
+        if to_center.dot(&direction.normalize()) < cos_max {
+            return 0.;
+        }
+
+        cone_pdf(cos_max)
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<(f64, Vector3<f64>)> {
+        let direction = ray.direction.normalize();
+        let offset = ray.origin - self.center;
+
+        let b = offset.dot(&direction);
+        let c = offset.norm_squared() - self.radius * self.radius;
+        let discriminant = b * b - c;
+        // The inside of the sphere is dark
+        if discriminant < 0. || c < 0. {
+            return None;
+        }
+
+        let distance = -b - discriminant.sqrt();
+        (distance > 0.).then_some((distance, self.radiance))
+    }
+}
+
+/// Density of sampling a cone uniformly, per unit solid angle
+fn cone_pdf(cos_max: f64) -> f64 {
+    // 1 - cos_max written in a way which doesn't lose all precision for tiny cones
+    let sin_max_squared = 1. - cos_max * cos_max;
+    1. / (2. * PI * sin_max_squared / (1. + cos_max))
+}
+
+/// Parallelogram spanned by two edges, emitting light on the side its
+/// normal `edge_u × edge_v` points to, or on both sides
+#[derive(Clone, Copy, Debug)]
+pub struct RectLight {
+    pub corner: Vector3<f64>,
+    pub edge_u: Vector3<f64>,
+    pub edge_v: Vector3<f64>,
+    pub radiance: Vector3<f64>,
+    pub two_sided: bool,
+}
+
+impl RectLight {
+    pub fn new(
+        corner: Vector3<f64>,
+        edge_u: Vector3<f64>,
+        edge_v: Vector3<f64>,
+        radiance: Vector3<f64>,
+        two_sided: bool,
+    ) -> Self {
+        Self {
+            corner,
+            edge_u,
+            edge_v,
+            radiance,
+            two_sided,
+        }
+    }
+
+    fn normal(&self) -> Vector3<f64> {
+        self.edge_u.cross(&self.edge_v).normalize()
+    }
+
+    fn area(&self) -> f64 {
+        self.edge_u.cross(&self.edge_v).norm()
+    }
+}
+
+impl Light for RectLight {
+    fn sample_li(&self, point: Vector3<f64>, u: Vector2<f64>) -> Option<LightSample> {
+        let target = self.corner + self.edge_u * u.x + self.edge_v * u.y;
+        area_sample(
+            point,
+            target,
+            self.normal(),
+            self.area(),
+            self.radiance,
+            self.two_sided,
+        )
+    }
+
+    fn pdf_li(&self, point: Vector3<f64>, direction: Vector3<f64>) -> f64 {
+        let ray = Ray {
+            origin: point,
+            direction,
+        };
+
+        match self.intersect(&ray) {
+            Some((distance, _)) => area_pdf(distance, direction, self.normal(), self.area()),
+            None => 0.,
+        }
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<(f64, Vector3<f64>)> {
+        let direction = ray.direction.normalize();
+        let (distance, local) = plane_hit(ray.origin, direction, self.corner, self.normal())?;
+
+        // Coordinates of the hit along the edges, which aren't necessarily perpendicular
+        let uu = self.edge_u.norm_squared();
+        let vv = self.edge_v.norm_squared();
+        let uv = self.edge_u.dot(&self.edge_v);
+        let pu = local.dot(&self.edge_u);
+        let pv = local.dot(&self.edge_v);
+        let determinant = uu * vv - uv * uv;
+        let s = (pu * vv - pv * uv) / determinant;
+        let t = (pv * uu - pu * uv) / determinant;
+        if !(0. ..=1.).contains(&s) || !(0. ..=1.).contains(&t) {
+            return None;
+        }
+
+        emits_towards(self.normal(), direction, self.two_sided).then_some((distance, self.radiance))
+    }
+}
+
+/// Flat disk emitting light on the side its `normal` points to, or on both sides
+#[derive(Clone, Copy, Debug)]
+pub struct DiskLight {
+    pub center: Vector3<f64>,
+    pub normal: Vector3<f64>,
+    pub radius: f64,
+    pub radiance: Vector3<f64>,
+    pub two_sided: bool,
+}
+
+impl DiskLight {
+    pub fn new(
+        center: Vector3<f64>,
+        normal: Vector3<f64>,
+        radius: f64,
+        radiance: Vector3<f64>,
+        two_sided: bool,
+    ) -> Self {
+        Self {
+            center,
+            normal: normal.normalize(),
+            radius,
+            radiance,
+            two_sided,
+        }
+    }
+
+    fn area(&self) -> f64 {
+        PI * self.radius * self.radius
+    }
+}
+
+impl Light for DiskLight {
+    fn sample_li(&self, point: Vector3<f64>, u: Vector2<f64>) -> Option<LightSample> {
+        let (s, t) = orthonormal_basis(self.normal);
+        let disk = concentric_disk(u) * self.radius;
+        let target = self.center + s * disk.x + t * disk.y;
+
+        area_sample(
+            point,
+            target,
+            self.normal,
+            self.area(),
+            self.radiance,
+            self.two_sided,
+        )
+    }
+
+    fn pdf_li(&self, point: Vector3<f64>, direction: Vector3<f64>) -> f64 {
+        let ray = Ray {
+            origin: point,
+            direction,
+        };
+
+        match self.intersect(&ray) {
+            Some((distance, _)) => area_pdf(distance, direction, self.normal, self.area()),
+            None => 0.,
+        }
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<(f64, Vector3<f64>)> {
+        let direction = ray.direction.normalize();
+        let (distance, local) = plane_hit(ray.origin, direction, self.center, self.normal)?;
+
+        if local.norm_squared() > self.radius * self.radius {
+            return None;
+        }
+
+        emits_towards(self.normal, direction, self.two_sided).then_some((distance, self.radiance))
+    }
+}
+
+/// Whether a flat light with the `normal` is visible when looking along `direction`
+fn emits_towards(normal: Vector3<f64>, direction: Vector3<f64>, two_sided: bool) -> bool {
+    two_sided || normal.dot(&direction) < 0.
+}
+
+/// Distance to the plane through `origin` along the unit `direction`, and the hit
+/// point relative to the `plane_point`
+fn plane_hit(
+    origin: Vector3<f64>,
+    direction: Vector3<f64>,
+    plane_point: Vector3<f64>,
+    normal: Vector3<f64>,
+) -> Option<(f64, Vector3<f64>)> {
+    let cos = normal.dot(&direction);
+    if cos == 0. {
+        return None;
+    }
+
+    let distance = normal.dot(&(plane_point - origin)) / cos;
+    (distance > 0.).then(|| (distance, origin + direction * distance - plane_point))
+}
+
+/// Turn a point picked uniformly on a flat light into a [LightSample]
+fn area_sample(
+    point: Vector3<f64>,
+    target: Vector3<f64>,
+    normal: Vector3<f64>,
+    area: f64,
+    radiance: Vector3<f64>,
+    two_sided: bool,
+) -> Option<LightSample> {
+    let offset = target - point;
+    let distance = offset.norm();
+    let direction = offset / distance;
+
+    if distance == 0. || !emits_towards(normal, direction, two_sided) {
+        return None;
+    }
+
+    let pdf = area_pdf(distance, direction, normal, area);
+    (pdf > 0. && pdf.is_finite()).then_some(LightSample {
+        direction,
+        distance,
+        radiance,
+        pdf,
+    })
+}
+
+/// Convert the density of picking points uniformly on a flat light with the
+/// `area` into a density per unit solid angle
+fn area_pdf(distance: f64, direction: Vector3<f64>, normal: Vector3<f64>, area: f64) -> f64 {
+    let cos = normal.dot(&direction.normalize()).abs();
+    if cos == 0. {
+        return 0.;
+    }
+
+    distance * distance / (cos * area)
+}
+
+/// Infinitely small light, shining equally in all directions
+#[derive(Clone, Copy, Debug)]
+pub struct PointLight {
+    pub position: Vector3<f64>,
+    /// Radiant intensity, the power emitted per unit solid angle
+    pub intensity: Vector3<f64>,
+}
+
+impl PointLight {
+    pub fn new(position: Vector3<f64>, intensity: Vector3<f64>) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample_li(&self, point: Vector3<f64>, _u: Vector2<f64>) -> Option<LightSample> {
+        delta_sample(point, self.position, self.intensity)
+    }
+
+    fn pdf_li(&self, _point: Vector3<f64>, _direction: Vector3<f64>) -> f64 {
+        0.
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+
+/// Point light which only shines into a cone. The intensity is constant up to
+/// the inner angle, and fades out smoothly towards the outer angle
+#[derive(Clone, Copy, Debug)]
+pub struct SpotLight {
+    pub position: Vector3<f64>,
+    /// Unit vector along the axis of the cone
+    pub direction: Vector3<f64>,
+    /// Radiant intensity along the axis, see [PointLight::intensity]
+    pub intensity: Vector3<f64>,
+    pub cos_inner: f64,
+    pub cos_outer: f64,
+}
+
+impl SpotLight {
+    /// Create a spot light, with the angles between the axis and the edges
+    /// of the cones given in radians
+    pub fn new(
+        position: Vector3<f64>,
+        direction: Vector3<f64>,
+        intensity: Vector3<f64>,
+        inner_angle: f64,
+        outer_angle: f64,
+    ) -> Self {
+        Self {
+            position,
+            direction: direction.normalize(),
+            intensity,
+            cos_inner: inner_angle.min(outer_angle).cos(),
+            cos_outer: outer_angle.cos(),
+        }
+    }
+
+    /// Fraction of the intensity emitted in a direction
+    fn falloff(&self, direction: Vector3<f64>) -> f64 {
+        let cos = self.direction.dot(&direction);
+        if cos >= self.cos_inner {
+            return 1.;
+        }
+        if cos <= self.cos_outer {
+            return 0.;
+        }
+
+        let t = (cos - self.cos_outer) / (self.cos_inner - self.cos_outer);
+        t * t * (3. - 2. * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_li(&self, point: Vector3<f64>, _u: Vector2<f64>) -> Option<LightSample> {
+        let falloff = self.falloff((point - self.position).normalize());
+        if falloff == 0. {
+            return None;
+        }
+
+        delta_sample(point, self.position, self.intensity * falloff)
+    }
+
+    fn pdf_li(&self, _point: Vector3<f64>, _direction: Vector3<f64>) -> f64 {
+        0.
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+
+/// Light of a point light as seen from `point`, with the intensity falling
+/// off with the squared distance
+fn delta_sample(
+    point: Vector3<f64>,
+    position: Vector3<f64>,
+    intensity: Vector3<f64>,
+) -> Option<LightSample> {
+    let offset = position - point;
+    let distance = offset.norm();
+    if distance == 0. {
+        return None;
+    }
+
+    Some(LightSample {
+        direction: offset / distance,
+        distance,
+        radiance: intensity / (distance * distance),
+        pdf: 1.,
+    })
+}
+
+/// Light coming from infinitely far away, like the sun. With a non zero angular
+/// diameter it covers a small disk of the sky, and casts soft shadows
+#[derive(Clone, Copy, Debug)]
+pub struct DirectionalLight {
+    /// Unit vector in the direction the light travels
+    pub direction: Vector3<f64>,
+    /// Irradiance on a surface facing the light
+    pub irradiance: Vector3<f64>,
+    /// Cosine of half of the angular diameter
+    pub cos_max: f64,
+}
+
+impl DirectionalLight {
+    /// Create a directional light, with the angular diameter given in radians
+    pub fn new(direction: Vector3<f64>, irradiance: Vector3<f64>, angular_diameter: f64) -> Self {
+        Self {
+            direction: direction.normalize(),
+            irradiance,
+            cos_max: (angular_diameter / 2.).cos(),
+        }
+    }
+
+    /// Radiance of the disk, chosen so that it delivers the [DirectionalLight::irradiance]
+    fn radiance(&self) -> Vector3<f64> {
+        // The irradiance from a disk is π L sin²θ
+        let sin_max_squared = 1. - self.cos_max * self.cos_max;
+        self.irradiance / (PI * sin_max_squared)
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample_li(&self, _point: Vector3<f64>, u: Vector2<f64>) -> Option<LightSample> {
+        if self.is_delta() {
+            return Some(LightSample {
+                direction: -self.direction,
+                distance: f64::INFINITY,
+                radiance: self.irradiance,
+                pdf: 1.,
+            });
+        }
+
+        Some(LightSample {
+            direction: to_world(uniform_cone(u, self.cos_max), -self.direction),
+            distance: f64::INFINITY,
+            radiance: self.radiance(),
+            pdf: cone_pdf(self.cos_max),
+        })
+    }
+
+    fn pdf_li(&self, _point: Vector3<f64>, direction: Vector3<f64>) -> f64 {
+        if self.is_delta() || -self.direction.dot(&direction.normalize()) < self.cos_max {
+            return 0.;
+        }
+
+        cone_pdf(self.cos_max)
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<(f64, Vector3<f64>)> {
+        let visible =
+            !self.is_delta() && -self.direction.dot(&ray.direction.normalize()) >= self.cos_max;
+        visible.then(|| (f64::INFINITY, self.radiance()))
+    }
+
+    fn is_delta(&self) -> bool {
+        self.cos_max >= 1.
+    }
+}
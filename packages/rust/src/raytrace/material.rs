@@ -1,12 +1,75 @@
-use nalgebra::Vector3;
+use std::{f64::consts::FRAC_1_PI, fmt::Debug};
+
+use nalgebra::{Vector2, Vector3};
 use rand::prelude::*;
 
-use super::ray::Ray;
+use super::{
+    SampledSpectrum, TextureCoordinates, TrowbridgeReitz, Wavelengths, ray::Ray, to_local, to_world,
+};
+
+pub trait Material: Debug {
+    /// Send the ray, which hit the surface at the `point` with the outward facing
+    /// `normal`, off into a new direction. Returns the factor the light carried by
+    /// the path gets multiplied by, before being tinted by the object color.
+    ///
+    /// Textures are looked up at the `coordinates` of the point
+    fn scatter(
+        &self,
+        ray: &mut Ray,
+        point: Vector3<f64>,
+        normal: Vector3<f64>,
+        coordinates: &TextureCoordinates,
+        wavelengths: &mut Wavelengths,
+    ) -> SampledSpectrum;
+
+    /// Fraction of the light arriving from `incoming` which gets scattered towards
+    /// `outgoing`, per unit solid angle, at each of the wavelengths, before being
+    /// tinted by the object color. Both directions point away from the surface
+    fn eval(
+        &self,
+        outgoing: Vector3<f64>,
+        incoming: Vector3<f64>,
+        normal: Vector3<f64>,
+        coordinates: &TextureCoordinates,
+        wavelengths: &Wavelengths,
+    ) -> SampledSpectrum;
+
+    /// Probability density of [Material::scatter] sending a ray, which arrived from
+    /// `outgoing`, off towards `incoming`, per unit solid angle
+    fn pdf(
+        &self,
+        outgoing: Vector3<f64>,
+        incoming: Vector3<f64>,
+        normal: Vector3<f64>,
+        coordinates: &TextureCoordinates,
+    ) -> f64;
+
+    /// Whether [Material::scatter] only ever picks a few exact directions, like
+    /// mirrors and glass do. Lights can then only be found by scattered rays
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    /// Light given off by the material itself, on top of the emission of the object
+    fn emission(&self) -> Vector3<f64> {
+        Vector3::zeros()
+    }
+
+    /// Whether the surface only marks the boundary of a [super::Medium], letting
+    /// light straight through. Crossing it doesn't count as a bounce, and it
+    /// casts no shadows
+    fn is_interface(&self) -> bool {
+        false
+    }
 
-pub trait Material {
-    fn scatter(&self, ray: &mut Ray, point: Vector3<f64>, normal: Vector3<f64>);
+    /// Color of the material, which the object color gets multiplied by for
+    /// the albedo buffer
+    fn albedo(&self, _coordinates: &TextureCoordinates) -> Vector3<f64> {
+        Vector3::repeat(1.)
+    }
 }
 
+#[derive(Debug)]
 pub struct Lambertian;
 
 impl Default for Lambertian {
@@ -22,7 +85,14 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, ray: &mut Ray, point: Vector3<f64>, normal: Vector3<f64>) {
+    fn scatter(
+        &self,
+        ray: &mut Ray,
+        point: Vector3<f64>,
+        normal: Vector3<f64>,
+        _coordinates: &TextureCoordinates,
+        wavelengths: &mut Wavelengths,
+    ) -> SampledSpectrum {
         ray.origin = point;
 
         let mut rng = rand::rng();
@@ -33,12 +103,579 @@ impl Material for Lambertian {
             rng.sample::<f64, _>(rand_distr::StandardNormal),
         )
         .normalize();
-        // Invert the ray, if it lays on the wrong hemisphere
-        if reflection.dot(&normal) < 0.0 {
+        // Random point on the unit sphere, stacked onto the normal, has a cosine
+        // weighted distribution. Flipping it onto the hemisphere first would not
+        if (normal + reflection).magnitude_squared() < 1e-12 {
             reflection = -reflection;
         }
 
-        // Apply cosine weighted distribution
         ray.direction = (normal + reflection).normalize();
+
+        // Cosine weighted sampling cancels out the cosine and the BRDF
+        wavelengths.constant(1.)
+    }
+
+    fn eval(
+        &self,
+        _outgoing: Vector3<f64>,
+        incoming: Vector3<f64>,
+        normal: Vector3<f64>,
+        _coordinates: &TextureCoordinates,
+        wavelengths: &Wavelengths,
+    ) -> SampledSpectrum {
+        if incoming.dot(&normal) <= 0. {
+            return wavelengths.constant(0.);
+        }
+
+        wavelengths.constant(FRAC_1_PI)
+    }
+
+    fn pdf(
+        &self,
+        _outgoing: Vector3<f64>,
+        incoming: Vector3<f64>,
+        normal: Vector3<f64>,
+        _coordinates: &TextureCoordinates,
+    ) -> f64 {
+        incoming.dot(&normal).max(0.) * FRAC_1_PI
+    }
+}
+
+/// Boundary of a [super::Medium] filling an object, which light passes through
+/// as if there were no surface at all. The object color is ignored
+#[derive(Debug)]
+pub struct Interface;
+
+impl Default for Interface {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interface {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Material for Interface {
+    fn scatter(
+        &self,
+        ray: &mut Ray,
+        point: Vector3<f64>,
+        _normal: Vector3<f64>,
+        _coordinates: &TextureCoordinates,
+        wavelengths: &mut Wavelengths,
+    ) -> SampledSpectrum {
+        ray.origin = point;
+        wavelengths.constant(1.)
+    }
+
+    fn eval(
+        &self,
+        _outgoing: Vector3<f64>,
+        _incoming: Vector3<f64>,
+        _normal: Vector3<f64>,
+        _coordinates: &TextureCoordinates,
+        wavelengths: &Wavelengths,
+    ) -> SampledSpectrum {
+        wavelengths.constant(0.)
+    }
+
+    fn pdf(
+        &self,
+        _outgoing: Vector3<f64>,
+        _incoming: Vector3<f64>,
+        _normal: Vector3<f64>,
+        _coordinates: &TextureCoordinates,
+    ) -> f64 {
+        0.
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+
+    fn is_interface(&self) -> bool {
+        true
+    }
+}
+
+/// Index of refraction, depending on the wavelength. Without the wavelength
+/// dependency, spectral rendering would be of little use for glass
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ior {
+    /// The same index for every wavelength
+    Constant(f64),
+    /// Cauchy's equation `n = a + b / λ²`, with the wavelength in micrometers
+    Cauchy { a: f64, b: f64 },
+    /// Sellmeier equation `n² = 1 + Σ bᵢ λ² / (λ² - cᵢ)`, with the wavelength in micrometers
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Ior {
+    /// Schott N-BK7 borosilicate crown glass, the most common optical glass
+    pub const BK7: Ior = Ior::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653],
+    };
+    /// Schott F2 flint glass, which disperses light about twice as much as BK7
+    pub const FLINT: Ior = Ior::Sellmeier {
+        b: [1.34533359, 0.209073176, 0.937357162],
+        c: [0.00997743871, 0.0470450767, 111.886764],
+    };
+    /// Water at room temperature
+    pub const WATER: Ior = Ior::Cauchy {
+        a: 1.324,
+        b: 0.00306,
+    };
+
+    /// Wavelength of the yellow helium d-line in nanometers, where indices of
+    /// refraction are usually quoted, and which is used when rendering in RGB
+    pub const REFERENCE_WAVELENGTH: f64 = 587.6;
+
+    /// Index of refraction at the wavelength, given in nanometers
+    pub fn at(&self, wavelength: f64) -> f64 {
+        let micrometers = wavelength / 1000.;
+        let squared = micrometers * micrometers;
+
+        match *self {
+            Ior::Constant(n) => n,
+            Ior::Cauchy { a, b } => a + b / squared,
+            Ior::Sellmeier { b, c } => (1.
+                + (0..3)
+                    .map(|i| b[i] * squared / (squared - c[i]))
+                    .sum::<f64>())
+            .sqrt(),
+        }
+    }
+
+    /// Whether the index changes with the wavelength
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+}
+
+/// Smooth glass or liquid, which reflects or refracts light, as decided by the
+/// Fresnel equations. With a dispersive [Ior], rendering spectrally splits
+/// white light into its colors
+#[derive(Debug)]
+pub struct Dielectric {
+    pub ior: Ior,
+}
+
+impl Dielectric {
+    pub fn new(ior: Ior) -> Self {
+        Self { ior }
     }
 }
+
+impl Material for Dielectric {
+    fn scatter(
+        &self,
+        ray: &mut Ray,
+        point: Vector3<f64>,
+        normal: Vector3<f64>,
+        _coordinates: &TextureCoordinates,
+        wavelengths: &mut Wavelengths,
+    ) -> SampledSpectrum {
+        // Every wavelength would take a different path, so only the hero wavelength
+        // carries on, standing in for the others (Wilkie et al. 2014)
+        let mut weight = wavelengths.constant(1.);
+        let ior = match wavelengths.hero() {
+            Some(hero) if self.ior.is_dispersive() => {
+                weight = wavelengths.terminate_secondary();
+                self.ior.at(hero)
+            }
+            _ => self.ior.at(Ior::REFERENCE_WAVELENGTH),
+        };
+
+        let direction = ray.direction.normalize();
+        let entering = direction.dot(&normal) < 0.;
+        let (normal, eta) = if entering {
+            (normal, 1. / ior)
+        } else {
+            (-normal, ior)
+        };
+
+        let cos_incident = -direction.dot(&normal);
+        let sin_squared = eta * eta * (1. - cos_incident * cos_incident);
+        let reflectance = if sin_squared >= 1. {
+            // Total internal reflection
+            1.
+        } else {
+            fresnel_dielectric(cos_incident, (1. - sin_squared).sqrt(), eta)
+        };
+
+        // Start the new ray a bit off of the surface, on the side it's going to
+        if rand::rng().random::<f64>() < reflectance {
+            ray.direction = direction + normal * (2. * cos_incident);
+            ray.origin = point + normal * 1e-4;
+        } else {
+            let cos_transmitted = (1. - sin_squared).sqrt();
+            ray.direction = direction * eta + normal * (eta * cos_incident - cos_transmitted);
+            ray.origin = point - normal * 1e-4;
+        }
+
+        weight
+    }
+
+    fn eval(
+        &self,
+        _outgoing: Vector3<f64>,
+        _incoming: Vector3<f64>,
+        _normal: Vector3<f64>,
+        _coordinates: &TextureCoordinates,
+        wavelengths: &Wavelengths,
+    ) -> SampledSpectrum {
+        wavelengths.constant(0.)
+    }
+
+    fn pdf(
+        &self,
+        _outgoing: Vector3<f64>,
+        _incoming: Vector3<f64>,
+        _normal: Vector3<f64>,
+        _coordinates: &TextureCoordinates,
+    ) -> f64 {
+        0.
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+}
+
+/// Fraction of unpolarized light reflected by a smooth boundary, given the cosines
+/// of the incident and transmitted angles, and the ratio of the indices `eta`
+pub(crate) fn fresnel_dielectric(cos_incident: f64, cos_transmitted: f64, eta: f64) -> f64 {
+    let parallel = (cos_incident - eta * cos_transmitted) / (cos_incident + eta * cos_transmitted);
+    let perpendicular =
+        (eta * cos_incident - cos_transmitted) / (eta * cos_incident + cos_transmitted);
+
+    (parallel * parallel + perpendicular * perpendicular) / 2.
+}
+
+/// Complex index of refraction `eta + i k` of a metal, for red, green and blue
+/// light. In between, spectral rendering interpolates linearly, taking the
+/// channels as samples at 650, 550 and 450 nanometers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ComplexIor {
+    pub eta: Vector3<f64>,
+    /// Extinction coefficient, how quickly light dies out inside of the metal
+    pub k: Vector3<f64>,
+}
+
+impl ComplexIor {
+    pub const GOLD: ComplexIor = ComplexIor::new([0.143, 0.374, 1.442], [3.983, 2.385, 1.603]);
+    pub const COPPER: ComplexIor = ComplexIor::new([0.200, 0.924, 1.102], [3.912, 2.452, 2.142]);
+    pub const ALUMINIUM: ComplexIor = ComplexIor::new([1.657, 0.880, 0.521], [9.224, 6.270, 4.837]);
+    pub const SILVER: ComplexIor = ComplexIor::new([0.155, 0.117, 0.138], [4.828, 3.122, 2.147]);
+
+    pub const fn new(eta: [f64; 3], k: [f64; 3]) -> Self {
+        Self {
+            eta: Vector3::new(eta[0], eta[1], eta[2]),
+            k: Vector3::new(k[0], k[1], k[2]),
+        }
+    }
+
+    /// `eta` and `k` at the wavelength, given in nanometers
+    pub fn at(&self, wavelength: f64) -> (f64, f64) {
+        // From blue to red, the channels in reverse
+        let t = ((wavelength - 450.) / 100.).clamp(0., 2.);
+        let index = (t as usize).min(1);
+        let lerp = |values: Vector3<f64>| {
+            let (from, to) = (values[2 - index], values[1 - index]);
+            from + (to - from) * (t - index as f64)
+        };
+
+        (lerp(self.eta), lerp(self.k))
+    }
+
+    /// Fraction of the light reflected at the cosine of the incident angle
+    fn fresnel(&self, cos_incident: f64, wavelengths: &Wavelengths) -> SampledSpectrum {
+        let rgb = Vector3::from_fn(|i, _| fresnel_conductor(cos_incident, self.eta[i], self.k[i]));
+
+        wavelengths.map(rgb, |w| {
+            let (eta, k) = self.at(w);
+            fresnel_conductor(cos_incident, eta, k)
+        })
+    }
+}
+
+/// Metal with a rough surface, made of microfacets reflecting like mirrors
+#[derive(Debug)]
+pub struct Conductor {
+    pub ior: ComplexIor,
+    pub distribution: TrowbridgeReitz,
+}
+
+impl Conductor {
+    /// Create a metal with a roughness from 0 for polished to 1 for very dull
+    pub fn new(ior: ComplexIor, roughness: f64) -> Self {
+        Self {
+            ior,
+            distribution: TrowbridgeReitz::new(roughness),
+        }
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(
+        &self,
+        ray: &mut Ray,
+        point: Vector3<f64>,
+        normal: Vector3<f64>,
+        _coordinates: &TextureCoordinates,
+        wavelengths: &mut Wavelengths,
+    ) -> SampledSpectrum {
+        let outgoing = -ray.direction.normalize();
+        let normal = if outgoing.dot(&normal) < 0. {
+            -normal
+        } else {
+            normal
+        };
+
+        let mut rng = rand::rng();
+        let o = to_local(outgoing, normal);
+        let h = self
+            .distribution
+            .sample_visible_normal(o, Vector2::new(rng.random(), rng.random()));
+        let i = h * (2. * o.dot(&h)) - o;
+
+        ray.origin = point + normal * 1e-4;
+        ray.direction = to_world(i, normal);
+        if o.z <= 0. || i.z <= 0. {
+            return wavelengths.constant(0.);
+        }
+
+        // Visible normal sampling leaves only the shadowing of the reflected ray
+        let shadowing = self.distribution.masking_shadowing(o, i) / self.distribution.masking(o);
+        self.ior.fresnel(o.dot(&h), wavelengths) * shadowing
+    }
+
+    fn eval(
+        &self,
+        outgoing: Vector3<f64>,
+        incoming: Vector3<f64>,
+        normal: Vector3<f64>,
+        _coordinates: &TextureCoordinates,
+        wavelengths: &Wavelengths,
+    ) -> SampledSpectrum {
+        let normal = if outgoing.dot(&normal) < 0. {
+            -normal
+        } else {
+            normal
+        };
+        let (o, i) = (to_local(outgoing, normal), to_local(incoming, normal));
+        let h = o + i;
+        if o.z <= 0. || i.z <= 0. || h == Vector3::zeros() {
+            return wavelengths.constant(0.);
+        }
+        let h = h.normalize();
+
+        let d = self.distribution.distribution(h);
+        let g = self.distribution.masking_shadowing(o, i);
+        self.ior.fresnel(o.dot(&h), wavelengths) * (d * g / (4. * o.z * i.z))
+    }
+
+    fn pdf(
+        &self,
+        outgoing: Vector3<f64>,
+        incoming: Vector3<f64>,
+        normal: Vector3<f64>,
+        _coordinates: &TextureCoordinates,
+    ) -> f64 {
+        let normal = if outgoing.dot(&normal) < 0. {
+            -normal
+        } else {
+            normal
+        };
+        let (o, i) = (to_local(outgoing, normal), to_local(incoming, normal));
+        let h = o + i;
+        if o.z <= 0. || i.z <= 0. || h == Vector3::zeros() {
+            return 0.;
+        }
+        let h = h.normalize();
+
+        // Reflecting about the microfacet normal squeezes its density by 4 o·h
+        self.distribution.visible_distribution(o, h) / (4. * o.dot(&h))
+    }
+}
+
+/// Frosted glass or liquid, made of microfacets which reflect or refract
+/// light like [Dielectric] does. The index of refraction is the same for
+/// every wavelength
+#[derive(Debug)]
+pub struct RoughDielectric {
+    pub ior: f64,
+    pub distribution: TrowbridgeReitz,
+}
+
+impl RoughDielectric {
+    /// Create a dielectric with a roughness from 0 for clear to 1 for very frosted
+    pub fn new(ior: f64, roughness: f64) -> Self {
+        Self {
+            ior,
+            distribution: TrowbridgeReitz::new(roughness),
+        }
+    }
+
+    /// Normal on the side of `outgoing`, and the index of refraction of the
+    /// other side relative to that side
+    fn orient(&self, outgoing: Vector3<f64>, normal: Vector3<f64>) -> (Vector3<f64>, f64) {
+        if outgoing.dot(&normal) < 0. {
+            (-normal, 1. / self.ior)
+        } else {
+            (normal, self.ior)
+        }
+    }
+
+    /// Fraction of the light reflected by a microfacet, at the cosine between
+    /// `outgoing` and its normal, and the cosine of the refracted ray
+    fn fresnel(cos_outgoing: f64, eta: f64) -> (f64, f64) {
+        let sin_squared = (1. - cos_outgoing * cos_outgoing) / (eta * eta);
+        if sin_squared >= 1. {
+            // Total internal reflection
+            return (1., 0.);
+        }
+
+        let cos_transmitted = (1. - sin_squared).sqrt();
+        (
+            fresnel_dielectric(cos_outgoing, cos_transmitted, 1. / eta),
+            cos_transmitted,
+        )
+    }
+
+    /// Microfacet normal which scatters `o` into `i`, both in the local frame,
+    /// on the same side as `o`. [None] if no microfacet facing `o` could
+    fn half_vector(o: Vector3<f64>, i: Vector3<f64>, eta: f64) -> Option<Vector3<f64>> {
+        let h = if i.z > 0. { o + i } else { o + i * eta };
+        if h.norm_squared() == 0. {
+            return None;
+        }
+
+        let h = h.normalize() * h.z.signum();
+        // Refraction goes through the microfacet, reflection stays on the side of `o`
+        let through = i.z < 0.;
+        (o.dot(&h) > 0. && (i.dot(&h) < 0.) == through).then_some(h)
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(
+        &self,
+        ray: &mut Ray,
+        point: Vector3<f64>,
+        normal: Vector3<f64>,
+        _coordinates: &TextureCoordinates,
+        wavelengths: &mut Wavelengths,
+    ) -> SampledSpectrum {
+        let outgoing = -ray.direction.normalize();
+        let (normal, eta) = self.orient(outgoing, normal);
+
+        let mut rng = rand::rng();
+        let o = to_local(outgoing, normal);
+        let h = self
+            .distribution
+            .sample_visible_normal(o, Vector2::new(rng.random(), rng.random()));
+        let cos_outgoing = o.dot(&h);
+        let (reflectance, cos_transmitted) = Self::fresnel(cos_outgoing, eta);
+
+        // Start the new ray a bit off of the surface, on the side it's going to
+        let reflected = rng.random::<f64>() < reflectance;
+        let i = if reflected {
+            ray.origin = point + normal * 1e-4;
+            h * (2. * cos_outgoing) - o
+        } else {
+            ray.origin = point - normal * 1e-4;
+            -o / eta + h * (cos_outgoing / eta - cos_transmitted)
+        };
+        ray.direction = to_world(i, normal);
+
+        // Microfacets can send the ray to the wrong side of the surface
+        if o.z <= 0. || i.z == 0. || (i.z > 0.) != reflected {
+            return wavelengths.constant(0.);
+        }
+
+        // Fresnel decided between reflection and refraction, and visible normal
+        // sampling leaves only the shadowing of the scattered ray
+        let shadowing = self.distribution.masking_shadowing(o, i) / self.distribution.masking(o);
+        wavelengths.constant(shadowing)
+    }
+
+    fn eval(
+        &self,
+        outgoing: Vector3<f64>,
+        incoming: Vector3<f64>,
+        normal: Vector3<f64>,
+        _coordinates: &TextureCoordinates,
+        wavelengths: &Wavelengths,
+    ) -> SampledSpectrum {
+        let (normal, eta) = self.orient(outgoing, normal);
+        let (o, i) = (to_local(outgoing, normal), to_local(incoming, normal));
+        let Some(h) = Self::half_vector(o, i, eta) else {
+            return wavelengths.constant(0.);
+        };
+        if o.z == 0. || i.z == 0. {
+            return wavelengths.constant(0.);
+        }
+
+        let (reflectance, _) = Self::fresnel(o.dot(&h), eta);
+        let d = self.distribution.distribution(h);
+        let g = self.distribution.masking_shadowing(o, i);
+
+        let value = if i.z > 0. {
+            reflectance * d * g / (4. * o.z * i.z)
+        } else {
+            let denominator = i.dot(&h) + o.dot(&h) / eta;
+            (1. - reflectance) * d * g * (i.dot(&h) * o.dot(&h)).abs()
+                / (o.z * i.z.abs() * denominator * denominator)
+        };
+        wavelengths.constant(value)
+    }
+
+    fn pdf(
+        &self,
+        outgoing: Vector3<f64>,
+        incoming: Vector3<f64>,
+        normal: Vector3<f64>,
+        _coordinates: &TextureCoordinates,
+    ) -> f64 {
+        let (normal, eta) = self.orient(outgoing, normal);
+        let (o, i) = (to_local(outgoing, normal), to_local(incoming, normal));
+        let Some(h) = Self::half_vector(o, i, eta) else {
+            return 0.;
+        };
+
+        let (reflectance, _) = Self::fresnel(o.dot(&h), eta);
+        let visible = self.distribution.visible_distribution(o, h);
+
+        // Density of the microfacet normal, squeezed by reflecting or refracting
+        if i.z > 0. {
+            reflectance * visible / (4. * o.dot(&h))
+        } else {
+            let denominator = i.dot(&h) + o.dot(&h) / eta;
+            (1. - reflectance) * visible * i.dot(&h).abs() / (denominator * denominator)
+        }
+    }
+}
+
+/// Fraction of unpolarized light reflected by a smooth metal, given the cosine
+/// of the incident angle, and the complex index of refraction `eta + i k`
+fn fresnel_conductor(cos_incident: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_incident.clamp(0., 1.).powi(2);
+    let sin2 = 1. - cos2;
+
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4. * eta * eta * k * k).sqrt();
+    let a = ((a2_plus_b2 + t0) / 2.).max(0.).sqrt();
+    let cos = cos2.sqrt();
+
+    let perpendicular = (a2_plus_b2 - 2. * a * cos + cos2) / (a2_plus_b2 + 2. * a * cos + cos2);
+    let t1 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t2 = 2. * a * cos * sin2;
+    let parallel = perpendicular * (t1 - t2) / (t1 + t2);
+
+    (perpendicular + parallel) / 2.
+}
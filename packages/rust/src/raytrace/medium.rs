@@ -0,0 +1,227 @@
+use std::{f64::consts::PI, sync::Arc};
+
+use nalgebra::{Vector2, Vector3};
+use rand::prelude::*;
+
+use super::{
+    Ray, SampledSpectrum, Texture, TextureCoordinates, Transform, Wavelengths, luminance, to_world,
+};
+
+/// Steps after which tracking through a medium gives up, as if the ray had
+/// left it. Only media thinning out towards infinity ever get there
+const MAX_STEPS: usize = 4096;
+
+/// Participating medium, like fog, smoke or murky water, which absorbs and
+/// scatters light everywhere inside of it rather than only at surfaces
+#[derive(Clone, Debug)]
+pub struct Medium {
+    /// Absorption coefficient σa, the fraction of the light absorbed per unit of
+    /// distance, given as a linear sRGB color
+    pub absorption: Vector3<f64>,
+    /// Scattering coefficient σs, the fraction of the light scattered into
+    /// other directions per unit of distance
+    pub scattering: Vector3<f64>,
+    /// Asymmetry g of the Henyey-Greenstein phase function, from -1 scattering
+    /// all light back, over 0 scattering evenly, to 1 letting it go on forward
+    pub asymmetry: f64,
+    /// Density which both coefficients get multiplied by, the luminance of the
+    /// texture at a point in object space, or world space for the fog of a scene.
+    /// Without one the medium is homogeneous, with a density of 1
+    pub density: Option<Arc<dyn Texture>>,
+    /// Highest density of the texture, which tracking through the medium relies
+    /// on. Denser spots get clamped to it
+    pub max_density: f64,
+}
+
+impl Medium {
+    /// Medium with the same coefficients everywhere
+    pub fn homogeneous(absorption: Vector3<f64>, scattering: Vector3<f64>, asymmetry: f64) -> Self {
+        Self {
+            absorption,
+            scattering,
+            asymmetry,
+            density: None,
+            max_density: 1.,
+        }
+    }
+
+    /// Medium whose coefficients are scaled by a `density` texture, which
+    /// never exceeds `max_density`
+    pub fn heterogeneous(
+        absorption: Vector3<f64>,
+        scattering: Vector3<f64>,
+        asymmetry: f64,
+        density: Arc<dyn Texture>,
+        max_density: f64,
+    ) -> Self {
+        Self {
+            density: Some(density),
+            max_density,
+            ..Self::homogeneous(absorption, scattering, asymmetry)
+        }
+    }
+
+    /// Fraction of the light hitting the medium which is scattered rather than
+    /// absorbed, the color the medium appears in
+    pub fn albedo(&self) -> Vector3<f64> {
+        let extinction = self.absorption + self.scattering;
+        self.scattering
+            .zip_map(&extinction, |s, t| if t > 0. { s / t } else { 0. })
+    }
+
+    /// Density at a point in world space, inside of the object placed by `frame`
+    fn density(&self, point: Vector3<f64>, frame: Option<&Transform>) -> f64 {
+        let Some(texture) = &self.density else {
+            return 1.;
+        };
+
+        let coordinates = TextureCoordinates {
+            point: frame.map_or(point, |frame| frame.apply_inverse(point)),
+            uv: Vector2::zeros(),
+        };
+        luminance(texture.evaluate(&coordinates)).clamp(0., self.max_density)
+    }
+
+    /// Scattering and extinction coefficients at a density of 1, at the wavelengths
+    fn coefficients(&self, wavelengths: &Wavelengths) -> (SampledSpectrum, SampledSpectrum) {
+        let scattering = wavelengths.unbounded(self.scattering);
+        let extinction = wavelengths.unbounded(self.absorption) + scattering;
+        (scattering, extinction)
+    }
+
+    /// Probability density per unit solid angle of light travelling along the unit
+    /// vector `direction` being scattered towards `scattered`, of the
+    /// Henyey-Greenstein phase function
+    pub fn phase(&self, direction: Vector3<f64>, scattered: Vector3<f64>) -> f64 {
+        let g = self.asymmetry;
+        let denominator = 1. + g * g - 2. * g * direction.dot(&scattered);
+        (1. - g * g) / (4. * PI * denominator * denominator.max(0.).sqrt())
+    }
+
+    /// Direction light travelling along the unit vector `direction` gets scattered
+    /// towards, picked with a density of exactly [Medium::phase] from a uniformly
+    /// distributed `u` in the unit square
+    pub fn sample_phase(&self, direction: Vector3<f64>, u: Vector2<f64>) -> Vector3<f64> {
+        let g = self.asymmetry;
+        let cos_theta = if g.abs() < 1e-3 {
+            1. - 2. * u.x
+        } else {
+            let square = (1. - g * g) / (1. - g + 2. * g * u.x);
+            (1. + g * g - square * square) / (2. * g)
+        }
+        .clamp(-1., 1.);
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * u.y;
+
+        to_world(
+            Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta),
+            direction,
+        )
+    }
+
+    /// Find where light travelling along the ray, whose direction has to be a unit
+    /// vector, gets scattered before reaching `max_distance`, inside of the object
+    /// placed by `frame`. Returns the distance, or [None] if the light passes
+    /// through, and the factor the light carried by the path gets multiplied by.
+    ///
+    /// Homogeneous media pick the distance by the extinction at one of the
+    /// wavelengths, weighed against the others by the balance heuristic. Others use
+    /// delta tracking with null collisions against a majorant shared by all
+    /// wavelengths, weighting each collision by how likely it is at each of them
+    pub fn sample_distance(
+        &self,
+        ray: &Ray,
+        max_distance: f64,
+        frame: Option<&Transform>,
+        wavelengths: &Wavelengths,
+        rng: &mut impl Rng,
+    ) -> (Option<f64>, SampledSpectrum) {
+        let (scattering, extinction) = self.coefficients(wavelengths);
+        let majorant = extinction.max() * self.max_density;
+        let mut weight = wavelengths.constant(1.);
+        if majorant <= 0. {
+            return (None, weight);
+        }
+
+        if self.density.is_none() {
+            let channel = rng.random_range(0..wavelengths.channels());
+            let distance = -(1. - rng.random::<f64>()).ln() / extinction[channel];
+            let travelled = distance.min(max_distance);
+            let passed = extinction.map(|e| if e > 0. { (-e * travelled).exp() } else { 1. });
+
+            if distance < max_distance {
+                let pdf = wavelengths.average(&extinction.component_mul(&passed));
+                return (Some(distance), scattering.component_mul(&passed) / pdf);
+            }
+            return (None, passed / wavelengths.average(&passed));
+        }
+
+        let mut distance = 0.;
+        for _ in 0..MAX_STEPS {
+            distance -= (1. - rng.random::<f64>()).ln() / majorant;
+            if distance >= max_distance {
+                break;
+            }
+
+            let density = self.density(ray.origin + ray.direction * distance, frame);
+            let extinction = extinction * density;
+            let null = extinction.map(|e| majorant - e);
+            let (real, unreal) = (wavelengths.average(&extinction), wavelengths.average(&null));
+
+            if rng.random::<f64>() * majorant < real {
+                weight.component_mul_assign(&(scattering * (density / real)));
+                return (Some(distance), weight);
+            }
+            weight.component_mul_assign(&(null / unreal));
+        }
+
+        (None, weight)
+    }
+
+    /// Fraction of the light which makes it through the medium along the ray,
+    /// whose direction has to be a unit vector, over the `distance`. Exact for
+    /// homogeneous media, and estimated by ratio tracking otherwise
+    pub fn transmittance(
+        &self,
+        ray: &Ray,
+        distance: f64,
+        frame: Option<&Transform>,
+        wavelengths: &Wavelengths,
+        rng: &mut impl Rng,
+    ) -> SampledSpectrum {
+        let (_, extinction) = self.coefficients(wavelengths);
+        if self.density.is_none() {
+            let passed = extinction.map(|e| if e > 0. { (-e * distance).exp() } else { 1. });
+            return passed.component_mul(&wavelengths.constant(1.));
+        }
+
+        let majorant = extinction.max() * self.max_density;
+        let mut transmittance = wavelengths.constant(1.);
+        if majorant <= 0. {
+            return transmittance;
+        }
+
+        let mut travelled = 0.;
+        for _ in 0..MAX_STEPS {
+            travelled -= (1. - rng.random::<f64>()).ln() / majorant;
+            if travelled >= distance {
+                break;
+            }
+
+            let density = self.density(ray.origin + ray.direction * travelled, frame);
+            transmittance.component_mul_assign(&extinction.map(|e| 1. - e * density / majorant));
+
+            // Once hardly any light is left, let the path either carry on with
+            // more of it or stop, rather than tracking it all the way
+            let left = transmittance.max();
+            if left < 0.1 {
+                if left <= 0. || rng.random::<f64>() >= left {
+                    return SampledSpectrum::zeros();
+                }
+                transmittance /= left;
+            }
+        }
+
+        transmittance
+    }
+}
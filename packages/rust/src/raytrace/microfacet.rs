@@ -0,0 +1,100 @@
+use std::f64::consts::PI;
+
+use nalgebra::{Vector2, Vector3};
+
+use super::concentric_disk;
+
+/// Smallest roughness the distribution works with, anything smoother runs out
+/// of floating point precision. Use the smooth materials for perfect mirrors and glass
+const MIN_ALPHA: f64 = 1e-3;
+
+/// Trowbridge-Reitz (GGX) distribution of microfacet normals, with Smith
+/// masking-shadowing (Walter et al. 2007, Heitz 2014).
+///
+/// Directions are given in a local frame with the surface normal as +Z
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrowbridgeReitz {
+    /// Width of the distribution, the slope a typical microfacet has
+    pub alpha: f64,
+}
+
+impl TrowbridgeReitz {
+    /// Distribution for a perceptual roughness from 0 to 1, which gets squared,
+    /// so that the look changes evenly along the range
+    pub fn new(roughness: f64) -> Self {
+        Self {
+            alpha: (roughness * roughness).max(MIN_ALPHA),
+        }
+    }
+
+    /// Density of microfacets facing towards `h`, per unit solid angle and
+    /// unit of projected surface area
+    pub fn distribution(&self, h: Vector3<f64>) -> f64 {
+        if h.z <= 0. {
+            return 0.;
+        }
+
+        let alpha2 = self.alpha * self.alpha;
+        let t = h.z * h.z * (alpha2 - 1.) + 1.;
+        alpha2 / (PI * t * t)
+    }
+
+    /// Smith's auxiliary function, the area of microfacets hidden from `v`
+    /// relative to the visible area
+    fn lambda(&self, v: Vector3<f64>) -> f64 {
+        let cos2 = v.z * v.z;
+        if cos2 == 0. {
+            return f64::INFINITY;
+        }
+
+        let tan2 = (1. - cos2).max(0.) / cos2;
+        ((1. + self.alpha * self.alpha * tan2).sqrt() - 1.) / 2.
+    }
+
+    /// Fraction of the microfacets visible from `v`
+    pub fn masking(&self, v: Vector3<f64>) -> f64 {
+        1. / (1. + self.lambda(v))
+    }
+
+    /// Fraction of the microfacets visible from both `o` and `i`, with the
+    /// height correlated form, which accounts for masking and shadowing
+    /// going together
+    pub fn masking_shadowing(&self, o: Vector3<f64>, i: Vector3<f64>) -> f64 {
+        1. / (1. + self.lambda(o) + self.lambda(i))
+    }
+
+    /// Density of the normals visible from `o`, per unit solid angle
+    pub fn visible_distribution(&self, o: Vector3<f64>, h: Vector3<f64>) -> f64 {
+        if o.z == 0. {
+            return 0.;
+        }
+
+        self.masking(o) * self.distribution(h) * o.dot(&h).max(0.) / o.z.abs()
+    }
+
+    /// Pick a microfacet normal visible from `o`, which must be above the
+    /// surface, distributed by [TrowbridgeReitz::visible_distribution] (Heitz 2018)
+    pub fn sample_visible_normal(&self, o: Vector3<f64>, u: Vector2<f64>) -> Vector3<f64> {
+        // Stretch the view, so that the microfacets form a hemisphere
+        let stretched = Vector3::new(self.alpha * o.x, self.alpha * o.y, o.z).normalize();
+
+        // The visible part of the hemisphere projects onto a disk, half of which
+        // gets squashed towards the horizon the more it is seen from the side
+        let disk = concentric_disk(u);
+        let horizontal = Vector2::new(-stretched.y, stretched.x);
+        let t1 = if horizontal.norm_squared() > 0. {
+            horizontal.normalize().push(0.)
+        } else {
+            Vector3::x()
+        };
+        let t2 = stretched.cross(&t1);
+        let s = (1. + stretched.z) / 2.;
+        let x = disk.x;
+        let y = (1. - s) * (1. - x * x).max(0.).sqrt() + s * disk.y;
+        let z = (1. - x * x - y * y).max(0.).sqrt();
+        let h = t1 * x + t2 * y + stretched * z;
+
+        // Unstretch
+        Vector3::new(self.alpha * h.x, self.alpha * h.y, h.z.max(1e-6)).normalize()
+    }
+}
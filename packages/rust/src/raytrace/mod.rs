@@ -1,21 +1,59 @@
+pub mod aov;
 pub mod camera;
+pub mod checkpoint;
+pub mod color;
 pub mod config;
+pub mod denoise;
+pub mod environment;
+pub mod filter;
+pub mod light;
 pub mod material;
+pub mod medium;
+pub mod microfacet;
+pub mod normal_map;
 pub mod object;
+pub mod openexr;
+pub mod principled;
+pub mod radiance;
 pub mod ray;
 pub mod render;
+pub mod sampling;
 pub mod scene;
 pub mod shape;
+pub mod sky;
+pub mod spectrum;
+pub mod texture;
+pub mod tile;
+pub mod tonemap;
 pub mod transform;
 
+pub use aov::*;
 pub use camera::*;
+pub use checkpoint::*;
+pub use color::*;
 pub use config::*;
+pub use denoise::*;
+pub use environment::*;
+pub use filter::*;
+pub use light::*;
 pub use material::*;
+pub use medium::*;
+pub use microfacet::*;
+pub use normal_map::*;
 pub use object::*;
+pub use openexr::*;
+pub use principled::*;
+pub use radiance::*;
 pub use ray::*;
 pub use render::*;
+pub use sampling::*;
 pub use scene::*;
 pub use shape::*;
+pub use sky::*;
+pub use spectrum::*;
+pub use texture::*;
+pub use tile::*;
+pub use tonemap::*;
 pub use transform::*;
 
 #[cfg(test)]
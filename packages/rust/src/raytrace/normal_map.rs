@@ -0,0 +1,84 @@
+use std::sync::Arc;
+
+use nalgebra::{Vector2, Vector3};
+
+use super::{Texture, TextureCoordinates, luminance};
+
+/// Step along the texture coordinates over which bump maps are differentiated
+const BUMP_STEP: f64 = 1e-4;
+
+/// Surface detail without extra geometry: the normal the materials see gets
+/// tilted, while the surface itself stays where it is
+#[derive(Clone, Debug)]
+pub enum NormalMap {
+    /// Normals in tangent space, encoded in colors like most normal maps are:
+    /// red along dp/du, green along dp/dv and blue out of the surface, each
+    /// going from -1 to 1 as the color goes from 0 to 1. Images of normals
+    /// should be loaded with [super::ImageTexture::load_linear].
+    ///
+    /// The `strength` scales the tilt, 1 leaving the normals as they are
+    Tangent {
+        texture: Arc<dyn Texture>,
+        strength: f64,
+    },
+    /// Heights, given by the luminance of the texture times the `scale`,
+    /// which the surface appears pushed out by along its normal
+    Bump {
+        height: Arc<dyn Texture>,
+        scale: f64,
+    },
+}
+
+impl NormalMap {
+    /// Tilt the outward facing `normal` at the coordinates, where the surface
+    /// has the tangents `dpdu` and `dpdv`, all in object space. The result stays
+    /// on the outside, and falls back to the `normal` where the tilt breaks down
+    pub fn perturb(
+        &self,
+        coordinates: &TextureCoordinates,
+        normal: Vector3<f64>,
+        dpdu: Vector3<f64>,
+        dpdv: Vector3<f64>,
+    ) -> Vector3<f64> {
+        let perturbed = match self {
+            NormalMap::Tangent { texture, strength } => {
+                // Where dp/du vanishes, like at the poles of a sphere, the tangent
+                // is the direction dp/du would have next to it
+                let mut tangent = dpdu - normal * normal.dot(&dpdu);
+                if tangent.norm_squared() < 1e-12 {
+                    tangent = dpdv.cross(&normal);
+                }
+                let tangent = tangent.normalize();
+                let bitangent = normal.cross(&tangent);
+
+                let encoded = texture.evaluate(coordinates) * 2. - Vector3::repeat(1.);
+                tangent * (encoded.x * strength)
+                    + bitangent * (encoded.y * strength)
+                    + normal * encoded.z.max(0.)
+            }
+            NormalMap::Bump { height, scale } => {
+                // The tangents of the displaced surface p + h n, leaving out
+                // how the normal itself turns, which bump maps barely notice
+                let height_at = |du: f64, dv: f64| {
+                    let shifted = TextureCoordinates {
+                        point: coordinates.point + dpdu * du + dpdv * dv,
+                        uv: coordinates.uv + Vector2::new(du, dv),
+                    };
+                    luminance(height.evaluate(&shifted)) * scale
+                };
+                let center = height_at(0., 0.);
+                let dhdu = (height_at(BUMP_STEP, 0.) - center) / BUMP_STEP;
+                let dhdv = (height_at(0., BUMP_STEP) - center) / BUMP_STEP;
+
+                (dpdu + normal * dhdu).cross(&(dpdv + normal * dhdv))
+            }
+        };
+
+        let perturbed = perturbed.normalize();
+        if perturbed.iter().all(|c| c.is_finite()) && perturbed.dot(&normal) > 0. {
+            perturbed
+        } else {
+            normal
+        }
+    }
+}
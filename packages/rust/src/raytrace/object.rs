@@ -1,25 +1,68 @@
+use std::{f64::consts::PI, sync::Arc};
+
 use nalgebra::Vector3;
 
-use super::{Lambertian, Material, Ray, Shape, Sphere, Transform};
+use super::{
+    LUMINOUS_EFFICACY, Lambertian, Material, Medium, NormalMap, Ray, SampledSpectrum, Shape,
+    SolidColor, Sphere, Texture, TextureCoordinates, Transform, Wavelengths, blackbody, luminance,
+};
+
+/// Point on the surface of an object, with everything it takes to shade it,
+/// all in world coordinates
+#[derive(Clone, Copy, Debug)]
+pub struct SurfacePoint {
+    pub point: Vector3<f64>,
+    /// Outward facing normal of the actual surface, which decides on which
+    /// side of it a direction is
+    pub normal: Vector3<f64>,
+    /// Normal the material sees, tilted by the [Object::normal_map]
+    pub shading_normal: Vector3<f64>,
+    pub coordinates: TextureCoordinates,
+}
+
+impl SurfacePoint {
+    /// Whether the shading normal agrees with the surface on `incoming` being
+    /// on the same side as `outgoing`, or on the other one. Following the shading
+    /// normal where they disagree would let light leak through the surface
+    pub fn is_consistent(&self, outgoing: Vector3<f64>, incoming: Vector3<f64>) -> bool {
+        let geometric = outgoing.dot(&self.normal) * incoming.dot(&self.normal);
+        let shading = outgoing.dot(&self.shading_normal) * incoming.dot(&self.shading_normal);
+        geometric * shading > 0.
+    }
+}
 
+#[derive(Debug)]
 pub struct Object {
-    pub color: Vector3<f64>,
+    /// Tint of all the light the surface scatters
+    pub color: Arc<dyn Texture>,
     pub emission_color: Vector3<f64>,
     pub emission_strength: f64,
+    /// Temperature in kelvin of a black body, whose spectrum replaces the
+    /// one of the [Object::emission_color] when rendering spectrally
+    pub emission_temperature: Option<f64>,
     pub transform: Transform,
     pub material: Box<dyn Material>,
     pub shape: Box<dyn Shape>,
+    /// Detail tilting the normal the material sees
+    pub normal_map: Option<NormalMap>,
+    /// Medium filling the inside of the object, whose shape has to be closed.
+    /// Media of different objects must not overlap. With an [super::Interface]
+    /// material, the object is nothing but the medium
+    pub medium: Option<Medium>,
 }
 
 impl Default for Object {
     fn default() -> Self {
         Object {
-            color: Vector3::new(1., 1., 1.),
+            color: Arc::new(SolidColor(Vector3::new(1., 1., 1.))),
             emission_color: Vector3::new(0., 0., 0.),
             emission_strength: 0.,
+            emission_temperature: None,
             material: Box::new(Lambertian::new()),
             shape: Box::new(Sphere::new()),
             transform: Transform::default(),
+            normal_map: None,
+            medium: None,
         }
     }
 }
@@ -34,7 +77,7 @@ impl Object {
         Self {
             shape,
             material,
-            color,
+            color: Arc::new(SolidColor(color)),
             transform,
             ..Default::default()
         }
@@ -67,4 +110,108 @@ impl Object {
         // Translate coordinates back to global coordinate space
         Some(self.transform.apply(intersection))
     }
+
+    /// Get the normal vector of the object at a point on its surface,
+    /// both in world coordinates
+    pub fn normal(&self, point: Vector3<f64>) -> Vector3<f64> {
+        let local_point = self.transform.apply_inverse(point);
+        self.transform.apply_normal(self.shape.normal(local_point))
+    }
+
+    /// Partial derivatives dp/du and dp/dv of a point on the surface, both in
+    /// world coordinates, see [Shape::tangents]
+    pub fn tangents(&self, point: Vector3<f64>) -> (Vector3<f64>, Vector3<f64>) {
+        let (dpdu, dpdv) = self.shape.tangents(self.transform.apply_inverse(point));
+        (
+            self.transform.apply_vector(dpdu),
+            self.transform.apply_vector(dpdv),
+        )
+    }
+
+    /// Where textures get looked up at a point on the surface, in world coordinates
+    pub fn texture_coordinates(&self, point: Vector3<f64>) -> TextureCoordinates {
+        let local_point = self.transform.apply_inverse(point);
+        TextureCoordinates {
+            point: local_point,
+            uv: self.shape.uv(local_point),
+        }
+    }
+
+    /// Everything it takes to shade a point on the surface, in world coordinates
+    pub fn surface(&self, point: Vector3<f64>) -> SurfacePoint {
+        let local_point = self.transform.apply_inverse(point);
+        let local_normal = self.shape.normal(local_point);
+        let coordinates = TextureCoordinates {
+            point: local_point,
+            uv: self.shape.uv(local_point),
+        };
+
+        let normal = self.transform.apply_normal(local_normal);
+        let shading_normal = match &self.normal_map {
+            Some(normal_map) => {
+                let (dpdu, dpdv) = self.shape.tangents(local_point);
+                let local_shading = normal_map.perturb(&coordinates, local_normal, dpdu, dpdv);
+                self.transform.apply_normal(local_shading)
+            }
+            None => normal,
+        };
+
+        SurfacePoint {
+            point,
+            normal,
+            shading_normal,
+            coordinates,
+        }
+    }
+
+    /// Light emitted by the surface, at the wavelengths
+    pub fn emission(&self, wavelengths: &Wavelengths) -> SampledSpectrum {
+        let rgb = self.emission_color * self.emission_strength + self.material.emission();
+
+        match self.emission_temperature {
+            Some(temperature) => wavelengths.blackbody(temperature, luminance(rgb), rgb),
+            None => wavelengths.illuminant(rgb),
+        }
+    }
+
+    /// Whether the surface gives off any light
+    pub fn is_emissive(&self) -> bool {
+        self.emission_strength != 0. || self.material.emission() != Vector3::zeros()
+    }
+
+    /// Surface area in world coordinates
+    pub fn area(&self) -> f64 {
+        self.shape.area(self.transform.scale)
+    }
+
+    /// Emit the light of a black body at the temperature, given in kelvin.
+    /// The strength is the luminance, 1 being 683 candela per square meter
+    pub fn set_emission_temperature(&mut self, temperature: f64, strength: f64) {
+        self.emission_color = blackbody(temperature);
+        self.emission_strength = strength;
+        self.emission_temperature = Some(temperature);
+    }
+
+    /// Scale the emission, so that the object gives off the luminous power,
+    /// given in lumens, keeping its color. Emission is the same in every direction
+    /// of every point of the surface, just like light reflected by [Lambertian]
+    pub fn set_luminous_power(&mut self, lumens: f64) {
+        // A surface emitting the radiance L evenly gives off π L per unit of area
+        let radiance = luminance(self.emission_color);
+        let area = self.area();
+        self.emission_strength = if radiance > 0. && area > 0. {
+            lumens / (LUMINOUS_EFFICACY * PI * area * radiance)
+        } else {
+            0.
+        };
+    }
+
+    /// Luminous power given off by the emission, in lumens
+    pub fn luminous_power(&self) -> f64 {
+        LUMINOUS_EFFICACY
+            * PI
+            * self.area()
+            * luminance(self.emission_color)
+            * self.emission_strength
+    }
 }
@@ -0,0 +1,99 @@
+use std::io::Cursor;
+
+use exr::prelude::*;
+use nalgebra::Vector3;
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// Precision of the samples written into an OpenEXR file
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExrPrecision {
+    /// 16-bit floats, half the file size and plenty for color data
+    Half,
+    /// 32-bit floats, for data that needs the full precision (depth, positions)
+    Float,
+}
+
+/// One named channel of an image (for example "R"), stored row by row
+pub struct ImageChannel {
+    pub name: String,
+    pub samples: Vec<f32>,
+}
+
+/// A group of channels which belong together, for example RGB of the main image
+pub struct ImageLayer {
+    /// Name of the layer. Channels of a layer named "albedo" are written as
+    /// "albedo.R", "albedo.G", ... while the unnamed layer is the main image
+    pub name: String,
+    pub channels: Vec<ImageChannel>,
+}
+
+/// Encode layers of an image as a single part OpenEXR file.
+///
+/// All channels must have `width * height` samples
+pub fn write_exr(
+    width: usize,
+    height: usize,
+    layers: &[ImageLayer],
+    precision: ExrPrecision,
+) -> Result<Vec<u8>> {
+    let mut channels = SmallVec::new();
+
+    for layer in layers {
+        for channel in &layer.channels {
+            let name = if layer.name.is_empty() {
+                channel.name.clone()
+            } else {
+                format!("{}.{}", layer.name, channel.name)
+            };
+
+            let samples = match precision {
+                ExrPrecision::Half => {
+                    FlatSamples::F16(channel.samples.iter().map(|&s| f16::from_f32(s)).collect())
+                }
+                ExrPrecision::Float => FlatSamples::F32(channel.samples.clone()),
+            };
+
+            channels.push(AnyChannel::new(name.as_str(), samples));
+        }
+    }
+
+    let layer = Layer::new(
+        (width, height),
+        LayerAttributes::default(),
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(channels),
+    );
+
+    let mut bytes = Vec::new();
+    Image::from_layer(layer)
+        .write()
+        .to_buffered(Cursor::new(&mut bytes))?;
+
+    Ok(bytes)
+}
+
+/// Decode the RGB channels of the first layer of an OpenEXR file, returning
+/// the width, height and linear colors of the pixels, row by row
+pub fn read_exr(bytes: &[u8]) -> Result<(usize, usize, Vec<Vector3<f64>>)> {
+    let image = read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .rgb_channels(
+            |resolution, _| {
+                let pixels = vec![Vector3::zeros(); resolution.width() * resolution.height()];
+                (resolution.width(), pixels)
+            },
+            |(width, pixels), position, (r, g, b): (f32, f32, f32)| {
+                pixels[position.y() * *width + position.x()] =
+                    Vector3::new(r as f64, g as f64, b as f64);
+            },
+        )
+        .first_valid_layer()
+        .all_attributes()
+        .from_buffered(Cursor::new(bytes))?;
+
+    let size = image.layer_data.size;
+    let (_, pixels) = image.layer_data.channel_data.pixels;
+    Ok((size.width(), size.height(), pixels))
+}
@@ -0,0 +1,473 @@
+use std::{f64::consts::FRAC_1_PI, sync::Arc};
+
+use nalgebra::{Vector2, Vector3};
+use rand::prelude::*;
+
+use super::{
+    Material, Ray, RoughDielectric, SampledSpectrum, SolidColor, Texture, TextureCoordinates,
+    TrowbridgeReitz, Wavelengths, concentric_disk, fresnel_dielectric, luminance, to_local,
+    to_world,
+};
+
+/// Roughness of the clear coating, which is always glossy
+const CLEARCOAT_ROUGHNESS: f64 = 0.15;
+
+/// Reflectance of the clear coating seen head on, like varnish with an index of refraction of 1.5
+const CLEARCOAT_REFLECTANCE: f64 = 0.04;
+
+/// Uber material covering most surfaces with a few intuitive parameters, after
+/// the principled BSDF by Burley 2012 and 2015. It blends diffuse reflection
+/// with a sheen, a microfacet reflection for metals and dielectrics, rough
+/// glass, and a clear coating on top.
+///
+/// All parameters but the colors go from 0 to 1. The object color should be
+/// left white, since it would tint the specular reflection of dielectrics too
+#[derive(Clone, Debug)]
+pub struct Principled {
+    /// Linear color of the diffuse reflection, of metals, and of light passing through
+    pub base_color: Arc<dyn Texture>,
+    /// From dielectrics, like plastic or wood, to metals
+    pub metallic: f64,
+    /// From polished to very dull, for both reflection and transmission
+    pub roughness: f64,
+    /// Reflectance of dielectrics seen head on, 0.5 being the 4% of most of them.
+    /// It also decides the index of refraction of transmitted light
+    pub specular: f64,
+    /// How much the specular reflection of dielectrics takes on the base color
+    pub specular_tint: f64,
+    /// White reflection at grazing angles, like cloth has
+    pub sheen: f64,
+    /// Strength of a clear, glossy coating on top
+    pub clearcoat: f64,
+    /// From opaque to letting light through like glass does
+    pub transmission: f64,
+    /// Light given off, on top of the emission of the object
+    pub emission: Vector3<f64>,
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Self {
+            base_color: Arc::new(SolidColor(Vector3::repeat(0.8))),
+            metallic: 0.,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.,
+            sheen: 0.,
+            clearcoat: 0.,
+            transmission: 0.,
+            emission: Vector3::zeros(),
+        }
+    }
+}
+
+/// Value and sampling density of [Principled], for some pair of directions
+#[derive(Default)]
+struct Evaluation {
+    /// Factors of the base color, and of white, which add up to the value
+    base: f64,
+    white: f64,
+    pdf: f64,
+}
+
+impl Principled {
+    pub fn new(base_color: Vector3<f64>) -> Self {
+        Self {
+            base_color: Arc::new(SolidColor(base_color)),
+            ..Default::default()
+        }
+    }
+
+    /// Index of refraction with the reflectance given by [Principled::specular]
+    pub fn ior(&self) -> f64 {
+        let reflectance = (0.08 * self.specular).clamp(0., 0.99);
+        (1. + reflectance.sqrt()) / (1. - reflectance.sqrt())
+    }
+
+    fn distribution(&self) -> TrowbridgeReitz {
+        TrowbridgeReitz::new(self.roughness)
+    }
+
+    fn glass(&self) -> RoughDielectric {
+        RoughDielectric::new(self.ior(), self.roughness)
+    }
+
+    /// Fraction of the light arriving at the cosine `cos` which the clear coating reflects
+    fn coating(&self, cos: f64) -> f64 {
+        self.clearcoat * schlick(CLEARCOAT_REFLECTANCE, cos)
+    }
+
+    /// Chance of sampling the diffuse, specular, glass and clear coating lobes,
+    /// roughly following how much light each of them reflects towards `o`
+    fn lobe_weights(&self, o: Vector3<f64>, base_color: Vector3<f64>) -> [f64; 4] {
+        let base = luminance(base_color);
+        let under = 1. - self.coating(o.z);
+        let fresnel = fresnel(o.z, self.ior());
+        let dielectric = (1. - self.metallic) * (1. - self.transmission);
+        let edge = (1. - o.z).powi(5);
+
+        let weights = [
+            under * dielectric * (1. - fresnel) * base.max(self.sheen),
+            under * (self.metallic * (base + (1. - base) * edge) + dielectric * fresnel),
+            under * (1. - self.metallic) * self.transmission,
+            self.coating(o.z),
+        ];
+
+        let total: f64 = weights.iter().sum();
+        if total <= 0. {
+            return [0.; 4];
+        }
+        weights.map(|w| w / total)
+    }
+
+    /// Value and density of scattering `outgoing` into `incoming`, in world space,
+    /// where the surface has the `base_color`
+    fn evaluate(
+        &self,
+        outgoing: Vector3<f64>,
+        incoming: Vector3<f64>,
+        normal: Vector3<f64>,
+        base_color: Vector3<f64>,
+    ) -> Evaluation {
+        let mut evaluation = Evaluation::default();
+        // All but the glass only reflect, on the side of the surface light leaves from
+        let facing = if outgoing.dot(&normal) < 0. {
+            -normal
+        } else {
+            normal
+        };
+        let (o, i) = (to_local(outgoing, facing), to_local(incoming, facing));
+        if o.z <= 0. {
+            return evaluation;
+        }
+
+        let [diffuse_pick, specular_pick, glass_pick, clearcoat_pick] =
+            self.lobe_weights(o, base_color);
+        let ior = self.ior();
+        let under = 1. - self.coating(o.z);
+        let dielectric = (1. - self.metallic) * (1. - self.transmission);
+
+        if i.z > 0. {
+            let h = (o + i).normalize();
+
+            // Diffuse, with the light refracted into the surface, turning into
+            // the sheen at grazing angles
+            let sheen = self.sheen * (1. - i.dot(&h)).powi(5);
+            let diffuse = under * dielectric * (1. - fresnel(o.z, ior)) * FRAC_1_PI;
+            evaluation.base += diffuse * (1. - sheen);
+            evaluation.white += diffuse * sheen;
+            evaluation.pdf += diffuse_pick * i.z * FRAC_1_PI;
+
+            // Metals reflect the base color, turning white at grazing angles,
+            // dielectrics reflect white, or their base color hue when tinted
+            let distribution = self.distribution();
+            let microfacets =
+                under * distribution.distribution(h) * distribution.masking_shadowing(o, i)
+                    / (4. * o.z * i.z);
+            let edge = (1. - o.dot(&h)).powi(5);
+            let reflectance = dielectric * fresnel(o.dot(&h), ior);
+            let base = luminance(base_color);
+            let tint = if base > 0. { self.specular_tint } else { 0. };
+            evaluation.base += microfacets
+                * (self.metallic * (1. - edge) + reflectance * tint / base.max(f64::EPSILON));
+            evaluation.white += microfacets * (self.metallic * edge + reflectance * (1. - tint));
+            evaluation.pdf +=
+                specular_pick * distribution.visible_distribution(o, h) / (4. * o.dot(&h));
+
+            let coating = TrowbridgeReitz::new(CLEARCOAT_ROUGHNESS);
+            evaluation.white += self.clearcoat
+                * schlick(CLEARCOAT_REFLECTANCE, o.dot(&h))
+                * coating.distribution(h)
+                * coating.masking_shadowing(o, i)
+                / (4. * o.z * i.z);
+            evaluation.pdf +=
+                clearcoat_pick * coating.visible_distribution(o, h) / (4. * o.dot(&h));
+        }
+
+        // Glass reflects white, and tints the light passing through
+        if glass_pick > 0. {
+            // Glass has no textures
+            let glass = self.glass();
+            let coordinates = TextureCoordinates::default();
+            let value = under
+                * (1. - self.metallic)
+                * self.transmission
+                * glass
+                    .eval(outgoing, incoming, normal, &coordinates, &Wavelengths::Rgb)
+                    .x;
+            if i.z < 0. {
+                evaluation.base += value;
+            } else {
+                evaluation.white += value;
+            }
+            evaluation.pdf += glass_pick * glass.pdf(outgoing, incoming, normal, &coordinates);
+        }
+
+        evaluation
+    }
+
+    fn spectrum(
+        evaluation: &Evaluation,
+        base_color: Vector3<f64>,
+        wavelengths: &Wavelengths,
+    ) -> SampledSpectrum {
+        wavelengths.reflectance(base_color) * evaluation.base
+            + wavelengths.constant(evaluation.white)
+    }
+}
+
+impl Material for Principled {
+    fn scatter(
+        &self,
+        ray: &mut Ray,
+        point: Vector3<f64>,
+        normal: Vector3<f64>,
+        coordinates: &TextureCoordinates,
+        wavelengths: &mut Wavelengths,
+    ) -> SampledSpectrum {
+        let base_color = self.base_color.evaluate(coordinates);
+        let outgoing = -ray.direction.normalize();
+        let facing = if outgoing.dot(&normal) < 0. {
+            -normal
+        } else {
+            normal
+        };
+        let o = to_local(outgoing, facing);
+
+        // Pick one of the lobes, then weigh the direction by all of them together
+        let mut rng = rand::rng();
+        let weights = self.lobe_weights(o, base_color);
+        let u = Vector2::new(rng.random(), rng.random());
+        let mut pick = rng.random::<f64>();
+        let lobe = weights
+            .iter()
+            .position(|&w| {
+                pick -= w;
+                pick < 0.
+            })
+            .unwrap_or(0);
+
+        let i = match lobe {
+            0 => {
+                let disk = concentric_disk(u);
+                disk.push((1. - disk.norm_squared()).max(0.).sqrt())
+            }
+            1 => {
+                let h = self.distribution().sample_visible_normal(o, u);
+                h * (2. * o.dot(&h)) - o
+            }
+            2 => {
+                // Glass decides on which side of the surface the ray continues
+                self.glass()
+                    .scatter(ray, point, normal, coordinates, &mut Wavelengths::Rgb);
+                to_local(ray.direction.normalize(), facing)
+            }
+            _ => {
+                let h = TrowbridgeReitz::new(CLEARCOAT_ROUGHNESS).sample_visible_normal(o, u);
+                h * (2. * o.dot(&h)) - o
+            }
+        };
+        if lobe != 2 {
+            ray.origin = point + facing * 1e-4;
+            ray.direction = to_world(i, facing);
+        }
+
+        let evaluation = self.evaluate(outgoing, ray.direction, normal, base_color);
+        if o.z <= 0. || evaluation.pdf <= 0. {
+            return wavelengths.constant(0.);
+        }
+
+        Self::spectrum(&evaluation, base_color, wavelengths) * (i.z.abs() / evaluation.pdf)
+    }
+
+    fn eval(
+        &self,
+        outgoing: Vector3<f64>,
+        incoming: Vector3<f64>,
+        normal: Vector3<f64>,
+        coordinates: &TextureCoordinates,
+        wavelengths: &Wavelengths,
+    ) -> SampledSpectrum {
+        let base_color = self.base_color.evaluate(coordinates);
+        let evaluation = self.evaluate(outgoing, incoming, normal, base_color);
+        Self::spectrum(&evaluation, base_color, wavelengths)
+    }
+
+    fn pdf(
+        &self,
+        outgoing: Vector3<f64>,
+        incoming: Vector3<f64>,
+        normal: Vector3<f64>,
+        coordinates: &TextureCoordinates,
+    ) -> f64 {
+        let base_color = self.base_color.evaluate(coordinates);
+        self.evaluate(outgoing, incoming, normal, base_color).pdf
+    }
+
+    fn emission(&self) -> Vector3<f64> {
+        self.emission
+    }
+
+    fn albedo(&self, coordinates: &TextureCoordinates) -> Vector3<f64> {
+        self.base_color.evaluate(coordinates)
+    }
+}
+
+/// Material of a glTF 2.0 asset: the metallic-roughness model of the core
+/// specification, with the extensions [Principled] has a parameter for.
+/// Alpha coverage is left out
+#[derive(Clone, Debug, PartialEq)]
+pub struct GltfMaterial {
+    pub base_color_factor: [f64; 4],
+    pub metallic_factor: f64,
+    pub roughness_factor: f64,
+    pub emissive_factor: [f64; 3],
+    /// From `KHR_materials_emissive_strength`
+    pub emissive_strength: f64,
+    /// From `KHR_materials_ior`
+    pub ior: f64,
+    /// From `KHR_materials_specular`
+    pub specular_factor: f64,
+    /// From `KHR_materials_sheen`, the sheen color is taken by its brightest channel
+    pub sheen_color_factor: [f64; 3],
+    /// From `KHR_materials_clearcoat`
+    pub clearcoat_factor: f64,
+    /// From `KHR_materials_transmission`
+    pub transmission_factor: f64,
+}
+
+impl Default for GltfMaterial {
+    /// Defaults of the specification
+    fn default() -> Self {
+        Self {
+            base_color_factor: [1.; 4],
+            metallic_factor: 1.,
+            roughness_factor: 1.,
+            emissive_factor: [0.; 3],
+            emissive_strength: 1.,
+            ior: 1.5,
+            specular_factor: 1.,
+            sheen_color_factor: [0.; 3],
+            clearcoat_factor: 0.,
+            transmission_factor: 0.,
+        }
+    }
+}
+
+impl From<&GltfMaterial> for Principled {
+    fn from(material: &GltfMaterial) -> Self {
+        let [r, g, b, _] = material.base_color_factor;
+        // glTF uses the same perceptual roughness, and a reflectance of 4% for dielectrics
+        let reflectance = ((material.ior - 1.) / (material.ior + 1.)).powi(2);
+
+        Self {
+            base_color: Arc::new(SolidColor(Vector3::new(r, g, b))),
+            metallic: material.metallic_factor,
+            roughness: material.roughness_factor,
+            specular: reflectance * material.specular_factor / 0.08,
+            specular_tint: 0.,
+            sheen: material.sheen_color_factor.into_iter().fold(0., f64::max),
+            clearcoat: material.clearcoat_factor,
+            transmission: material.transmission_factor,
+            emission: Vector3::from(material.emissive_factor) * material.emissive_strength,
+        }
+    }
+}
+
+/// Material of a Wavefront MTL file, including the physically based extension
+/// with its `Pr`, `Pm`, `Ps` and `Pc` statements
+#[derive(Clone, Debug, PartialEq)]
+pub struct MtlMaterial {
+    pub name: String,
+    /// `Kd`
+    pub diffuse: Vector3<f64>,
+    /// `Ks`
+    pub specular: Vector3<f64>,
+    /// `Ns`, the Phong exponent
+    pub shininess: f64,
+    /// `Ni`
+    pub ior: Option<f64>,
+    /// `illum`, the illumination model. Models 4, 6, 7 and 9 let light pass through
+    pub illum: Option<u32>,
+    /// `Ke`
+    pub emission: Vector3<f64>,
+    /// `Pr`, replacing the [MtlMaterial::shininess]
+    pub roughness: Option<f64>,
+    /// `Pm`
+    pub metallic: f64,
+    /// `Ps`
+    pub sheen: f64,
+    /// `Pc`
+    pub clearcoat: f64,
+}
+
+impl MtlMaterial {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            diffuse: Vector3::repeat(0.8),
+            specular: Vector3::repeat(0.5),
+            shininess: 250.,
+            ior: None,
+            illum: None,
+            emission: Vector3::zeros(),
+            roughness: None,
+            metallic: 0.,
+            sheen: 0.,
+            clearcoat: 0.,
+        }
+    }
+}
+
+impl From<&MtlMaterial> for Principled {
+    fn from(material: &MtlMaterial) -> Self {
+        // A Phong lobe with the exponent n is about as wide as a microfacet
+        // distribution with α = √(2 / (n + 2)) (Walter et al. 2007)
+        let roughness = material.roughness.unwrap_or_else(|| {
+            let alpha = (2. / (material.shininess.max(0.) + 2.)).sqrt();
+            alpha.sqrt()
+        });
+        // Without an index of refraction, a black specular color turns the highlights off
+        let specular = match material.ior {
+            Some(ior) => ((ior - 1.) / (ior + 1.)).powi(2) / 0.08,
+            None if material.specular == Vector3::zeros() => 0.,
+            None => 0.5,
+        };
+
+        // The dissolve is left out, like the alpha of glTF, since it's how much of
+        // the surface is there, rather than how much light passes through it
+        let transmission = match material.illum {
+            Some(4 | 6 | 7 | 9) => 1.,
+            _ => 0.,
+        };
+
+        Self {
+            base_color: Arc::new(SolidColor(material.diffuse)),
+            metallic: material.metallic,
+            roughness,
+            specular,
+            specular_tint: 0.,
+            sheen: material.sheen,
+            clearcoat: material.clearcoat,
+            transmission,
+            emission: material.emission,
+        }
+    }
+}
+
+/// Schlick's approximation of the Fresnel reflectance at the cosine `cos`,
+/// given the reflectance seen head on
+fn schlick(reflectance: f64, cos: f64) -> f64 {
+    reflectance + (1. - reflectance) * (1. - cos).powi(5)
+}
+
+/// Fraction of the light arriving at the cosine `cos` from outside which a
+/// smooth dielectric with the index of refraction reflects
+fn fresnel(cos: f64, ior: f64) -> f64 {
+    let sin_squared = (1. - cos * cos) / (ior * ior);
+    if sin_squared >= 1. {
+        return 1.;
+    }
+
+    fresnel_dielectric(cos, (1. - sin_squared).sqrt(), 1. / ior)
+}
@@ -0,0 +1,186 @@
+use nalgebra::Vector3;
+
+use super::ImageError;
+
+/// Largest image read, in pixels. The resolution comes from the file, so it's
+/// checked before allocating memory for all of them
+const MAX_PIXELS: usize = 1 << 28;
+
+/// Encode an image as a Radiance RGBE (.hdr) file.
+///
+/// `pixels` are linear colors stored row by row, starting from the top
+pub fn write_hdr(width: usize, height: usize, pixels: &[Vector3<f64>]) -> Vec<u8> {
+    let header = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {height} +X {width}\n");
+
+    let mut bytes = Vec::with_capacity(header.len() + pixels.len() * 4);
+    bytes.extend_from_slice(header.as_bytes());
+
+    // Scanlines are stored flat, without run length encoding,
+    // which every reader has to support
+    for pixel in pixels {
+        bytes.extend_from_slice(&to_rgbe(*pixel));
+    }
+
+    bytes
+}
+
+/// Pack a color into a shared exponent representation: three 8-bit mantissas
+/// and one exponent common to all of them
+fn to_rgbe(color: Vector3<f64>) -> [u8; 4] {
+    let color = color.map(|c| c.max(0.));
+    let max = color.max();
+
+    if max < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+
+    // Find the exponent, so that max = mantissa * 2^exponent, with mantissa in [0.5, 1)
+    let exponent = (max.log2().floor() as i32 + 1).clamp(-128, 127);
+    let scale = 256. / 2f64.powi(exponent);
+
+    [
+        (color.x * scale).min(255.) as u8,
+        (color.y * scale).min(255.) as u8,
+        (color.z * scale).min(255.) as u8,
+        (exponent + 128) as u8,
+    ]
+}
+
+/// Decode a Radiance RGBE (.hdr) file, returning the width, height and linear
+/// colors of the pixels, row by row, starting from the top.
+///
+/// Supports flat and run length encoded scanlines, in the standard `-Y h +X w` orientation
+pub fn read_hdr(bytes: &[u8]) -> Result<(usize, usize, Vec<Vector3<f64>>), ImageError> {
+    let invalid = |message: &str| ImageError::Hdr(message.to_string());
+    if !bytes.starts_with(b"#?") {
+        return Err(invalid("not a Radiance HDR file"));
+    }
+
+    // The header ends with an empty line, followed by the resolution
+    let mut rest = bytes;
+    let mut next_line = || -> Result<&str, ImageError> {
+        let end = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| invalid("truncated header"))?;
+        let line = std::str::from_utf8(&rest[..end]).map_err(|_| invalid("invalid header"))?;
+        rest = &rest[end + 1..];
+        Ok(line)
+    };
+
+    loop {
+        let line = next_line()?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=")
+            && format != "32-bit_rle_rgbe"
+        {
+            return Err(ImageError::Hdr(format!("unsupported format {format}")));
+        }
+    }
+
+    let resolution = next_line()?;
+    let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => (
+            height
+                .parse::<usize>()
+                .map_err(|_| invalid("invalid height"))?,
+            width
+                .parse::<usize>()
+                .map_err(|_| invalid("invalid width"))?,
+        ),
+        _ => {
+            return Err(ImageError::Hdr(format!(
+                "unsupported orientation \"{resolution}\""
+            )));
+        }
+    };
+    let size = width
+        .checked_mul(height)
+        .filter(|&size| size > 0 && size <= MAX_PIXELS)
+        .ok_or(ImageError::InvalidSize { width, height })?;
+
+    let mut data = rest;
+    let mut pixels = Vec::with_capacity(size);
+    let mut scanline = vec![[0u8; 4]; width];
+
+    for _ in 0..height {
+        read_scanline(&mut data, &mut scanline).map_err(ImageError::Hdr)?;
+        pixels.extend(scanline.iter().map(|&rgbe| from_rgbe(rgbe)));
+    }
+
+    Ok((width, height, pixels))
+}
+
+/// Read one scanline of RGBE pixels, either run length encoded or flat
+fn read_scanline(data: &mut &[u8], scanline: &mut [[u8; 4]]) -> Result<(), String> {
+    let truncated = || "truncated pixel data".to_string();
+    let width = scanline.len();
+
+    // Run length encoded scanlines start with 2, 2 and the width
+    let encoded = (8..0x8000).contains(&width)
+        && data.len() >= 4
+        && data[0] == 2
+        && data[1] == 2
+        && ((data[2] as usize) << 8 | data[3] as usize) == width;
+
+    if !encoded {
+        let bytes = data.get(..width * 4).ok_or_else(truncated)?;
+        for (pixel, rgbe) in scanline.iter_mut().zip(bytes.chunks_exact(4)) {
+            pixel.copy_from_slice(rgbe);
+        }
+        *data = &data[width * 4..];
+        return Ok(());
+    }
+
+    *data = &data[4..];
+    // Every component is stored separately, as runs of equal bytes and literal bytes
+    for component in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let (&count, rest) = data.split_first().ok_or_else(truncated)?;
+            *data = rest;
+
+            if count > 128 {
+                let count = count as usize - 128;
+                let (&value, rest) = data.split_first().ok_or_else(truncated)?;
+                *data = rest;
+                for pixel in scanline.get_mut(x..x + count).ok_or("invalid run")? {
+                    pixel[component] = value;
+                }
+                x += count;
+            } else {
+                let count = count as usize;
+                let values = data.get(..count).ok_or_else(truncated)?;
+                for (pixel, &value) in scanline
+                    .get_mut(x..x + count)
+                    .ok_or("invalid run")?
+                    .iter_mut()
+                    .zip(values)
+                {
+                    pixel[component] = value;
+                }
+                *data = &data[count..];
+                x += count;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Unpack a shared exponent color, the inverse of [to_rgbe]
+fn from_rgbe(rgbe: [u8; 4]) -> Vector3<f64> {
+    if rgbe[3] == 0 {
+        return Vector3::zeros();
+    }
+
+    // Mantissas are stored as fractions of 256, rounded down
+    let scale = 2f64.powi(rgbe[3] as i32 - 128 - 8);
+    Vector3::new(
+        (rgbe[0] as f64 + 0.5) * scale,
+        (rgbe[1] as f64 + 0.5) * scale,
+        (rgbe[2] as f64 + 0.5) * scale,
+    )
+}
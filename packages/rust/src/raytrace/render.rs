@@ -1,6 +1,11 @@
-use nalgebra::Vector3;
+use nalgebra::{Vector2, Vector3};
 
-use super::Config;
+use super::{
+    Aov, CHECKPOINT_MAGIC, CHECKPOINT_VERSION, CheckpointError, CheckpointReader, CheckpointWriter,
+    Config, DenoiseGuides, DenoiseSettings, ExrPrecision, Filter, ImageChannel, ImageLayer,
+    PathSample, Tile, ToneMapping, denoise, reject_outliers, write_exr, write_hdr,
+    xyz_to_linear_srgb,
+};
 
 /// Render resembles a virtual screen onto which the scene can be rendered
 /// It serves as an intermediate type, having useful methods used by the [Scene],
@@ -8,8 +13,33 @@ use super::Config;
 pub struct Render {
     pub config: Config,
     accumulated_exposure: Vec<Vector3<f64>>,
+    // Sum of the filter weights of all samples splatted into each pixel
+    accumulated_weight: Vec<f64>,
+    filter: Filter,
     // Track the count of iterations to properly calculate exposure
     samples: usize,
+    // Auxiliary buffers, only allocated when enabled in the config
+    aovs: Option<AovBuffers>,
+    // Statistics of the samples taken inside of each pixel, used to estimate the noise
+    stats: Vec<PixelStats>,
+}
+
+/// Running sums of a pixel's sample luminance, holding the first and second moments
+#[derive(Clone, Copy, Debug, Default)]
+struct PixelStats {
+    samples: usize,
+    sum: f64,
+    sum_squares: f64,
+}
+
+struct AovBuffers {
+    // Filtered layers, indexed by [Aov]. The object ID layer is left empty,
+    // since averaging IDs makes no sense
+    layers: Vec<Vec<Vector3<f64>>>,
+    // ID of the object seen by the sample closest to the pixel center
+    object_id: Vec<f64>,
+    // Squared distance from that sample to the pixel center
+    object_distance: Vec<f64>,
 }
 
 impl Render {
@@ -21,21 +51,93 @@ impl Render {
             pixels.push(Vector3::new(0., 0., 0.));
         }
 
+        let size = config.width * config.height;
+        let aovs = config.aovs.then(|| AovBuffers {
+            layers: Aov::ALL
+                .iter()
+                .map(|&aov| match aov {
+                    Aov::ObjectId => Vec::new(),
+                    _ => vec![Vector3::zeros(); size],
+                })
+                .collect(),
+            object_id: vec![-1.; size],
+            object_distance: vec![f64::INFINITY; size],
+        });
+
         Render {
+            aovs,
+            stats: vec![PixelStats::default(); size],
+            filter: config.filter(),
+            accumulated_weight: vec![0.; config.width * config.height],
             config,
             accumulated_exposure: pixels,
             samples: 0,
         }
     }
 
-    /// Add exposure to pixel at the specified coordinates
-    pub fn add_exposure(&mut self, x: usize, y: usize, color: Vector3<f64>) {
-        self.accumulated_exposure[y * self.config.width + x] += color
+    /// Add a sample taken at the specified film position. The color is linear
+    /// sRGB, or CIE XYZ with [Config::spectral] enabled.
+    ///
+    /// Film coordinates are measured in pixels, so the center of pixel (x, y)
+    /// is at (x + 0.5, y + 0.5). The sample is splatted into every pixel
+    /// covered by the reconstruction filter, weighted accordingly.
+    pub fn add_sample(&mut self, film: Vector2<f64>, color: Vector3<f64>) {
+        // Noise statistics are tracked in the pixel the sample was taken in
+        let x = film.x.floor() as usize;
+        let y = film.y.floor() as usize;
+        if self.config.region().contains(x, y) {
+            let luminance = luminance(self.to_rgb(color));
+            let stats = &mut self.stats[y * self.config.width + x];
+
+            stats.samples += 1;
+            stats.sum += luminance;
+            stats.sum_squares += luminance * luminance;
+        }
+
+        let exposure = &mut self.accumulated_exposure;
+        let weights = &mut self.accumulated_weight;
+
+        splat(&self.config, self.filter, film, |index, weight, _| {
+            exposure[index] += color * weight;
+            weights[index] += weight;
+        });
+    }
+    /// Add a whole path sample at the specified film position. Works like
+    /// [Render::add_sample], but also fills in the [Aov]s, when they are enabled
+    pub fn add_path(&mut self, film: Vector2<f64>, sample: &PathSample) {
+        self.add_sample(film, sample.radiance());
+
+        let Some(aovs) = &mut self.aovs else {
+            return;
+        };
+
+        splat(
+            &self.config,
+            self.filter,
+            film,
+            |index, weight, distance| {
+                for aov in Aov::ALL {
+                    if aov == Aov::ObjectId {
+                        if distance < aovs.object_distance[index] {
+                            aovs.object_distance[index] = distance;
+                            aovs.object_id[index] = sample.aov(aov).x;
+                        }
+                    } else {
+                        aovs.layers[aov as usize][index] += sample.aov(aov) * weight;
+                    }
+                }
+            },
+        );
     }
-    /// Get the raw value of a pixel at the specified coordinates
+    /// Get the raw value of a pixel at the specified coordinates, in
+    /// CIE XYZ with [Config::spectral] enabled
     pub fn get_pixel(&self, x: usize, y: usize) -> Vector3<f64> {
         self.accumulated_exposure[y * self.config.width + x]
     }
+    /// Get the sum of filter weights accumulated in a pixel
+    pub fn get_weight(&self, x: usize, y: usize) -> f64 {
+        self.accumulated_weight[y * self.config.width + x]
+    }
 
     /// Increment the sample count by one.
     ///
@@ -43,23 +145,463 @@ impl Render {
     pub fn inc_sample_count(&mut self) {
         self.samples += 1;
     }
+    /// Number of full iterations rendered so far
+    pub fn sample_count(&self) -> usize {
+        self.samples
+    }
+
+    /// Number of samples taken inside of a pixel
+    pub fn pixel_samples(&self, x: usize, y: usize) -> usize {
+        self.stats[y * self.config.width + x].samples
+    }
+
+    /// Estimated relative error of a pixel: the standard error of the mean
+    /// luminance, divided by the mean itself. Very dark pixels are divided by
+    /// a small constant instead, since their noise is hardly visible
+    pub fn pixel_error(&self, x: usize, y: usize) -> f64 {
+        let stats = self.stats[y * self.config.width + x];
+        if stats.samples < 2 {
+            return f64::INFINITY;
+        }
+
+        let n = stats.samples as f64;
+        let mean = stats.sum / n;
+        // Unbiased sample variance from the first two moments
+        let variance = ((stats.sum_squares - n * mean * mean) / (n - 1.)).max(0.);
+
+        (variance / n).sqrt() / mean.max(0.1)
+    }
+
+    /// Whether adaptive sampling is enabled, and the pixel has converged
+    pub fn is_pixel_converged(&self, x: usize, y: usize) -> bool {
+        self.config.adaptive_threshold > 0.
+            && self.pixel_samples(x, y) >= self.config.adaptive_min_samples
+            && self.pixel_error(x, y) < self.config.adaptive_threshold
+    }
+
+    /// How far along the render is, from 0 to 1.
+    ///
+    /// Converged pixels count as finished, others by how many of the
+    /// [Config::samples_per_pixel] they received. Only pixels inside
+    /// of the crop rectangle are taken into account
+    pub fn convergence(&self) -> f64 {
+        let target = self.config.samples_per_pixel.max(1) as f64;
+        let region = self.config.region();
+        let mut total = 0.;
+
+        if region.area() == 0 {
+            return 1.;
+        }
+
+        for y in region.y..region.y + region.height {
+            for x in region.x..region.x + region.width {
+                total += if self.is_pixel_converged(x, y) {
+                    1.
+                } else {
+                    (self.pixel_samples(x, y) as f64 / target).min(1.)
+                };
+            }
+        }
+
+        total / region.area() as f64
+    }
+
+    /// Throw away everything accumulated inside of the region, leaving
+    /// the rest of the image untouched
+    pub fn clear_region(&mut self, region: Tile) {
+        let width = self.config.width;
+
+        for y in region.y..(region.y + region.height).min(self.config.height) {
+            for x in region.x..(region.x + region.width).min(width) {
+                let index = y * width + x;
+
+                self.accumulated_exposure[index] = Vector3::zeros();
+                self.accumulated_weight[index] = 0.;
+                self.stats[index] = PixelStats::default();
+
+                if let Some(aovs) = &mut self.aovs {
+                    for layer in aovs.layers.iter_mut().filter(|l| !l.is_empty()) {
+                        layer[index] = Vector3::zeros();
+                    }
+                    aovs.object_id[index] = -1.;
+                    aovs.object_distance[index] = f64::INFINITY;
+                }
+            }
+        }
+    }
+
+    /// Get the averaged (filtered) linear color of a pixel
+    pub fn get_pixel_averaged(&self, x: usize, y: usize) -> Vector3<f64> {
+        let weight = self.get_weight(x, y);
+
+        // Pixels which didn't receive any samples yet are black
+        if weight <= 0. {
+            return Vector3::zeros();
+        }
+
+        self.to_rgb(self.get_pixel(x, y) / weight)
+    }
+
+    /// Convert an accumulated color to linear sRGB
+    fn to_rgb(&self, color: Vector3<f64>) -> Vector3<f64> {
+        if self.config.spectral {
+            xyz_to_linear_srgb(color)
+        } else {
+            color
+        }
+    }
+
+    /// Get value of pixel at specified coordinates, with adjusted exposure
+    /// and tone mapping applied. The result is sRGB encoded, from 0 to 1
+    pub fn get_pixel_tonemapped(
+        &self,
+        x: usize,
+        y: usize,
+        tone_mapping: &ToneMapping,
+    ) -> Vector3<f64> {
+        tone_mapping.apply(self.get_pixel_averaged(x, y))
+    }
+
+    /// Convert the render into an 8-bit RGBA image, row by row
+    pub fn to_rgba8(&self, tone_mapping: &ToneMapping) -> Vec<u8> {
+        tone_mapping.to_rgba8(&self.averaged())
+    }
+
+    /// Averaged linear colors of all pixels with the noise filtered out.
+    ///
+    /// Works best with [Config::aovs] enabled, since the albedo, normal and depth
+    /// buffers tell the denoiser where the edges are
+    pub fn denoised(&self, settings: &DenoiseSettings) -> Vec<Vector3<f64>> {
+        let albedo = self.get_aov(Aov::Albedo);
+        let normal = self.get_aov(Aov::Normal);
+        let depth = self.get_aov(Aov::Depth);
+
+        denoise(
+            self.config.width,
+            self.config.height,
+            &self.averaged(),
+            DenoiseGuides {
+                albedo: albedo.as_deref(),
+                normal: normal.as_deref(),
+                depth: depth.as_deref(),
+            },
+            settings,
+        )
+    }
+
+    /// Averaged values of an [Aov] for all pixels, row by row.
+    ///
+    /// Returns [None] if the AOVs weren't enabled in the config
+    pub fn get_aov(&self, aov: Aov) -> Option<Vec<Vector3<f64>>> {
+        let aovs = self.aovs.as_ref()?;
+
+        if aov == Aov::ObjectId {
+            return Some(
+                aovs.object_id
+                    .iter()
+                    .map(|&id| Vector3::new(id, 0., 0.))
+                    .collect(),
+            );
+        }
+
+        // Light is accumulated like the image itself
+        let is_light = matches!(aov, Aov::Direct | Aov::Indirect);
+        let layer = &aovs.layers[aov as usize];
+        Some(
+            layer
+                .iter()
+                .zip(&self.accumulated_weight)
+                .map(|(value, &weight)| {
+                    if weight <= 0. {
+                        Vector3::zeros()
+                    } else if is_light {
+                        self.to_rgb(value / weight)
+                    } else {
+                        value / weight
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Add everything accumulated by another render of the same scene to this one,
+    /// as if all of its samples had been taken here. Lets several machines with
+    /// different random seeds work on the same image.
+    ///
+    /// Fails, leaving this render as it was, if the resolution differs, or only
+    /// one of the renders has AOVs
+    pub fn merge(&mut self, other: &Render) -> Result<(), CheckpointError> {
+        let resolution = (other.config.width, other.config.height);
+        if resolution != (self.config.width, self.config.height) {
+            return Err(CheckpointError::ResolutionMismatch {
+                expected: (self.config.width, self.config.height),
+                found: resolution,
+            });
+        }
+        if self.aovs.is_some() != other.aovs.is_some() {
+            return Err(CheckpointError::SceneMismatch);
+        }
+
+        self.samples += other.samples;
+
+        for (exposure, other) in self
+            .accumulated_exposure
+            .iter_mut()
+            .zip(&other.accumulated_exposure)
+        {
+            *exposure += other;
+        }
+        for (weight, other) in self
+            .accumulated_weight
+            .iter_mut()
+            .zip(&other.accumulated_weight)
+        {
+            *weight += other;
+        }
+        for (stats, other) in self.stats.iter_mut().zip(&other.stats) {
+            stats.samples += other.samples;
+            stats.sum += other.sum;
+            stats.sum_squares += other.sum_squares;
+        }
+
+        if let (Some(aovs), Some(other)) = (&mut self.aovs, &other.aovs) {
+            for (layer, other) in aovs.layers.iter_mut().zip(&other.layers) {
+                for (value, other) in layer.iter_mut().zip(other) {
+                    *value += other;
+                }
+            }
+
+            // Keep the ID of whichever sample landed closer to the pixel center
+            for (index, &distance) in other.object_distance.iter().enumerate() {
+                if distance < aovs.object_distance[index] {
+                    aovs.object_distance[index] = distance;
+                    aovs.object_id[index] = other.object_id[index];
+                }
+            }
+        }
+
+        Ok(())
+    }
 
-    /// Get value of pixel at specified coordinates, with adjusted exporuse
-    /// and corrected gamma
-    pub fn get_pixel_corrected(&self, x: usize, y: usize) -> Vector3<f64> {
-        let color = self.get_pixel(x, y);
+    /// Serialize everything accumulated so far, so that the render can be
+    /// continued later with [Render::from_checkpoint].
+    ///
+    /// The `scene_hash` identifies the scene the render belongs to, see [super::Scene::hash]
+    pub fn to_checkpoint(&self, scene_hash: u64) -> Vec<u8> {
+        let size = self.config.width * self.config.height;
+        let mut writer = CheckpointWriter::new(32 + size * 56);
+
+        writer.bytes.extend_from_slice(CHECKPOINT_MAGIC);
+        writer.u32(CHECKPOINT_VERSION);
+        writer.u64(scene_hash);
+        writer.u32(self.config.width as u32);
+        writer.u32(self.config.height as u32);
+        writer.u64(self.samples as u64);
+        writer.u32(self.aovs.is_some() as u32);
 
-        // Compute the averaged color value by dividing the accumulated exposure
-        // by the total number of samples, giving a value from 0 to 1
-        let mut r = color.x / self.samples as f64;
-        let mut g = color.y / self.samples as f64;
-        let mut b = color.z / self.samples as f64;
+        for &exposure in &self.accumulated_exposure {
+            writer.vector(exposure);
+        }
+        for &weight in &self.accumulated_weight {
+            writer.f64(weight);
+        }
+        for stats in &self.stats {
+            writer.u64(stats.samples as u64);
+            writer.f64(stats.sum);
+            writer.f64(stats.sum_squares);
+        }
 
-        // Apply gamma correction
-        r = r.powf(1.0 / 2.2);
-        g = g.powf(1.0 / 2.2);
-        b = b.powf(1.0 / 2.2);
+        if let Some(aovs) = &self.aovs {
+            for layer in &aovs.layers {
+                for &value in layer {
+                    writer.vector(value);
+                }
+            }
+            for (&id, &distance) in aovs.object_id.iter().zip(&aovs.object_distance) {
+                writer.f64(id);
+                writer.f64(distance);
+            }
+        }
 
-        Vector3::new(r, g, b)
+        writer.bytes
     }
+
+    /// Restore a render saved by [Render::to_checkpoint], so that sampling can
+    /// continue where it left off.
+    ///
+    /// Fails if the checkpoint was taken from a scene with a different `scene_hash`,
+    /// or its resolution doesn't match the config
+    pub fn from_checkpoint(
+        config: Config,
+        checkpoint: &[u8],
+        scene_hash: u64,
+    ) -> Result<Self, CheckpointError> {
+        let mut reader = CheckpointReader::new(checkpoint);
+
+        reader.magic()?;
+        let version = reader.u32()?;
+        if version != CHECKPOINT_VERSION {
+            return Err(CheckpointError::UnsupportedVersion(version));
+        }
+        if reader.u64()? != scene_hash {
+            return Err(CheckpointError::SceneMismatch);
+        }
+        let width = reader.u32()? as usize;
+        let height = reader.u32()? as usize;
+        if (width, height) != (config.width, config.height) {
+            return Err(CheckpointError::ResolutionMismatch {
+                expected: (config.width, config.height),
+                found: (width, height),
+            });
+        }
+        let samples = reader.u64()? as usize;
+        if (reader.u32()? != 0) != config.aovs {
+            return Err(CheckpointError::SceneMismatch);
+        }
+
+        let mut render = Render::new(config);
+        render.samples = samples;
+
+        for exposure in &mut render.accumulated_exposure {
+            *exposure = reader.vector()?;
+        }
+        for weight in &mut render.accumulated_weight {
+            *weight = reader.f64()?;
+        }
+        for stats in &mut render.stats {
+            stats.samples = reader.u64()? as usize;
+            stats.sum = reader.f64()?;
+            stats.sum_squares = reader.f64()?;
+        }
+
+        if let Some(aovs) = &mut render.aovs {
+            for layer in &mut aovs.layers {
+                for value in layer {
+                    *value = reader.vector()?;
+                }
+            }
+            for (id, distance) in aovs.object_id.iter_mut().zip(&mut aovs.object_distance) {
+                *id = reader.f64()?;
+                *distance = reader.f64()?;
+            }
+        }
+
+        reader.finish()?;
+        Ok(render)
+    }
+
+    /// Averaged linear colors of all pixels, row by row.
+    ///
+    /// Applies the outlier rejection, if it's enabled in the config
+    pub fn averaged(&self) -> Vec<Vector3<f64>> {
+        let mut pixels = Vec::with_capacity(self.config.width * self.config.height);
+
+        for y in 0..self.config.height {
+            for x in 0..self.config.width {
+                pixels.push(self.get_pixel_averaged(x, y));
+            }
+        }
+
+        if self.config.outlier_rejection > 0. {
+            pixels = reject_outliers(
+                self.config.width,
+                self.config.height,
+                &pixels,
+                self.config.outlier_rejection,
+            );
+        }
+
+        pixels
+    }
+
+    /// All layers of the render, ready to be written into a multi-channel image
+    pub fn layers(&self) -> Vec<ImageLayer> {
+        let pixels = self.averaged();
+        let channel = |name: &str, component: usize| ImageChannel {
+            name: name.to_string(),
+            samples: pixels.iter().map(|p| p[component] as f32).collect(),
+        };
+
+        let mut layers = vec![ImageLayer {
+            name: String::new(),
+            channels: vec![channel("R", 0), channel("G", 1), channel("B", 2)],
+        }];
+
+        for aov in Aov::ALL {
+            let Some(pixels) = self.get_aov(aov) else {
+                break;
+            };
+
+            layers.push(ImageLayer {
+                name: aov.name().to_string(),
+                channels: aov
+                    .channels()
+                    .iter()
+                    .enumerate()
+                    .map(|(component, name)| ImageChannel {
+                        name: name.to_string(),
+                        samples: pixels.iter().map(|p| p[component] as f32).collect(),
+                    })
+                    .collect(),
+            });
+        }
+
+        layers
+    }
+
+    /// Encode the raw (not tone mapped) render as an OpenEXR file
+    pub fn to_exr(&self, precision: ExrPrecision) -> exr::error::Result<Vec<u8>> {
+        write_exr(
+            self.config.width,
+            self.config.height,
+            &self.layers(),
+            precision,
+        )
+    }
+
+    /// Encode the raw (not tone mapped) render as a Radiance HDR file
+    pub fn to_hdr(&self) -> Vec<u8> {
+        write_hdr(self.config.width, self.config.height, &self.averaged())
+    }
+}
+
+/// Call `f` for every pixel covered by the filter centered at the film position,
+/// with the pixel index, filter weight and squared distance to the pixel center.
+///
+/// Film coordinates are measured in pixels, so the center of pixel (x, y)
+/// is at (x + 0.5, y + 0.5). Only pixels inside of [Config::region] are visited
+fn splat(config: &Config, filter: Filter, film: Vector2<f64>, mut f: impl FnMut(usize, f64, f64)) {
+    let radius = filter.radius;
+
+    // Range of pixels whose centers lie within the filter radius,
+    // pixels outside of the crop rectangle never receive any samples
+    let region = config.region();
+    let x0 = (film.x - 0.5 - radius).ceil().max(region.x as f64) as usize;
+    let y0 = (film.y - 0.5 - radius).ceil().max(region.y as f64) as usize;
+    let x1 = ((film.x - 0.5 + radius).floor() as isize).min((region.x + region.width) as isize - 1);
+    let y1 =
+        ((film.y - 0.5 + radius).floor() as isize).min((region.y + region.height) as isize - 1);
+
+    for y in y0 as isize..=y1 {
+        for x in x0 as isize..=x1 {
+            let dx = x as f64 + 0.5 - film.x;
+            let dy = y as f64 + 0.5 - film.y;
+
+            let weight = filter.evaluate(dx, dy);
+            if weight == 0. {
+                continue;
+            }
+
+            f(
+                y as usize * config.width + x as usize,
+                weight,
+                dx * dx + dy * dy,
+            );
+        }
+    }
+}
+
+/// Relative luminance of a linear sRGB color
+pub fn luminance(color: Vector3<f64>) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
 }
@@ -0,0 +1,68 @@
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+use nalgebra::{Vector2, Vector3};
+
+/// Two unit vectors perpendicular to the unit vector `n`, and to each other.
+///
+/// Uses the branchless construction by Duff et al. 2017, which has no
+/// problems with any particular direction of `n`
+pub fn orthonormal_basis(n: Vector3<f64>) -> (Vector3<f64>, Vector3<f64>) {
+    let sign = 1f64.copysign(n.z);
+    let a = -1. / (sign + n.z);
+    let b = n.x * n.y * a;
+
+    (
+        Vector3::new(1. + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        Vector3::new(b, sign + n.y * n.y * a, -n.y),
+    )
+}
+
+/// Map a point of the unit square onto the unit disk, keeping the areas
+/// proportional (Shirley & Chiu 1997)
+pub fn concentric_disk(u: Vector2<f64>) -> Vector2<f64> {
+    let offset = u * 2. - Vector2::new(1., 1.);
+    if offset.x == 0. && offset.y == 0. {
+        return Vector2::zeros();
+    }
+
+    let (radius, theta) = if offset.x.abs() > offset.y.abs() {
+        (offset.x, FRAC_PI_4 * (offset.y / offset.x))
+    } else {
+        (offset.y, FRAC_PI_2 - FRAC_PI_4 * (offset.x / offset.y))
+    };
+
+    Vector2::new(theta.cos(), theta.sin()) * radius
+}
+
+/// Uniformly distributed direction on the unit sphere
+pub fn uniform_sphere(u: Vector2<f64>) -> Vector3<f64> {
+    let z = 1. - 2. * u.x;
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * PI * u.y;
+
+    Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Uniformly distributed direction inside of a cone around +Z, with the
+/// cosine of its half angle being `cos_max`. The density is
+/// `1 / (2π (1 - cos_max))` per unit solid angle
+pub fn uniform_cone(u: Vector2<f64>, cos_max: f64) -> Vector3<f64> {
+    let cos_theta = 1. - u.x * (1. - cos_max);
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * PI * u.y;
+
+    Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+/// Express a direction given relative to a frame with `n` as its Z axis in world space
+pub fn to_world(local: Vector3<f64>, n: Vector3<f64>) -> Vector3<f64> {
+    let (s, t) = orthonormal_basis(n);
+    s * local.x + t * local.y + n * local.z
+}
+
+/// Express a world space direction relative to a frame with `n` as its Z axis,
+/// the inverse of [to_world]
+pub fn to_local(world: Vector3<f64>, n: Vector3<f64>) -> Vector3<f64> {
+    let (s, t) = orthonormal_basis(n);
+    Vector3::new(world.dot(&s), world.dot(&t), world.dot(&n))
+}
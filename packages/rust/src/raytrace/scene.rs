@@ -1,6 +1,25 @@
-use nalgebra::Vector3;
+use std::fmt::Write;
 
-use super::{Camera, Config, Object, Ray, Render};
+use nalgebra::{Vector2, Vector3};
+use rand::prelude::*;
+
+use super::{
+    Camera, CheckpointError, Config, Environment, Fnv1a, Light, Medium, Object, PathSample, Ray,
+    Render, SampledSpectrum, SurfacePoint, Tile, TileScheduler, Transform, Wavelengths,
+};
+
+/// Interfaces of media a ray may pass through before giving up on it
+const MAX_CROSSINGS: usize = 64;
+
+/// What a ray reaches next, after passing through the interfaces of media
+enum Event<'a> {
+    /// Light sources hit at once, see [Scene::collide_light]
+    Light(Vec<(usize, f64, Vector3<f64>)>),
+    /// Point the light gets scattered at inside of the medium
+    Medium(Vector3<f64>, &'a Medium),
+    /// Point on the surface of an object, by index
+    Surface(Vector3<f64>, usize),
+}
 
 /// Scene is the core structure of the simulation, combining a [Camera], an
 /// output [Render], an a list of [Object]s to produce a full scene
@@ -12,24 +31,42 @@ pub struct Scene {
     pub render: Render,
     /// An array with all objects in the scene
     pub objects: Vec<Object>,
+    /// Light sources, which are sampled directly at every bounce
+    pub lights: Vec<Box<dyn Light>>,
+    /// Light arriving from far away, seen by rays which miss every object.
+    /// Without an environment they turn black
+    pub environment: Option<Environment>,
+    /// Medium filling all of the space outside of objects, like fog. The camera
+    /// has to be in it, rather than inside the medium of an [Object]
+    pub fog: Option<Medium>,
     camera: Camera,
+    // Remembers which tile to render next, see [Scene::sample_tiles]
+    tiles: TileScheduler,
 }
 
 impl Scene {
     pub fn new(config: Config, camera: Camera) -> Self {
+        let tiles = TileScheduler::new(config.region(), config.tile_size);
+
         Self {
             camera,
+            tiles,
             render: Render::new(config),
             objects: Vec::new(),
+            lights: Vec::new(),
+            environment: None,
+            fog: None,
         }
     }
 
-    fn collide_ray(&self, ray: &Ray) -> Option<(Vector3<f64>, &Object)> {
+    /// Find the closest object hit by the ray, returning the intersection
+    /// point and the index of the object
+    fn collide_ray(&self, ray: &Ray) -> Option<(Vector3<f64>, usize)> {
         let mut min_dist = f64::INFINITY;
         let mut intersected_object = None;
         let mut point = None;
 
-        for object in &self.objects {
+        for (index, object) in self.objects.iter().enumerate() {
             let intersection = object.hit(ray);
 
             if let Some(intersection) = intersection {
@@ -38,7 +75,7 @@ impl Scene {
                 // This fixes the "shadow acne" problem
                 if dist > 0.001 && dist < min_dist {
                     min_dist = dist;
-                    intersected_object = Some(object);
+                    intersected_object = Some(index);
                     point = Some(intersection);
                 }
             }
@@ -47,47 +84,635 @@ impl Scene {
         Some((point?, intersected_object?))
     }
 
-    fn trace_ray(&self, ray: &mut Ray) -> Vector3<f64> {
-        let mut ray_color = Vector3::new(1.0, 1.0, 1.0);
-        let mut incoming_light = Vector3::new(0.0, 0.0, 0.0);
+    /// Number of light sources, including the environment
+    fn light_count(&self) -> usize {
+        self.lights.len() + self.environment.is_some() as usize
+    }
 
-        for _ in 0..self.render.config.max_bounce_count {
-            let intersection = self.collide_ray(ray);
-            if let Some((point, object)) = intersection {
-                object
-                    .material
-                    .scatter(ray, point, object.shape.normal(point));
+    /// Light source by index, the environment comes after all of the [Scene::lights]
+    fn light(&self, index: usize) -> &dyn Light {
+        match self.lights.get(index) {
+            Some(light) => light.as_ref(),
+            None => self.environment.as_ref().unwrap(),
+        }
+    }
+
+    /// Find the closest lights hit by the ray, before it reaches `max_distance`.
+    /// Returns the index of every light at the same closest distance, the
+    /// distance, and the radiance it emits towards the ray origin. Lights far
+    /// away, like the sun and the sky around it, are all hit at once
+    fn collide_light(&self, ray: &Ray, max_distance: f64) -> Vec<(usize, f64, Vector3<f64>)> {
+        let mut min_dist = max_distance;
+        let mut lights = Vec::new();
+
+        for index in 0..self.light_count() {
+            let Some((dist, radiance)) = self.light(index).intersect(ray) else {
+                continue;
+            };
+            if dist < min_dist {
+                min_dist = dist;
+                lights.clear();
+            }
+            if dist <= min_dist {
+                lights.push((index, dist, radiance));
+            }
+        }
+
+        lights
+    }
+
+    /// Medium a ray is in, either the fog for [None], or the medium of the
+    /// object by index, along with where the object is placed
+    fn medium(&self, inside: Option<usize>) -> Option<(&Medium, Option<&Transform>)> {
+        match inside {
+            Some(index) => {
+                let object = &self.objects[index];
+                Some((object.medium.as_ref()?, Some(&object.transform)))
+            }
+            None => self.fog.as_ref().map(|fog| (fog, None)),
+        }
+    }
+
+    /// Medium a ray is in after leaving the surface of the object by index
+    /// along `direction`, having been `inside` before
+    fn enter(
+        &self,
+        inside: Option<usize>,
+        index: usize,
+        direction: Vector3<f64>,
+        normal: Vector3<f64>,
+    ) -> Option<usize> {
+        if self.objects[index].medium.is_none() {
+            return inside;
+        }
+
+        (direction.dot(&normal) < 0.).then_some(index)
+    }
+
+    /// Fraction of the light making it from `point` along the unit `direction`,
+    /// up to the `distance`, starting out `inside` a medium. Objects block all of
+    /// it, only the interfaces of media let it through
+    fn transmittance(
+        &self,
+        point: Vector3<f64>,
+        direction: Vector3<f64>,
+        distance: f64,
+        mut inside: Option<usize>,
+        wavelengths: &Wavelengths,
+        rng: &mut impl Rng,
+    ) -> SampledSpectrum {
+        let mut ray = Ray {
+            origin: point,
+            direction,
+        };
+        let mut remaining = distance;
+        let mut transmittance = wavelengths.constant(1.);
+
+        for _ in 0..MAX_CROSSINGS {
+            // Stop a bit short of the light, so that it doesn't shadow itself
+            let hit = self.collide_ray(&ray).filter(|(hit, _)| {
+                (hit - ray.origin).magnitude() < remaining * (1. - 1e-6) - 0.001
+            });
+            let segment = hit.map_or(remaining, |(hit, _)| (hit - ray.origin).magnitude());
+
+            if let Some((medium, frame)) = self.medium(inside) {
+                let passed = medium.transmittance(&ray, segment, frame, wavelengths, rng);
+                transmittance.component_mul_assign(&passed);
+            }
+
+            let Some((hit, index)) = hit else {
+                return transmittance;
+            };
+            let object = &self.objects[index];
+            if !object.material.is_interface() || transmittance == SampledSpectrum::zeros() {
+                break;
+            }
+
+            // Carry on from just past the interface, so that it isn't hit again
+            let normal = object.normal(hit);
+            inside = self.enter(inside, index, direction, normal);
+            ray.origin = hit + normal * (1e-4 * direction.dot(&normal).signum());
+            remaining -= segment;
+        }
+
+        SampledSpectrum::zeros()
+    }
 
-                let emitted_light = object.emission_color * object.emission_strength;
+    /// Follow the ray through media and the interfaces between them, until it
+    /// gets scattered, or reaches a light or an object. The medium the ray is
+    /// `inside` changes as it crosses interfaces, and `ray_color` takes in what
+    /// the media do to the light
+    fn next_event(
+        &self,
+        ray: &mut Ray,
+        inside: &mut Option<usize>,
+        ray_color: &mut SampledSpectrum,
+        wavelengths: &Wavelengths,
+        rng: &mut impl Rng,
+    ) -> Option<Event<'_>> {
+        ray.direction = ray.direction.normalize();
 
-                incoming_light += emitted_light.component_mul(&ray_color);
-                ray_color.component_mul_assign(&object.color);
+        for _ in 0..MAX_CROSSINGS {
+            let hit = self.collide_ray(ray);
+            let hit_distance =
+                hit.map_or(f64::INFINITY, |(point, _)| (point - ray.origin).magnitude());
+            let lights = self.collide_light(ray, hit_distance);
+
+            if let Some((medium, frame)) = self.medium(*inside) {
+                let reached = lights
+                    .first()
+                    .map_or(hit_distance, |&(_, distance, _)| distance);
+                let (scattered, weight) =
+                    medium.sample_distance(ray, reached, frame, wavelengths, rng);
+                ray_color.component_mul_assign(&weight);
+
+                if let Some(distance) = scattered {
+                    return Some(Event::Medium(ray.origin + ray.direction * distance, medium));
+                }
+            }
+
+            if !lights.is_empty() {
+                return Some(Event::Light(lights));
+            }
+
+            let (point, index) = hit?;
+            let object = &self.objects[index];
+            if !object.material.is_interface() {
+                return Some(Event::Surface(point, index));
+            }
+
+            let normal = object.normal(point);
+            *inside = self.enter(*inside, index, ray.direction, normal);
+            ray.origin = point + normal * (1e-4 * ray.direction.dot(&normal).signum());
+        }
+
+        None
+    }
+
+    /// Light arriving at `point` straight from one randomly picked light, scattered
+    /// by `scattering`, which gives the factor and the density of scattering light
+    /// arriving from a direction, or [None] where it can't. Shadow rays start
+    /// where `start` says for their direction, inside of the medium it says.
+    /// Returns zero if the light is blocked
+    fn sample_direct(
+        &self,
+        point: Vector3<f64>,
+        start: impl Fn(Vector3<f64>) -> (Vector3<f64>, Option<usize>),
+        wavelengths: &Wavelengths,
+        rng: &mut impl Rng,
+        scattering: impl Fn(Vector3<f64>) -> Option<(SampledSpectrum, f64)>,
+    ) -> SampledSpectrum {
+        let count = self.light_count();
+        if count == 0 {
+            return SampledSpectrum::zeros();
+        }
+
+        let light = self.light(rng.random_range(0..count));
+        let pick_pdf = 1. / count as f64;
+        let u = Vector2::new(rng.random::<f64>(), rng.random::<f64>());
+        let Some(sample) = light.sample_li(point, u) else {
+            return SampledSpectrum::zeros();
+        };
+        let Some((factor, scatter_pdf)) = scattering(sample.direction) else {
+            return SampledSpectrum::zeros();
+        };
+        if factor == SampledSpectrum::zeros() {
+            return SampledSpectrum::zeros();
+        }
+
+        let (origin, inside) = start(sample.direction);
+        let transmittance = self.transmittance(
+            origin,
+            sample.direction,
+            sample.distance,
+            inside,
+            wavelengths,
+            rng,
+        );
+
+        // Delta lights can't be hit by scattered rays, so they take all the weight
+        let light_pdf = sample.pdf * pick_pdf;
+        let weight = if light.is_delta() {
+            1.
+        } else {
+            power_heuristic(light_pdf, scatter_pdf)
+        };
+
+        factor
+            .component_mul(&transmittance)
+            .component_mul(&wavelengths.illuminant(sample.radiance))
+            * (weight / light_pdf)
+    }
+
+    /// Light arriving at a point on the surface of the object by index straight
+    /// from one randomly picked light, scattered towards `outgoing`, where the
+    /// ray arrived from `inside` a medium
+    fn sample_light(
+        &self,
+        index: usize,
+        surface: &SurfacePoint,
+        outgoing: Vector3<f64>,
+        inside: Option<usize>,
+        wavelengths: &Wavelengths,
+        rng: &mut impl Rng,
+    ) -> SampledSpectrum {
+        let object = &self.objects[index];
+        let (normal, coordinates) = (surface.shading_normal, &surface.coordinates);
+
+        // Light passing through the surface counts too, if the material lets it.
+        // The shadow ray starts a bit off of the surface on the side it leaves
+        // from, so that it can't slip through the object it starts on
+        let start = |direction: Vector3<f64>| {
+            let side = direction.dot(&surface.normal).signum();
+            (
+                surface.point + surface.normal * (1e-4 * side),
+                self.enter(inside, index, direction, surface.normal),
+            )
+        };
+        let scattering = |direction: Vector3<f64>| {
+            if direction.dot(&surface.normal) == 0. || !surface.is_consistent(outgoing, direction) {
+                return None;
+            }
+
+            let material = &object.material;
+            let reflectance = material.eval(outgoing, direction, normal, coordinates, wavelengths);
+            let tint = if reflectance == SampledSpectrum::zeros() {
+                reflectance
             } else {
+                wavelengths.reflectance(object.color.evaluate(coordinates))
+            };
+            Some((
+                reflectance.component_mul(&tint) * direction.dot(&normal).abs(),
+                material.pdf(outgoing, direction, normal, coordinates),
+            ))
+        };
+
+        self.sample_direct(surface.point, start, wavelengths, rng, scattering)
+    }
+
+    fn trace_ray(&self, ray: &mut Ray, mut wavelengths: Wavelengths) -> PathSample {
+        let mut rng = rand::rng();
+        let mut sample = PathSample::default();
+        let mut direct = SampledSpectrum::zeros();
+        let mut indirect = SampledSpectrum::zeros();
+        let mut ray_color = wavelengths.constant(1.);
+        // Density of the direction picked by the last scatter, for weighting light hits
+        let mut scatter_pdf = 0.;
+
+        // Medium the path is in, see [Scene::medium]
+        let mut inside = None;
+
+        for bounce in 0..self.render.config.max_bounce_count {
+            let origin = ray.origin;
+            let event = self.next_event(ray, &mut inside, &mut ray_color, &wavelengths, &mut rng);
+
+            // Emitters seen by the camera, or lighting the first surface directly
+            let mut add_light = |light: SampledSpectrum| {
+                if bounce <= 1 {
+                    direct += light;
+                } else {
+                    indirect += light;
+                }
+            };
+
+            let (point, index) = match event {
+                None => break,
+                // Lights found by chance were also sampled directly at the previous
+                // bounce, so both ways of finding them are weighted against each other
+                Some(Event::Light(lights)) => {
+                    // Every light could have been sampled directly on its own
+                    for (index, _, radiance) in lights {
+                        let weight = if bounce == 0 {
+                            1.
+                        } else {
+                            let light_pdf = self.light(index).pdf_li(origin, ray.direction)
+                                / self.light_count() as f64;
+                            power_heuristic(scatter_pdf, light_pdf)
+                        };
+
+                        add_light(
+                            wavelengths.illuminant(radiance).component_mul(&ray_color) * weight,
+                        );
+                    }
+                    break;
+                }
+                Some(Event::Medium(point, medium)) => {
+                    let direction = ray.direction;
+                    if bounce == 0 {
+                        sample.albedo = medium.albedo();
+                        sample.position = point;
+                        sample.depth = (point - origin).magnitude();
+                    }
+
+                    // Light scattered towards the camera by the medium, which
+                    // already took the scattering coefficient into account
+                    if bounce + 1 < self.render.config.max_bounce_count {
+                        let light = self.sample_direct(
+                            point,
+                            |_| (point, inside),
+                            &wavelengths,
+                            &mut rng,
+                            |incoming| {
+                                let phase = medium.phase(direction, incoming);
+                                Some((wavelengths.constant(phase), phase))
+                            },
+                        );
+                        let light = light.component_mul(&ray_color);
+                        if bounce == 0 {
+                            direct += light;
+                        } else {
+                            indirect += light;
+                        }
+                    }
+
+                    let u = Vector2::new(rng.random::<f64>(), rng.random::<f64>());
+                    ray.origin = point;
+                    ray.direction = medium.sample_phase(direction, u);
+                    scatter_pdf = medium.phase(direction, ray.direction);
+                    continue;
+                }
+                Some(Event::Surface(point, index)) => (point, index),
+            };
+            let object = &self.objects[index];
+            let surface = object.surface(point);
+            let (normal, coordinates) = (surface.shading_normal, surface.coordinates);
+            let outgoing = -ray.direction.normalize();
+
+            // Remember what the camera sees first
+            if bounce == 0 {
+                sample.albedo = object
+                    .color
+                    .evaluate(&coordinates)
+                    .component_mul(&object.material.albedo(&coordinates));
+                sample.normal = normal;
+                sample.position = point;
+                sample.depth = (point - origin).magnitude();
+                sample.object = Some(index);
+            }
+
+            if object.is_emissive() {
+                add_light(object.emission(&wavelengths).component_mul(&ray_color));
+            }
+
+            // Light reaching this surface straight from a light source belongs to the next bounce
+            if bounce + 1 < self.render.config.max_bounce_count {
+                let light =
+                    self.sample_light(index, &surface, outgoing, inside, &wavelengths, &mut rng);
+                let light = light.component_mul(&ray_color);
+                if bounce == 0 {
+                    direct += light;
+                } else {
+                    indirect += light;
+                }
+            }
+
+            let weight =
+                object
+                    .material
+                    .scatter(ray, point, normal, &coordinates, &mut wavelengths);
+            // Materials only know the shading normal, so the ray leaves from the
+            // side of the actual surface it goes to, and paths which would cross
+            // the surface where the material meant to reflect end
+            let direction = ray.direction.normalize();
+            if !surface.is_consistent(outgoing, direction) {
                 break;
             }
+            ray.origin = point + surface.normal * (1e-4 * direction.dot(&surface.normal).signum());
+            inside = self.enter(inside, index, direction, surface.normal);
+
+            scatter_pdf = if object.material.is_specular() {
+                f64::INFINITY
+            } else {
+                object
+                    .material
+                    .pdf(outgoing, ray.direction.normalize(), normal, &coordinates)
+            };
+
+            ray_color.component_mul_assign(&weight);
+            ray_color.component_mul_assign(
+                &wavelengths.reflectance(object.color.evaluate(&coordinates)),
+            );
+        }
+
+        sample.direct = wavelengths.to_color(direct);
+        sample.indirect = wavelengths.to_color(indirect);
+
+        let max = self.render.config.clamp_radiance;
+        if max > 0. {
+            sample.indirect = clamp_radiance(sample.indirect, max);
+            if !self.render.config.clamp_indirect_only {
+                sample.direct = clamp_radiance(sample.direct, max);
+            }
         }
 
-        incoming_light
+        sample
     }
 
-    fn project_pixel(&self, x: usize, y: usize) -> Ray {
+    fn project_pixel(&self, film: Vector2<f64>) -> Ray {
         self.camera.generate_ray(
-            // Convert our pixel coordinates, which go from 0 to N
+            // Convert our film coordinates, which go from 0 to N
             // to screen coordinates, which go from -1 to +1
-            1.0 - (x as f64 / self.render.config.width as f64) * 2.0,
-            1.0 - (y as f64 / self.render.config.height as f64) * 2.0,
+            1.0 - (film.x / self.render.config.width as f64) * 2.0,
+            1.0 - (film.y / self.render.config.height as f64) * 2.0,
         )
     }
 
+    /// Trace one sample for every pixel of the region
+    fn sample_region(&mut self, region: Tile) {
+        let mut rng = rand::rng();
+
+        for x in region.x..region.x + region.width {
+            for y in region.y..region.y + region.height {
+                // With adaptive sampling, converged pixels don't need any more samples
+                if self.render.is_pixel_converged(x, y) {
+                    continue;
+                }
+
+                // Pick a random point within the pixel, the reconstruction
+                // filter of the render takes care of anti-aliasing
+                let film = Vector2::new(
+                    x as f64 + rng.random::<f64>(),
+                    y as f64 + rng.random::<f64>(),
+                );
+
+                let wavelengths = if self.render.config.spectral {
+                    Wavelengths::sample(rng.random())
+                } else {
+                    Wavelengths::Rgb
+                };
+
+                let mut ray = self.project_pixel(film);
+                let sample = self.trace_ray(&mut ray, wavelengths);
+                self.render.add_path(film, &sample);
+            }
+        }
+    }
+
     /// Progress a sample of one frame
     pub fn sample(&mut self) {
-        for x in 0..self.render.config.width {
-            for y in 0..self.render.config.height {
-                let mut ray = self.project_pixel(x, y);
-                self.render.add_exposure(x, y, self.trace_ray(&mut ray));
+        self.sample_region(self.render.config.region());
+
+        self.render.inc_sample_count();
+    }
+
+    /// Render the next `count` tiles of the frame, picking up where the previous
+    /// call left off. Lets the caller stay responsive while rendering big images.
+    ///
+    /// Returns the regions of the render which have changed. They are slightly
+    /// bigger than the tiles, since the reconstruction filter spreads samples
+    /// over the neighbouring pixels
+    pub fn sample_tiles(&mut self, count: usize) -> Vec<Tile> {
+        let mut remaining = count;
+        self.sample_while(|| {
+            let keep_going = remaining > 0;
+            remaining = remaining.saturating_sub(1);
+            keep_going
+        })
+    }
+
+    /// Render tiles one by one, for as long as `keep_going` returns true.
+    /// Returns the changed regions, like [Scene::sample_tiles]
+    pub fn sample_while(&mut self, mut keep_going: impl FnMut() -> bool) -> Vec<Tile> {
+        let width = self.render.config.width;
+        let height = self.render.config.height;
+        let margin = self.render.config.filter_radius.ceil() as usize;
+        let mut changed = Vec::new();
+
+        while keep_going() {
+            let Some((tile, pass_finished)) = self.tiles.next_tile() else {
+                break;
+            };
+
+            self.sample_region(tile);
+            if pass_finished {
+                self.render.inc_sample_count();
             }
+
+            changed.push(tile.expand(margin, width, height));
         }
 
-        self.render.inc_sample_count();
+        changed
+    }
+
+    /// Render tiles until the time budget runs out. At least one tile is
+    /// always rendered. Returns the changed regions, like [Scene::sample_tiles]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn sample_for(&mut self, budget: std::time::Duration) -> Vec<Tile> {
+        let start = std::time::Instant::now();
+        let mut first = true;
+
+        self.sample_while(|| {
+            let keep_going = first || start.elapsed() < budget;
+            first = false;
+            keep_going
+        })
+    }
+
+    /// Only render pixels inside of the `crop` rectangle from now on, or the
+    /// whole screen for `None`.
+    ///
+    /// With `keep_previous`, pixels outside of the new region keep what has been
+    /// rendered so far, which is useful to re-render a detail of a finished image.
+    /// Otherwise the whole render starts over
+    pub fn set_crop(&mut self, crop: Option<Tile>, keep_previous: bool) {
+        self.render.config.crop = crop;
+        let region = self.render.config.region();
+
+        if keep_previous {
+            self.render.clear_region(region);
+        } else {
+            self.render = Render::new(self.render.config.clone());
+        }
+
+        self.tiles = TileScheduler::new(region, self.render.config.tile_size);
+    }
+
+    /// Fingerprint of everything that influences the rendered image: the camera,
+    /// the objects, and the config, except for the settings which only decide
+    /// how long to render, or how the result is read out.
+    ///
+    /// Meant to tell apart checkpoints of different scenes, it's only stable
+    /// for a given build of the renderer
+    pub fn hash(&self) -> u64 {
+        let config = &self.render.config;
+        let mut hasher = Fnv1a::new();
+
+        // Writing into the hasher never fails
+        let _ = write!(
+            hasher,
+            "{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}",
+            config.width,
+            config.height,
+            config.max_bounce_count,
+            config.spectral,
+            config.filter,
+            config.filter_radius,
+            config.clamp_radiance,
+            config.clamp_indirect_only,
+            config.aovs,
+            self.camera,
+            self.objects,
+            self.lights,
+            self.environment,
+            self.fog,
+        );
+
+        hasher.finish()
+    }
+
+    /// Save the render so far, see [Render::to_checkpoint]
+    pub fn checkpoint(&self) -> Vec<u8> {
+        self.render.to_checkpoint(self.hash())
+    }
+
+    /// Continue rendering from a checkpoint of the same scene, replacing
+    /// everything rendered so far. Fails if the scene doesn't match
+    pub fn resume(&mut self, checkpoint: &[u8]) -> Result<(), CheckpointError> {
+        self.render = Render::from_checkpoint(self.render.config.clone(), checkpoint, self.hash())?;
+        Ok(())
+    }
+
+    /// Add the samples of a checkpoint taken from another render of the same
+    /// scene to this render, see [Render::merge]
+    pub fn merge_checkpoint(&mut self, checkpoint: &[u8]) -> Result<(), CheckpointError> {
+        let other = Render::from_checkpoint(self.render.config.clone(), checkpoint, self.hash())?;
+        self.render.merge(&other)
+    }
+
+    /// Whether every pixel has either converged, or received all of its samples
+    pub fn is_finished(&self) -> bool {
+        self.render.convergence() >= 1.
+    }
+}
+
+/// Scale the color down, so that none of its components exceed `max`,
+/// while keeping its hue
+fn clamp_radiance(color: Vector3<f64>, max: f64) -> Vector3<f64> {
+    let brightest = color.max();
+
+    if brightest > max {
+        color * (max / brightest)
+    } else {
+        color
+    }
+}
+
+/// Weight of a sample taken with the density `pdf`, when the same light could also
+/// have been found with the density `other_pdf` (Veach 1997). Specular scattering
+/// has an infinite density, only it can find lights in a mirror
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    if pdf.is_infinite() {
+        return 1.;
+    }
+    if other_pdf.is_infinite() {
+        return 0.;
+    }
+
+    let pdf = pdf * pdf;
+    let other_pdf = other_pdf * other_pdf;
+
+    if pdf + other_pdf == 0. {
+        return 0.;
     }
+    pdf / (pdf + other_pdf)
 }
@@ -1,13 +1,22 @@
-use nalgebra::Vector3;
+use std::{f64::consts::PI, fmt::Debug};
+
+use nalgebra::{Vector2, Vector3};
 
 use super::Ray;
 
 // Base trait for all shapes
-pub trait Shape {
+pub trait Shape: Debug {
     // Returns the intersection point
     fn intersect(&self, ray: &Ray) -> Option<Vector3<f64>>;
     // Get a normal vector for a point on an object
     fn normal(&self, point: Vector3<f64>) -> Vector3<f64>;
+    // Surface area, after being stretched by the scale along every axis
+    fn area(&self, scale: Vector3<f64>) -> f64;
+    // Texture coordinates of a point on the surface, from 0 to 1
+    fn uv(&self, point: Vector3<f64>) -> Vector2<f64>;
+    // How a point on the surface moves along with the texture coordinates,
+    // dp/du and dp/dv, whose cross product points out of the surface
+    fn tangents(&self, point: Vector3<f64>) -> (Vector3<f64>, Vector3<f64>);
 }
 
 #[derive(Debug)]
@@ -23,13 +32,56 @@ impl Sphere {
     pub fn new() -> Self {
         Self {}
     }
+
+    // Longitude φ going around the Y axis from +X towards -Z, and the angle θ
+    // from the top. φ is 0 at the poles, whichever signs their zeros have
+    fn spherical(point: Vector3<f64>) -> (f64, f64) {
+        let point = point.normalize();
+        let phi = if point.xz() == Vector2::zeros() {
+            0.
+        } else {
+            (-point.z).atan2(point.x)
+        };
+
+        (phi, point.y.clamp(-1., 1.).acos())
+    }
 }
 
 impl Shape for Sphere {
+    // Scaled spheres are ellipsoids, whose area is approximated within 1.1%
+    // by Knud Thomsen's formula, and exact for spheres
+    fn area(&self, scale: Vector3<f64>) -> f64 {
+        const P: f64 = 1.6075;
+        let [a, b, c] = [scale.x, scale.y, scale.z].map(|s| s.abs().powf(P));
+
+        4. * PI * ((a * b + a * c + b * c) / 3.).powf(1. / P)
+    }
+
     fn normal(&self, point: Vector3<f64>) -> Vector3<f64> {
         (point).normalize()
     }
 
+    // Longitude going around the Y axis from +X towards -Z, and latitude going
+    // from the bottom up, so that an equirectangular image seen from outside
+    // isn't mirrored. The seam is at +X, where u wraps from 1 back to 0
+    fn uv(&self, point: Vector3<f64>) -> Vector2<f64> {
+        let (phi, theta) = Self::spherical(point);
+
+        Vector2::new((phi / (2. * PI)).rem_euclid(1.), 1. - theta / PI)
+    }
+
+    // The derivatives of x = sin θ cos φ, y = cos θ, z = -sin θ sin φ, with
+    // φ = 2πu and θ = π(1 - v). At the poles dp/du vanishes, while dp/dv keeps
+    // pointing along the seam
+    fn tangents(&self, point: Vector3<f64>) -> (Vector3<f64>, Vector3<f64>) {
+        let (phi, theta) = Self::spherical(point);
+        let (sin_theta, cos_theta) = theta.sin_cos();
+
+        let dpdu = 2. * PI * sin_theta * Vector3::new(-phi.sin(), 0., -phi.cos());
+        let dpdv = PI * Vector3::new(-cos_theta * phi.cos(), sin_theta, cos_theta * phi.sin());
+        (dpdu, dpdv)
+    }
+
     // Based on  the sphere equation - x^2 + y^2 + z^2 = r^2.
     // Substitude xyz for point of the sphere, which can be written as
     // (Ox + tDx)^2 + (Oy+tDy)^2 + (Oz+tDz)^2 = r^2, where
@@ -48,12 +100,13 @@ impl Shape for Sphere {
             return None;
         }
 
-        let t1 = (-b - discriminant.sqrt()) / 2.0 * a;
-        let t2 = (-b + discriminant.sqrt()) / 2.0 * a;
-        let dst = t1.min(t2);
+        let t1 = (-b - discriminant.sqrt()) / (2.0 * a);
+        let t2 = (-b + discriminant.sqrt()) / (2.0 * a);
 
-        // If either t is negative, that means that at least
-        // part of the object is behind the camera, so we ignore it
+        // The closer intersection is behind the ray origin when the ray starts
+        // inside of the sphere, like rays refracted into glass do. With both
+        // behind, the sphere is behind the ray
+        let dst = if t1 >= 0. { t1 } else { t2 };
         if dst < 0. {
             return None;
         }
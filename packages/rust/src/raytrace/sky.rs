@@ -0,0 +1,213 @@
+use std::f64::consts::{FRAC_PI_2, PI};
+
+use nalgebra::Vector3;
+
+use super::{
+    DirectionalLight, LUMINOUS_EFFICACY, planck, spectrum_to_xyz, xyy_to_xyz, xyz_to_linear_srgb,
+};
+
+/// Angular diameter of the sun seen from the earth, in radians
+pub const SUN_ANGULAR_DIAMETER: f64 = 0.0093;
+
+/// Surface temperature of the sun in kelvin, for its spectrum above the atmosphere
+const SUN_TEMPERATURE: f64 = 5778.;
+
+/// Clear sky lit by the sun, following the analytic model by Preetham, Shirley
+/// & Smits 1999. The sun itself is not part of the sky, add [Sky::sun] to the
+/// lights of the scene to get the matching sun disk.
+///
+/// The Y axis points up, +X east and -Z north. Radiance is given in watts per
+/// square meter and steradian, weighted by the sensitivity of the eye, so that
+/// a luminance of 683 candela per square meter comes out as 1. Directions below
+/// the horizon are black, the ground should be part of the scene
+#[derive(Clone, Debug)]
+pub struct Sky {
+    /// Unit vector pointing towards the sun
+    sun_direction: Vector3<f64>,
+    /// Haziness of the atmosphere, from 2 for a very clear sky to 10 for a hazy one
+    turbidity: f64,
+    /// Factor applied to the radiance of the sky and the sun
+    pub strength: f64,
+    // Coefficients of the Perez distribution for the luminance and the x, y chromaticity
+    perez: [[f64; 5]; 3],
+    // Luminance and chromaticity at the zenith, divided by the Perez distribution there
+    zenith: [f64; 3],
+    // Color of the sun disk, after passing through the atmosphere
+    sun_radiance: Vector3<f64>,
+}
+
+impl Sky {
+    /// Create a sky with the sun at the elevation above the horizon, and the
+    /// azimuth clockwise from north, both given in radians. The turbidity is
+    /// clamped to the range of 1.7 to 10 the model was fitted for
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64) -> Self {
+        let turbidity = turbidity.clamp(1.7, 10.);
+        let sun_direction = Vector3::new(
+            azimuth.sin() * elevation.cos(),
+            elevation.sin(),
+            -azimuth.cos() * elevation.cos(),
+        );
+
+        // The model only covers the sun above the horizon
+        let theta_sun = (FRAC_PI_2 - elevation).clamp(0., FRAC_PI_2);
+        let t = turbidity;
+
+        #[rustfmt::skip]
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+
+        // Zenith luminance in kilocandela per square meter, and chromaticity
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_sun);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let chromaticity = |coefficients: [[f64; 4]; 3]| {
+            let cubic = |c: [f64; 4]| {
+                c[0] * theta_sun.powi(3) + c[1] * theta_sun.powi(2) + c[2] * theta_sun + c[3]
+            };
+            t * t * cubic(coefficients[0]) + t * cubic(coefficients[1]) + cubic(coefficients[2])
+        };
+        let x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let zenith = [luminance * 1000. / LUMINOUS_EFFICACY, x, y];
+        let zenith =
+            [0, 1, 2].map(|i| zenith[i] / perez_distribution(perez[i], 1., theta_sun.cos()));
+
+        Self {
+            sun_direction,
+            turbidity,
+            strength: 1.,
+            perez,
+            zenith,
+            sun_radiance: sun_radiance(theta_sun, turbidity),
+        }
+    }
+
+    /// Create a sky with the sun where it stands at a place and time. The latitude
+    /// and longitude are given in radians, north and east being positive, the
+    /// day of the year starts at 1 on January 1st, and the time is given in hours UTC
+    pub fn at_time(
+        latitude: f64,
+        longitude: f64,
+        day_of_year: f64,
+        hours: f64,
+        turbidity: f64,
+    ) -> Self {
+        let (elevation, azimuth) = sun_position(latitude, longitude, day_of_year, hours);
+        Self::new(elevation, azimuth, turbidity)
+    }
+
+    /// Unit vector pointing towards the sun
+    pub fn sun_direction(&self) -> Vector3<f64> {
+        self.sun_direction
+    }
+
+    pub fn turbidity(&self) -> f64 {
+        self.turbidity
+    }
+
+    /// Radiance arriving from the direction, which doesn't need to be normalized
+    pub fn radiance(&self, direction: Vector3<f64>) -> Vector3<f64> {
+        let direction = direction.normalize();
+        if direction.y <= 0. {
+            return Vector3::zeros();
+        }
+
+        let cos_gamma = direction.dot(&self.sun_direction);
+        let [luminance, x, y] = [0, 1, 2]
+            .map(|i| self.zenith[i] * perez_distribution(self.perez[i], direction.y, cos_gamma));
+
+        let rgb = xyz_to_linear_srgb(xyy_to_xyz(x, y, luminance));
+        rgb.map(|c| c.max(0.)) * self.strength
+    }
+
+    /// Light of the sun disk, matching the radiance of the sky. It is dark
+    /// while the sun is below the horizon
+    pub fn sun(&self) -> DirectionalLight {
+        // The irradiance from a disk is π L sin²θ
+        let sin_max = (SUN_ANGULAR_DIAMETER / 2.).sin();
+        let irradiance = if self.sun_direction.y > 0. {
+            self.sun_radiance * (PI * sin_max * sin_max) * self.strength
+        } else {
+            Vector3::zeros()
+        };
+
+        DirectionalLight::new(-self.sun_direction, irradiance, SUN_ANGULAR_DIAMETER)
+    }
+}
+
+/// Perez sky luminance distribution, relative to the angle `theta` from the zenith
+/// and the angle `gamma` from the sun, given by their cosines
+fn perez_distribution([a, b, c, d, e]: [f64; 5], cos_theta: f64, cos_gamma: f64) -> f64 {
+    let gamma = cos_gamma.clamp(-1., 1.).acos();
+    // The distribution diverges at the horizon
+    let cos_theta = cos_theta.max(0.01);
+
+    (1. + a * (b / cos_theta).exp()) * (1. + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+/// Radiance of the sun disk seen at the angle `theta_sun` from the zenith, after
+/// Rayleigh scattering and scattering by aerosols along the way through the atmosphere
+fn sun_radiance(theta_sun: f64, turbidity: f64) -> Vector3<f64> {
+    // Relative optical mass of the air, by Kasten 1966
+    let mass =
+        1. / (theta_sun.cos() + 0.15 * (93.885 - theta_sun.to_degrees()).max(0.1).powf(-1.253));
+    // Ångström coefficients of the aerosols
+    let beta = 0.04608 * turbidity - 0.04586;
+    let alpha = 1.3;
+
+    let xyz = spectrum_to_xyz(|wavelength| {
+        let micrometers = wavelength / 1000.;
+        let rayleigh = (-0.008735 * micrometers.powf(-4.08) * mass).exp();
+        let aerosols = (-beta * micrometers.powf(-alpha) * mass).exp();
+        planck(wavelength, SUN_TEMPERATURE) * rayleigh * aerosols
+    });
+
+    xyz_to_linear_srgb(xyz).map(|c| c.max(0.))
+}
+
+/// Elevation above the horizon and azimuth clockwise from north of the sun,
+/// in radians, by the approximations of the NOAA general solar position.
+///
+/// The latitude and longitude are given in radians, the day of the year starts
+/// at 1 on January 1st, and the time is given in hours UTC
+pub fn sun_position(latitude: f64, longitude: f64, day_of_year: f64, hours: f64) -> (f64, f64) {
+    // Fractional year in radians
+    let year = 2. * PI / 365. * (day_of_year - 1. + (hours - 12.) / 24.);
+
+    // Equation of time in minutes, and declination of the sun
+    let equation_of_time = 229.18
+        * (0.000075 + 0.001868 * year.cos()
+            - 0.032077 * year.sin()
+            - 0.014615 * (2. * year).cos()
+            - 0.040849 * (2. * year).sin());
+    let declination = 0.006918 - 0.399912 * year.cos() + 0.070257 * year.sin()
+        - 0.006758 * (2. * year).cos()
+        + 0.000907 * (2. * year).sin()
+        - 0.002697 * (3. * year).cos()
+        + 0.00148 * (3. * year).sin();
+
+    // True solar time in minutes, the earth turns by a degree every 4 minutes
+    let solar_time = hours * 60. + equation_of_time + 4. * longitude.to_degrees();
+    let hour_angle = (solar_time / 4. - 180.).to_radians();
+
+    let cos_zenith =
+        latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+    let elevation = FRAC_PI_2 - cos_zenith.clamp(-1., 1.).acos();
+    let azimuth = hour_angle
+        .sin()
+        .atan2(hour_angle.cos() * latitude.sin() - declination.tan() * latitude.cos())
+        + PI;
+
+    (elevation, azimuth.rem_euclid(2. * PI))
+}
@@ -0,0 +1,361 @@
+use std::{cell::RefCell, collections::HashMap, sync::OnceLock};
+
+use nalgebra::{Matrix3, Vector3, Vector4};
+
+use super::{VISIBLE_RANGE, cie_xyz, planck, xyz_to_linear_srgb};
+
+/// Values of a spectral quantity at the four wavelengths of [Wavelengths::Sampled].
+/// In RGB mode, the first three channels hold red, green and blue instead
+pub type SampledSpectrum = Vector4<f64>;
+
+/// CIE XYZ of the D65 white point, which sRGB white is defined by
+const D65_WHITE: Vector3<f64> = Vector3::new(0.95047, 1., 1.08883);
+
+/// Relative spectral power of the CIE standard illuminant D65, every 10 nm from 360 nm
+#[rustfmt::skip]
+const D65: [f64; 48] = [
+    46.6383, 52.0891, 49.9755, 54.6482, 82.7549, 91.486, 93.4318, 86.6823, 104.865, 117.008,
+    117.812, 114.861, 115.923, 108.811, 109.354, 107.802, 104.79, 107.689, 104.405, 104.046,
+    100., 96.3342, 95.788, 88.6856, 90.0062, 89.5991, 87.6987, 83.2886, 83.6992, 80.0268,
+    80.2146, 82.2778, 78.2842, 69.7213, 71.6091, 74.349, 61.604, 69.8856, 75.087, 63.5927,
+    46.4182, 66.8054, 63.3828, 64.304, 59.4519, 51.959, 57.4406, 60.3125,
+];
+
+/// Relative power of D65 at the wavelength in nanometers, 1 at 560 nm
+fn d65(wavelength: f64) -> f64 {
+    let position = ((wavelength - VISIBLE_RANGE.0) / 10.).clamp(0., (D65.len() - 1) as f64);
+    let index = (position as usize).min(D65.len() - 2);
+    let t = position - index as f64;
+
+    (D65[index] * (1. - t) + D65[index + 1] * t) / 100.
+}
+
+/// Constants for turning spectra into colors, computed once
+struct Tables {
+    // Luminance of the D65 spectrum, divided out so that white has a luminance of 1
+    d65_luminance: f64,
+    // Correction of the small errors of [cie_xyz], so that D65 comes out exactly white
+    white_balance: Vector3<f64>,
+    // Linear sRGB contributed by a reflectance of 1 at each step, lit by D65
+    steps: Vec<(f64, Vector3<f64>)>,
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+
+    TABLES.get_or_init(|| {
+        let (first, last) = VISIBLE_RANGE;
+        let step = 5.;
+        let wavelengths: Vec<f64> = (0..=((last - first) / step) as usize)
+            .map(|i| first + i as f64 * step)
+            .collect();
+
+        let xyz: Vector3<f64> = wavelengths
+            .iter()
+            .map(|&w| cie_xyz(w) * d65(w) * step)
+            .sum();
+        let white_balance = D65_WHITE.component_div(&(xyz / xyz.y));
+
+        let steps = wavelengths
+            .iter()
+            .map(|&w| {
+                let xyz = cie_xyz(w).component_mul(&white_balance) * d65(w) * step / xyz.y;
+                ((w - first) / (last - first), xyz_to_linear_srgb(xyz))
+            })
+            .collect();
+
+        Tables {
+            d65_luminance: xyz.y,
+            white_balance,
+            steps,
+        }
+    })
+}
+
+/// Smooth reflectance spectrum matching an RGB color, by Jakob & Hanika 2019:
+/// a quadratic polynomial of the wavelength, squeezed into 0 to 1 by a sigmoid
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RgbSigmoid {
+    /// Polynomial coefficients, for the wavelength mapped onto 0 to 1 across the visible range
+    coefficients: Vector3<f64>,
+}
+
+impl RgbSigmoid {
+    /// Find the spectrum whose color under D65 light is closest to the linear
+    /// sRGB `color`, with components from 0 to 1.
+    ///
+    /// Shades of gray get a constant spectrum, other colors are fitted by
+    /// Levenberg-Marquardt iterations. Saturated colors outside of what a
+    /// smooth spectrum can reach get the closest match
+    pub fn fit(color: Vector3<f64>) -> Self {
+        let color = color.map(|c| c.clamp(0., 1.));
+        let gray = |value: f64| {
+            // Inverse of the sigmoid, kept finite for black and white
+            let value = value.clamp(1e-4, 1. - 1e-4);
+            Vector3::new(0., 0., (value - 0.5) / (value * (1. - value)).sqrt())
+        };
+
+        let mut coefficients = gray(color.mean());
+        if color.x == color.y && color.y == color.z {
+            return Self { coefficients };
+        }
+
+        let mut residual = Self::rgb(coefficients) - color;
+        let mut damping = 1e-3;
+        for _ in 0..100 {
+            if residual.norm() < 1e-6 {
+                break;
+            }
+
+            let jacobian = Self::jacobian(coefficients);
+            let normal = jacobian.transpose() * jacobian;
+            let damped = normal + Matrix3::from_diagonal(&normal.diagonal()) * damping;
+            let Some(inverse) = damped.try_inverse() else {
+                break;
+            };
+
+            let candidate = coefficients - inverse * jacobian.transpose() * residual;
+            let candidate_residual = Self::rgb(candidate) - color;
+            if candidate_residual.norm() < residual.norm() {
+                coefficients = candidate;
+                residual = candidate_residual;
+                damping = (damping / 3.).max(1e-9);
+            } else {
+                damping *= 3.;
+                if damping > 1e9 {
+                    break;
+                }
+            }
+        }
+
+        Self { coefficients }
+    }
+
+    fn polynomial(coefficients: Vector3<f64>, t: f64) -> f64 {
+        (coefficients.x * t + coefficients.y) * t + coefficients.z
+    }
+
+    fn sigmoid(x: f64) -> f64 {
+        if x.is_infinite() {
+            return if x > 0. { 1. } else { 0. };
+        }
+        0.5 + x / (2. * (1. + x * x).sqrt())
+    }
+
+    /// Color of the spectrum under D65 light
+    fn rgb(coefficients: Vector3<f64>) -> Vector3<f64> {
+        tables()
+            .steps
+            .iter()
+            .map(|&(t, rgb)| rgb * Self::sigmoid(Self::polynomial(coefficients, t)))
+            .sum()
+    }
+
+    /// Derivatives of [RgbSigmoid::rgb] by the coefficients, one column per coefficient
+    fn jacobian(coefficients: Vector3<f64>) -> Matrix3<f64> {
+        tables()
+            .steps
+            .iter()
+            .map(|&(t, rgb)| {
+                let x = Self::polynomial(coefficients, t);
+                let slope = 0.5 / (1. + x * x).powf(1.5);
+                rgb * (Vector3::new(t * t, t, 1.) * slope).transpose()
+            })
+            .sum()
+    }
+
+    /// Reflectance at the wavelength in nanometers
+    pub fn evaluate(&self, wavelength: f64) -> f64 {
+        let (first, last) = VISIBLE_RANGE;
+        let t = (wavelength - first) / (last - first);
+        Self::sigmoid(Self::polynomial(self.coefficients, t))
+    }
+}
+
+thread_local! {
+    // Fitting takes a while, and the same few colors come up at every hit
+    static FITTED: RefCell<HashMap<[u64; 3], RgbSigmoid>> = RefCell::new(HashMap::new());
+}
+
+/// [RgbSigmoid::fit], remembering the results
+fn fit_cached(color: Vector3<f64>) -> RgbSigmoid {
+    let key = [color.x, color.y, color.z].map(f64::to_bits);
+
+    FITTED.with_borrow_mut(|fitted| {
+        if let Some(&sigmoid) = fitted.get(&key) {
+            return sigmoid;
+        }
+
+        // Textures could come up with endless colors
+        if fitted.len() > 1 << 16 {
+            fitted.clear();
+        }
+        let sigmoid = RgbSigmoid::fit(color);
+        fitted.insert(key, sigmoid);
+        sigmoid
+    })
+}
+
+/// Which colors a path carries: either red, green and blue, or the spectrum at
+/// four wavelengths, picked by hero wavelength sampling (Wilkie et al. 2014)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Wavelengths {
+    Rgb,
+    /// Wavelengths in nanometers, evenly spaced around the visible range from
+    /// a uniformly distributed hero wavelength, which comes first
+    Sampled([f64; 4]),
+    /// The same wavelengths after wavelength dependent scattering, where only
+    /// the hero wavelength is still carried by the path
+    Hero([f64; 4]),
+}
+
+impl Wavelengths {
+    /// Pick the wavelengths of a path from a uniformly distributed `u` in 0 to 1
+    pub fn sample(u: f64) -> Self {
+        let (first, last) = VISIBLE_RANGE;
+
+        Wavelengths::Sampled([0., 1., 2., 3.].map(|i| first + ((u + i / 4.) % 1.) * (last - first)))
+    }
+
+    /// Spectrum with the same value everywhere
+    pub fn constant(&self, value: f64) -> SampledSpectrum {
+        match self {
+            Wavelengths::Rgb => Vector4::new(value, value, value, 0.),
+            Wavelengths::Sampled(_) | Wavelengths::Hero(_) => Vector4::repeat(value),
+        }
+    }
+
+    /// Spectrum of a quantity which is `rgb` in RGB mode, and `f` of the wavelength
+    /// otherwise. Only the wavelengths still carried get evaluated, the unused
+    /// fourth channel stays empty in RGB mode
+    pub fn map(&self, rgb: Vector3<f64>, f: impl Fn(f64) -> f64) -> SampledSpectrum {
+        match self {
+            Wavelengths::Rgb => rgb.push(0.),
+            Wavelengths::Sampled(wavelengths) => Vector4::from(wavelengths.map(f)),
+            Wavelengths::Hero(wavelengths) => Vector4::new(f(wavelengths[0]), 0., 0., 0.),
+        }
+    }
+
+    /// Spectrum of a surface reflecting the linear sRGB color, clamped to 0 to 1
+    pub fn reflectance(&self, color: Vector3<f64>) -> SampledSpectrum {
+        if color.x == color.y && color.y == color.z || *self == Wavelengths::Rgb {
+            return self.map(color, |_| color.x.clamp(0., 1.));
+        }
+
+        let sigmoid = fit_cached(color);
+        self.map(color, |w| sigmoid.evaluate(w))
+    }
+
+    /// Spectrum of a factor which can exceed 1, like the value of a BSDF
+    pub fn unbounded(&self, color: Vector3<f64>) -> SampledSpectrum {
+        let scale = 2. * color.max();
+        if color.x == color.y && color.y == color.z || *self == Wavelengths::Rgb || scale <= 0. {
+            return self.map(color, |_| color.x);
+        }
+
+        let sigmoid = fit_cached(color / scale);
+        self.map(color, |w| sigmoid.evaluate(w) * scale)
+    }
+
+    /// Spectral power distribution of light with the linear sRGB color: a smooth
+    /// spectrum lighting up D65, so that white light has the spectrum of daylight
+    pub fn illuminant(&self, color: Vector3<f64>) -> SampledSpectrum {
+        let scale = 2. * color.max();
+        if *self == Wavelengths::Rgb {
+            return color.push(0.);
+        }
+        if scale <= 0. {
+            return self.constant(0.);
+        }
+
+        let gray = color.x == color.y && color.y == color.z;
+        let sigmoid = (!gray).then(|| fit_cached(color / scale));
+        self.map(color, |w| {
+            let reflectance = sigmoid.map_or(color.x, |s| s.evaluate(w) * scale);
+            reflectance * d65(w)
+        })
+    }
+
+    /// Spectrum of a black body at the temperature in kelvin, with the
+    /// `luminance` of a light whose color is `rgb`, used in RGB mode
+    pub fn blackbody(
+        &self,
+        temperature: f64,
+        luminance: f64,
+        rgb: Vector3<f64>,
+    ) -> SampledSpectrum {
+        if *self == Wavelengths::Rgb {
+            return rgb.push(0.);
+        }
+
+        // Scaled like [Wavelengths::illuminant], where white light has a luminance of 1
+        let (first, last) = VISIBLE_RANGE;
+        let mut total = 0.;
+        let mut wavelength = first;
+        while wavelength <= last {
+            total += planck(wavelength, temperature) * cie_xyz(wavelength).y;
+            wavelength += 5.;
+        }
+        let scale = luminance * tables().d65_luminance / (total * 5.);
+
+        self.map(rgb, |w| planck(w, temperature) * scale)
+    }
+
+    /// The hero wavelength, which decides the path of wavelength dependent
+    /// scattering. [None] in RGB mode
+    pub fn hero(&self) -> Option<f64> {
+        match self {
+            Wavelengths::Rgb => None,
+            Wavelengths::Sampled(wavelengths) | Wavelengths::Hero(wavelengths) => {
+                Some(wavelengths[0])
+            }
+        }
+    }
+
+    /// Number of channels the path still carries, which come first
+    pub fn channels(&self) -> usize {
+        match self {
+            Wavelengths::Rgb => 3,
+            Wavelengths::Sampled(_) => 4,
+            Wavelengths::Hero(_) => 1,
+        }
+    }
+
+    /// Mean of a spectrum over the channels the path still carries
+    pub fn average(&self, spectrum: &SampledSpectrum) -> f64 {
+        spectrum.rows(0, self.channels()).mean()
+    }
+
+    /// Drop all but the hero wavelength, when a path scatters differently
+    /// depending on the wavelength. Returns the weight which makes up for the
+    /// dropped wavelengths, applied once per path
+    pub fn terminate_secondary(&mut self) -> SampledSpectrum {
+        match *self {
+            Wavelengths::Rgb => self.constant(1.),
+            Wavelengths::Sampled(wavelengths) => {
+                *self = Wavelengths::Hero(wavelengths);
+                Vector4::new(4., 0., 0., 0.)
+            }
+            Wavelengths::Hero(_) => Vector4::new(1., 0., 0., 0.),
+        }
+    }
+
+    /// Color of the light carried by a path: linear sRGB in RGB mode, or
+    /// CIE XYZ estimated from the samples of the spectrum
+    pub fn to_color(&self, spectrum: SampledSpectrum) -> Vector3<f64> {
+        let (Wavelengths::Sampled(wavelengths) | Wavelengths::Hero(wavelengths)) = self else {
+            return spectrum.xyz();
+        };
+
+        let (first, last) = VISIBLE_RANGE;
+        let tables = tables();
+        // Every wavelength was picked with a density of 1 / (last - first)
+        let xyz: Vector3<f64> = wavelengths
+            .iter()
+            .zip(spectrum.iter())
+            .map(|(&w, &value)| cie_xyz(w) * value)
+            .sum();
+
+        xyz.component_mul(&tables.white_balance) * (last - first) / (4. * tables.d65_luminance)
+    }
+}
@@ -1,7 +1,40 @@
+use std::sync::Arc;
+
 use nalgebra::{Vector2, Vector3};
 
 use crate::raytrace::{
-    camera::Camera, material::Lambertian, object::Object, shape::Sphere,
+    aov::Aov,
+    camera::Camera,
+    checkpoint::CheckpointError,
+    color::{blackbody, cie_xyz, spectrum_to_xyz, xyz_to_linear_srgb},
+    config::Config,
+    denoise::{DenoiseGuides, DenoiseSettings, denoise, reject_outliers},
+    environment::{Environment, EnvironmentMap, ImageError},
+    filter::{Filter, FilterKind},
+    light::{DirectionalLight, DiskLight, Light, PointLight, RectLight, SphereLight, SpotLight},
+    material::{
+        ComplexIor, Conductor, Dielectric, Interface, Ior, Lambertian, Material, RoughDielectric,
+    },
+    medium::Medium,
+    microfacet::TrowbridgeReitz,
+    normal_map::NormalMap,
+    object::Object,
+    openexr::ExrPrecision,
+    principled::{GltfMaterial, MtlMaterial, Principled},
+    radiance::read_hdr,
+    ray::Ray,
+    render::{Render, luminance},
+    sampling::uniform_sphere,
+    scene::Scene,
+    shape::{Shape, Sphere},
+    sky::{Sky, sun_position},
+    spectrum::{SampledSpectrum, Wavelengths},
+    texture::{
+        Checker, CheckerSpace, ImageTexture, Marble, Noise, Perlin, SolidColor, Texture,
+        TextureCoordinates, Wood, WrapMode,
+    },
+    tile::{Tile, TileScheduler},
+    tonemap::{ToneMapOperator, ToneMapping, srgb_decode, srgb_encode},
     transform::TransformBuilder,
 };
 
@@ -59,11 +92,2047 @@ fn ray_physics() {
     // Test that the distance to the sphere equals to 9 (center of the sphere minus radius)
     assert_eq!(intersection, Vector3::new(9., 0., 0.));
 
+    // Rays starting inside of the sphere hit its far side, and rays
+    // pointing away from it miss it
+    let inside = Ray {
+        origin: Vector3::new(10., 0., 0.),
+        direction: Vector3::new(1., 0., 0.),
+    };
+    assert_eq!(sphere.hit(&inside), Some(Vector3::new(11., 0., 0.)));
+    let away = Ray {
+        origin: Vector3::new(12., 0., 0.),
+        direction: Vector3::new(1., 0., 0.),
+    };
+    assert_eq!(sphere.hit(&away), None);
+
     // 10 thousand reflections should be enough
     for _ in 0..10_000 {
-        sphere
-            .material
-            .scatter(&mut ray, intersection, sphere.shape.normal(intersection));
+        sphere.material.scatter(
+            &mut ray,
+            intersection,
+            sphere.shape.normal(intersection),
+            &TextureCoordinates::default(),
+            &mut Wavelengths::Rgb,
+        );
         assert!(ray.direction.dot(&sphere.shape.normal(intersection)) >= 0.0);
     }
 }
+
+#[test]
+// Every filter must peak at the pixel center and vanish outside of its radius
+fn filter_weights() {
+    for kind in [
+        FilterKind::Box,
+        FilterKind::Tent,
+        FilterKind::Gaussian,
+        FilterKind::Mitchell,
+    ] {
+        let filter = Filter::new(kind, 2.);
+
+        let center = filter.evaluate(0., 0.);
+        assert!(center > 0.);
+        assert!(filter.evaluate(0.7, 0.3) <= center);
+        assert_eq!(filter.evaluate(2.5, 0.), 0.);
+        assert_eq!(filter.evaluate(0., -2.5), 0.);
+        // Filters are symmetric
+        assert_eq!(filter.evaluate(0.4, 1.1), filter.evaluate(-0.4, -1.1));
+
+        // Radii too small to reach the pixel a sample falls into are raised
+        let filter = Filter::new(kind, 0.);
+        assert_eq!(filter.radius, 0.5);
+        assert!(filter.evaluate(0., 0.) > 0. && filter.evaluate(0.3, 0.2).is_finite());
+    }
+}
+
+#[test]
+// Splatting samples of a constant color must average back to the very same color,
+// no matter how the filter distributes the weights
+fn filter_splatting() {
+    for kind in [FilterKind::Box, FilterKind::Tent, FilterKind::Gaussian] {
+        let mut config = Config::new(8, 8, 1, 1);
+        config.filter = kind;
+        config.filter_radius = 1.5;
+        let mut render = Render::new(config);
+
+        let color = Vector3::new(0.25, 0.5, 1.);
+        for x in 0..8 {
+            for y in 0..8 {
+                render.add_sample(Vector2::new(x as f64 + 0.3, y as f64 + 0.8), color);
+            }
+        }
+
+        for x in 0..8 {
+            for y in 0..8 {
+                assert!((render.get_pixel_averaged(x, y) - color).magnitude() < 1e-9);
+            }
+        }
+    }
+
+    // A sample right in the middle of a pixel only reaches its direct neighbours
+    let mut config = Config::new(8, 8, 1, 1);
+    config.filter = FilterKind::Tent;
+    config.filter_radius = 1.;
+    let mut render = Render::new(config);
+    render.add_sample(Vector2::new(3.5, 3.5), Vector3::new(1., 1., 1.));
+    assert!(render.get_weight(3, 3) > 0.);
+    assert_eq!(render.get_weight(4, 3), 0.);
+    assert_eq!(render.get_weight(2, 2), 0.);
+}
+
+#[test]
+// Tone mapping must always produce displayable values, keep black black,
+// and never make a brighter input darker
+fn tone_mapping() {
+    // Reference points of the sRGB transfer function
+    assert_eq!(srgb_encode(0.), 0.);
+    assert!((srgb_encode(1.) - 1.).abs() < 1e-12);
+    assert!((srgb_encode(0.18) - 0.461356).abs() < 1e-6);
+    assert!((srgb_decode(srgb_encode(0.5)) - 0.5).abs() < 1e-12);
+
+    for operator in [
+        ToneMapOperator::Linear,
+        ToneMapOperator::Reinhard,
+        ToneMapOperator::ExtendedReinhard,
+        ToneMapOperator::Aces,
+        ToneMapOperator::AgX,
+    ] {
+        let tone_mapping = ToneMapping::new(operator, 0.);
+
+        assert!(tone_mapping.apply(Vector3::zeros()).magnitude() < 0.01);
+
+        let mut previous = 0.;
+        for i in 0..100 {
+            let value = i as f64 * 0.25;
+            let mapped = tone_mapping.apply(Vector3::new(value, value, value));
+
+            assert!(mapped.iter().all(|c| (0. ..=1.).contains(c)));
+            assert!(mapped.x >= previous - 1e-9);
+            previous = mapped.x;
+        }
+    }
+
+    // One stop of exposure doubles the linear value
+    let linear = ToneMapping::new(ToneMapOperator::Linear, 1.);
+    let mapped = linear.apply(Vector3::new(0.1, 0.1, 0.1));
+    assert!((srgb_decode(mapped.x) - 0.2).abs() < 1e-9);
+
+    // Extended Reinhard maps the white point to pure white
+    let mut reinhard = ToneMapping::new(ToneMapOperator::ExtendedReinhard, 0.);
+    reinhard.white_point = 3.;
+    assert!((reinhard.apply(Vector3::new(3., 3., 3.)).x - 1.).abs() < 1e-9);
+}
+
+#[test]
+// High dynamic range exports must keep values above 1, which 8-bit images can't
+fn hdr_export() {
+    use exr::prelude::traits::*;
+
+    let mut config = Config::new(4, 2, 1, 1);
+    config.filter = FilterKind::Box;
+    config.filter_radius = 0.5;
+    let mut render = Render::new(config);
+    for x in 0..4 {
+        for y in 0..2 {
+            render.add_sample(
+                Vector2::new(x as f64 + 0.5, y as f64 + 0.5),
+                Vector3::new(x as f64 * 4., 0.5, y as f64),
+            );
+        }
+    }
+
+    for precision in [ExrPrecision::Half, ExrPrecision::Float] {
+        let bytes = render.to_exr(precision).unwrap();
+        let image = exr::prelude::read()
+            .no_deep_data()
+            .largest_resolution_level()
+            .all_channels()
+            .first_valid_layer()
+            .all_attributes()
+            .from_buffered(std::io::Cursor::new(bytes))
+            .unwrap();
+
+        let channels = &image.layer_data.channel_data.list;
+        let red = channels.iter().find(|c| c.name.to_string() == "R").unwrap();
+        // Last pixel of the first row
+        assert_eq!(red.sample_data.value_by_flat_index(3).to_f32(), 12.);
+    }
+
+    let hdr = render.to_hdr();
+    let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 4\n";
+    assert!(hdr.starts_with(header));
+    assert_eq!(hdr.len(), header.len() + 4 * 2 * 4);
+    // 12 = 0.75 * 2^4, so the mantissa is 0.75 * 256 with an exponent of 128 + 4
+    assert_eq!(
+        &hdr[header.len() + 12..header.len() + 16],
+        &[192, 8, 0, 132]
+    );
+}
+
+#[test]
+// Render a single sphere in front of the camera, and check what the AOVs saw
+fn aov_buffers() {
+    let mut config = Config::new(16, 9, 4, 1);
+    config.aovs = true;
+    let camera = Camera::new(
+        Vector2::new(16, 9),
+        Vector3::new(0., 0., 0.),
+        Vector3::new(10., 0., 0.),
+    );
+    let mut scene = Scene::new(config, camera);
+    scene.objects.push(Object::new_emissive(
+        Box::new(Sphere::new()),
+        Vector3::new(1., 1., 1.),
+        2.,
+        Box::new(Lambertian::new()),
+        TransformBuilder::new()
+            .translate_x(10.)
+            .scale_uniform(3.)
+            .build(),
+    ));
+    scene.sample();
+
+    let render = &scene.render;
+    let center = 4 * 16 + 8;
+    let corner = 0;
+
+    let ids = render.get_aov(Aov::ObjectId).unwrap();
+    assert_eq!(ids[center].x, 0.);
+    assert_eq!(ids[corner].x, -1.);
+
+    // The camera looks straight at the sphere, so the normal points right back at it
+    let normals = render.get_aov(Aov::Normal).unwrap();
+    assert!(normals[center].x < -0.95);
+
+    let depth = render.get_aov(Aov::Depth).unwrap();
+    assert!((depth[center].x - 7.).abs() < 0.3);
+    assert_eq!(depth[corner].x, 0.);
+
+    // The emitter is seen directly, so all of its light is direct
+    let direct = render.get_aov(Aov::Direct).unwrap();
+    let indirect = render.get_aov(Aov::Indirect).unwrap();
+    let beauty = render.averaged();
+    assert!((direct[center] - Vector3::new(2., 2., 2.)).magnitude() < 1e-9);
+    for i in 0..16 * 9 {
+        assert!((direct[i] + indirect[i] - beauty[i]).magnitude() < 1e-9);
+    }
+
+    // Layers are written next to the main image
+    let names: Vec<String> = render.layers().iter().map(|l| l.name.clone()).collect();
+    assert!(names.contains(&"albedo".to_string()));
+    assert!(names.contains(&"object".to_string()));
+
+    // AOVs are opt-in
+    assert!(
+        Render::new(Config::new(4, 4, 1, 1))
+            .get_aov(Aov::Albedo)
+            .is_none()
+    );
+}
+
+#[test]
+// Denoising must smooth out the noise, but keep the edge between two differently
+// colored surfaces intact
+fn denoiser() {
+    use rand::prelude::*;
+
+    let (width, height) = (32, 16);
+    let mut rng = rand::rng();
+
+    let mut albedo = Vec::new();
+    let mut color = Vec::new();
+    for _ in 0..height {
+        for x in 0..width {
+            let a = if x < width / 2 { 0.2 } else { 0.8 };
+            // Noisy, but on average uniform lighting
+            let lighting = rng.random_range(0.0..2.0);
+
+            albedo.push(Vector3::new(a, a, a));
+            color.push(Vector3::new(a, a, a) * lighting);
+        }
+    }
+
+    let denoised = denoise(
+        width,
+        height,
+        &color,
+        DenoiseGuides {
+            albedo: Some(&albedo),
+            ..Default::default()
+        },
+        &DenoiseSettings {
+            sigma_color: 10.,
+            ..Default::default()
+        },
+    );
+
+    let variance = |pixels: &[Vector3<f64>], range: std::ops::Range<usize>, mean: f64| {
+        let values: Vec<f64> = (0..height)
+            .flat_map(|y| range.clone().map(move |x| pixels[y * width + x].x))
+            .collect();
+        values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64
+    };
+
+    for (range, mean) in [(0..width / 2, 0.2), (width / 2..width, 0.8)] {
+        assert!(
+            variance(&denoised, range.clone(), mean) < variance(&color, range.clone(), mean) / 10.
+        );
+    }
+
+    // Pixels right next to the edge keep their own color
+    assert!((denoised[width / 2 - 1].x - 0.2).abs() < 0.1);
+    assert!((denoised[width / 2].x - 0.8).abs() < 0.2);
+}
+
+#[test]
+// Pixels without any noise (the empty background, and the emitter itself)
+// must stop receiving samples once they converge
+fn adaptive_sampling() {
+    let scene_with = |config: Config| {
+        let camera = Camera::new(
+            Vector2::new(config.width, config.height),
+            Vector3::new(0., 0., 0.),
+            Vector3::new(10., 0., 0.),
+        );
+        let mut scene = Scene::new(config, camera);
+        scene.objects.push(Object::new_emissive(
+            Box::new(Sphere::new()),
+            Vector3::new(1., 1., 1.),
+            1.,
+            Box::new(Lambertian::new()),
+            TransformBuilder::new()
+                .translate_x(10.)
+                .scale_uniform(3.)
+                .build(),
+        ));
+        scene
+    };
+
+    // Without adaptive sampling the progress is simply the share of passes done
+    let mut scene = scene_with(Config::new(16, 9, 2, 4));
+    scene.sample();
+    assert!((scene.render.convergence() - 0.25).abs() < 1e-9);
+    for _ in 0..3 {
+        scene.sample();
+    }
+    assert!(scene.is_finished());
+
+    let mut config = Config::new(16, 9, 2, 64);
+    config.adaptive_threshold = 0.01;
+    config.adaptive_min_samples = 4;
+    let mut scene = scene_with(config);
+
+    let mut passes = 0;
+    while !scene.is_finished() {
+        scene.sample();
+        passes += 1;
+    }
+
+    // Only the pixels on the edge of the sphere are noisy, everything else
+    // stops right after the minimal sample count
+    assert!(passes > 4 && passes <= 64);
+    assert_eq!(scene.render.pixel_samples(0, 0), 4);
+    assert_eq!(scene.render.pixel_samples(8, 4), 4);
+}
+
+#[test]
+// Clamping must limit the brightness of samples, optionally sparing direct light,
+// and outlier rejection must only touch isolated bright pixels
+fn firefly_suppression() {
+    let render_emitter = |indirect_only: bool| {
+        let mut config = Config::new(16, 9, 2, 1);
+        config.aovs = true;
+        config.clamp_radiance = 1.;
+        config.clamp_indirect_only = indirect_only;
+        let camera = Camera::new(
+            Vector2::new(16, 9),
+            Vector3::new(0., 0., 0.),
+            Vector3::new(10., 0., 0.),
+        );
+        let mut scene = Scene::new(config, camera);
+        scene.objects.push(Object::new_emissive(
+            Box::new(Sphere::new()),
+            Vector3::new(1., 0.5, 0.),
+            100.,
+            Box::new(Lambertian::new()),
+            TransformBuilder::new()
+                .translate_x(10.)
+                .scale_uniform(3.)
+                .build(),
+        ));
+        scene.sample();
+        scene.render.get_aov(Aov::Direct).unwrap()[4 * 16 + 8]
+    };
+
+    // The hue stays the same
+    assert!((render_emitter(false) - Vector3::new(1., 0.5, 0.)).magnitude() < 1e-9);
+    // The emitter is seen directly, so it's left alone
+    assert!((render_emitter(true) - Vector3::new(100., 50., 0.)).magnitude() < 1e-9);
+
+    let (width, height) = (5, 5);
+    let mut pixels = vec![Vector3::new(0.5, 0.5, 0.5); width * height];
+    pixels[2 * width + 2] = Vector3::new(50., 50., 50.);
+    // A bright, but smooth gradient must not be affected
+    for (x, pixel) in pixels.iter_mut().take(width).enumerate() {
+        *pixel = Vector3::new(1., 1., 1.) * (x + 1) as f64;
+    }
+
+    let result = reject_outliers(width, height, &pixels, 3.);
+    assert!(result[2 * width + 2].x < 1.);
+    assert_eq!(result[..width], pixels[..width]);
+    assert_eq!(result[4 * width + 4], pixels[4 * width + 4]);
+}
+
+#[test]
+// Tiles must cover every pixel exactly once per pass, starting from the center
+fn tile_scheduling() {
+    let region = Tile::new(3, 2, 100, 50);
+    let mut scheduler = TileScheduler::new(region, 16);
+    assert_eq!(scheduler.len(), 7 * 4);
+
+    let mut coverage = vec![0; 110 * 60];
+    for i in 0..scheduler.len() {
+        let (tile, pass_finished) = scheduler.next_tile().unwrap();
+        assert_eq!(pass_finished, i == scheduler.len() - 1);
+
+        // The spiral starts in the middle of the region
+        if i == 0 {
+            assert!(tile.contains(53, 27));
+        }
+
+        for y in tile.y..tile.y + tile.height {
+            for x in tile.x..tile.x + tile.width {
+                coverage[y * 110 + x] += 1;
+            }
+        }
+    }
+    for y in 0..60 {
+        for x in 0..110 {
+            assert_eq!(coverage[y * 110 + x], region.contains(x, y) as i32);
+        }
+    }
+
+    // The next pass starts over
+    assert_eq!(
+        scheduler.next_tile().unwrap().0,
+        TileScheduler::new(region, 16).next_tile().unwrap().0
+    );
+
+    let mut config = Config::new(40, 20, 1, 1);
+    config.tile_size = 10;
+    let camera = Camera::new(
+        Vector2::new(40, 20),
+        Vector3::new(0., 0., 0.),
+        Vector3::new(10., 0., 0.),
+    );
+    let mut scene = Scene::new(config, camera);
+
+    // Changed regions include the pixels reached by the filter
+    let changed = scene.sample_tiles(3);
+    assert_eq!(changed.len(), 3);
+    assert!(changed.iter().all(|t| t.area() > 100));
+    assert_eq!(scene.render.sample_count(), 0);
+
+    // Rendering the remaining tiles finishes the pass
+    scene.sample_tiles(5);
+    assert_eq!(scene.render.sample_count(), 1);
+    assert!(scene.is_finished());
+
+    // Even without any time left, one tile is rendered
+    #[cfg(not(target_arch = "wasm32"))]
+    assert_eq!(scene.sample_for(std::time::Duration::ZERO).len(), 1);
+}
+
+#[test]
+// Only pixels inside of the crop rectangle must receive samples, and cropping
+// to a detail must keep the rest of the image
+fn crop_rendering() {
+    let mut config = Config::new(40, 20, 1, 4);
+    config.set_crop(10, 5, 50, 10);
+    // The crop rectangle is limited to the screen
+    assert_eq!(config.region(), Tile::new(10, 5, 30, 10));
+
+    let camera = Camera::new(
+        Vector2::new(40, 20),
+        Vector3::new(0., 0., 0.),
+        Vector3::new(10., 0., 0.),
+    );
+    let mut scene = Scene::new(config, camera);
+
+    while !scene.is_finished() {
+        scene.sample();
+    }
+    assert_eq!(scene.render.sample_count(), 4);
+
+    // Filter splats don't leak out of the rectangle either
+    let region = scene.render.config.region();
+    for y in 0..20 {
+        for x in 0..40 {
+            let inside = region.contains(x, y);
+            assert_eq!(scene.render.get_weight(x, y) > 0., inside);
+            assert_eq!(scene.render.pixel_samples(x, y), 4 * inside as usize);
+        }
+    }
+
+    // Re-rendering a detail keeps the rest of the image
+    scene.set_crop(Some(Tile::new(0, 0, 5, 5)), true);
+    assert_eq!(scene.render.convergence(), 0.);
+    assert!(scene.render.get_weight(20, 10) > 0.);
+    scene.sample();
+    assert_eq!(scene.render.pixel_samples(2, 2), 1);
+    assert_eq!(scene.render.pixel_samples(20, 10), 4);
+
+    // Otherwise the render starts over
+    scene.set_crop(None, false);
+    assert_eq!(scene.render.get_weight(20, 10), 0.);
+    assert_eq!(scene.render.sample_count(), 0);
+}
+
+#[test]
+// A checkpoint must let a copy of the scene continue the render exactly where
+// it left off, and be rejected by any other scene
+fn checkpoints() {
+    let build = |color: f64| {
+        let mut config = Config::new(16, 8, 2, 4);
+        config.aovs = true;
+        let camera = Camera::new(
+            Vector2::new(16, 8),
+            Vector3::new(0., 0., 0.),
+            Vector3::new(10., 0., 0.),
+        );
+
+        let mut scene = Scene::new(config, camera);
+        scene.objects.push(Object::new_emissive(
+            Box::new(Sphere),
+            Vector3::new(color, 1., 1.),
+            1.,
+            Box::new(Lambertian),
+            TransformBuilder::new()
+                .translate_x(10.)
+                .scale_uniform(3.)
+                .build(),
+        ));
+        scene
+    };
+
+    let mut scene = build(1.);
+    scene.sample();
+    scene.sample();
+    let checkpoint = scene.checkpoint();
+
+    // A fresh copy of the scene continues where the first one left off
+    let mut resumed = build(1.);
+    assert_eq!(resumed.hash(), scene.hash());
+    resumed.resume(&checkpoint).unwrap();
+    assert_eq!(resumed.render.sample_count(), 2);
+    assert_eq!(resumed.render.averaged(), scene.render.averaged());
+    assert_eq!(
+        resumed.render.get_aov(Aov::ObjectId),
+        scene.render.get_aov(Aov::ObjectId)
+    );
+    assert_eq!(resumed.checkpoint(), checkpoint);
+
+    resumed.sample();
+    assert_eq!(resumed.render.sample_count(), 3);
+    assert_eq!(resumed.render.pixel_samples(8, 4), 3);
+
+    // Checkpoints of other scenes are rejected
+    let mut other = build(0.5);
+    assert_eq!(
+        other.resume(&checkpoint),
+        Err(CheckpointError::SceneMismatch)
+    );
+    assert_eq!(
+        Render::from_checkpoint(Config::new(8, 8, 2, 4), &checkpoint, scene.hash()).err(),
+        Some(CheckpointError::ResolutionMismatch {
+            expected: (8, 8),
+            found: (16, 8),
+        })
+    );
+    assert_eq!(
+        resumed.resume(&checkpoint[..checkpoint.len() - 1]),
+        Err(CheckpointError::Malformed)
+    );
+    assert_eq!(
+        resumed.resume(b"not a checkpoint"),
+        Err(CheckpointError::Malformed)
+    );
+}
+
+#[test]
+// Merging must sum up the samples of two renders of the same scene, and refuse
+// renders of other scenes or with other buffers
+fn merging() {
+    let build = |color: f64, aovs: bool| {
+        let mut config = Config::new(16, 8, 2, 4);
+        config.aovs = aovs;
+        let camera = Camera::new(
+            Vector2::new(16, 8),
+            Vector3::new(0., 0., 0.),
+            Vector3::new(10., 0., 0.),
+        );
+
+        let mut scene = Scene::new(config, camera);
+        scene.objects.push(Object::new_emissive(
+            Box::new(Sphere),
+            Vector3::new(color, 1., 1.),
+            1.,
+            Box::new(Lambertian),
+            TransformBuilder::new()
+                .translate_x(10.)
+                .scale_uniform(3.)
+                .build(),
+        ));
+        scene
+    };
+
+    let mut scene = build(1., true);
+    scene.sample();
+    scene.sample();
+    let checkpoint = scene.checkpoint();
+
+    // Merging sums up the samples of both renders
+    let mut merged = build(1., true);
+    merged.sample();
+    let weight = merged.render.get_weight(8, 4) + scene.render.get_weight(8, 4);
+    merged.merge_checkpoint(&checkpoint).unwrap();
+    assert_eq!(merged.render.sample_count(), 3);
+    assert_eq!(merged.render.pixel_samples(8, 4), 3);
+    assert!((merged.render.get_weight(8, 4) - weight).abs() < 1e-9);
+    assert!(merged.render.get_aov(Aov::Albedo).is_some());
+
+    // Checkpoints of other scenes, or with other buffers, are rejected
+    assert_eq!(
+        build(0.5, true).merge_checkpoint(&checkpoint),
+        Err(CheckpointError::SceneMismatch)
+    );
+    assert_eq!(
+        build(1., false).merge_checkpoint(&checkpoint),
+        Err(CheckpointError::SceneMismatch)
+    );
+
+    // Renders with AOVs don't lose them to renders without, or the other way around
+    let mut config = merged.render.config.clone();
+    config.aovs = false;
+    let mut plain = Render::new(config);
+    assert_eq!(
+        merged.render.merge(&plain),
+        Err(CheckpointError::SceneMismatch)
+    );
+    assert_eq!(merged.render.sample_count(), 3);
+    assert!(merged.render.get_aov(Aov::Albedo).is_some());
+    assert_eq!(
+        plain.merge(&merged.render),
+        Err(CheckpointError::SceneMismatch)
+    );
+}
+
+#[test]
+// Sampled area lights give the analytic irradiance, with densities
+// matching their intersections, and one-sided ones are dark from behind
+fn area_lights() {
+    use std::f64::consts::PI;
+
+    use rand::prelude::*;
+
+    let mut rng = rand::rng();
+    let point = Vector3::zeros();
+    let up = Vector3::new(0., 1., 0.);
+    let white = Vector3::new(1., 1., 1.);
+
+    // Monte Carlo estimate of the irradiance at the origin, facing up
+    let mut irradiance = |light: &dyn Light| {
+        let count = 20000;
+        let mut sum = 0.;
+        for _ in 0..count {
+            let u = Vector2::new(rng.random::<f64>(), rng.random::<f64>());
+            let Some(sample) = light.sample_li(point, u) else {
+                continue;
+            };
+
+            // The densities of sampling and evaluating have to agree
+            let pdf = light.pdf_li(point, sample.direction);
+            assert!(
+                (pdf - sample.pdf).abs() < 1e-6 * pdf,
+                "{pdf} != {}",
+                sample.pdf
+            );
+            let ray = Ray {
+                origin: point,
+                direction: sample.direction,
+            };
+            let (distance, _) = light.intersect(&ray).unwrap();
+            assert!((distance - sample.distance).abs() < 1e-6);
+
+            sum += sample.radiance.x * sample.direction.dot(&up).max(0.) / sample.pdf;
+        }
+        sum / count as f64
+    };
+
+    // Analytic irradiance from a sphere straight above: π L sin²θ
+    let sphere = SphereLight::new(Vector3::new(0., 4., 0.), 1., white);
+    assert!((irradiance(&sphere) - PI / 16.).abs() < 1e-3);
+    // A sphere covering the point doesn't light it
+    let around = SphereLight::new(Vector3::zeros(), 1., white);
+    assert!(around.sample_li(point, Vector2::new(0.5, 0.5)).is_none());
+
+    // Disk facing down: π L r² / (h² + r²)
+    let disk = DiskLight::new(Vector3::new(0., 2., 0.), -up, 1., white, false);
+    assert!((irradiance(&disk) - PI / 5.).abs() < 0.02);
+
+    // One-sided lights are dark from behind
+    let rect = RectLight::new(
+        Vector3::new(-1., 2., -1.),
+        Vector3::new(2., 0., 0.),
+        Vector3::new(0., 0., 2.),
+        white,
+        false,
+    );
+    let u = Vector2::new(0.3, 0.6);
+    assert!(rect.sample_li(point, u).is_some());
+    assert!(rect.sample_li(Vector3::new(0., 4., 0.), u).is_none());
+    assert_eq!(rect.pdf_li(Vector3::new(0., 4., 0.), -up), 0.);
+    let two_sided = RectLight {
+        two_sided: true,
+        ..rect
+    };
+    assert!(two_sided.sample_li(Vector3::new(0., 4., 0.), u).is_some());
+
+    // A square split into four rectangles with a corner above the point, each
+    // lighting it with L (A / sqrt(1 + A²)) atan(B / sqrt(1 + A²)), with A = B = 1/2
+    let expected = 4. * (1. / 5f64.sqrt()) * (1. / 5f64.sqrt()).atan();
+    assert!((irradiance(&rect) - expected).abs() < 0.02);
+}
+
+#[test]
+// Point, spot and directional lights give a white surface facing them the
+// analytic brightness, and nothing outside their cone or behind the surface
+fn delta_lights() {
+    use std::f64::consts::PI;
+
+    // A white sphere in front of the camera, its closest point 7 units away
+    let render = |light: Box<dyn Light>, samples: usize| {
+        let config = Config::new(41, 41, 2, samples);
+        let camera = Camera::new(
+            Vector2::new(41, 41),
+            Vector3::new(0., 0., 0.),
+            Vector3::new(10., 0., 0.),
+        );
+        let mut scene = Scene::new(config, camera);
+        scene.objects.push(Object::new(
+            Box::new(Sphere),
+            Vector3::new(1., 1., 1.),
+            Box::new(Lambertian),
+            TransformBuilder::new()
+                .translate_x(10.)
+                .scale_uniform(3.)
+                .build(),
+        ));
+        scene.lights.push(light);
+
+        while !scene.is_finished() {
+            scene.sample();
+        }
+        scene.render.get_pixel_averaged(20, 20).x
+    };
+
+    // Lambertian surface facing the light: I / d² / π
+    let point = PointLight::new(Vector3::zeros(), Vector3::repeat(49.));
+    assert!((render(Box::new(point), 4) - 1. / PI).abs() < 0.01);
+
+    // Spot lights only shine into their cone
+    let spot = |direction: Vector3<f64>| {
+        Box::new(SpotLight::new(
+            Vector3::zeros(),
+            direction,
+            Vector3::repeat(49.),
+            0.2,
+            0.3,
+        ))
+    };
+    assert!((render(spot(Vector3::new(1., 0., 0.)), 4) - 1. / PI).abs() < 0.01);
+    assert_eq!(render(spot(Vector3::new(-1., 0., 0.)), 4), 0.);
+
+    // The sun, hard or soft, delivers its irradiance: E / π
+    let sun = DirectionalLight::new(Vector3::new(1., 0., 0.), Vector3::repeat(1.), 0.);
+    assert!((render(Box::new(sun), 4) - 1. / PI).abs() < 0.01);
+    let soft = DirectionalLight::new(Vector3::new(1., 0., 0.), Vector3::repeat(1.), 0.2);
+    assert!(!soft.is_delta());
+    assert!((render(Box::new(soft), 64) - 1. / PI).abs() < 0.01);
+    // Lights behind the sphere are blocked by it
+    let behind = DirectionalLight::new(Vector3::new(-1., 0., 0.), Vector3::repeat(1.), 0.);
+    assert_eq!(render(Box::new(behind), 4), 0.);
+}
+
+#[test]
+// Environments light the scene without bias, maps are sampled by brightness,
+// and HDR and EXR files load while malformed ones are rejected
+fn environment_lighting() {
+    use std::f64::consts::PI;
+
+    // A white sphere in front of the camera, lit by nothing but the environment
+    let render = |environment: Environment, sphere: bool| {
+        let config = Config::new(41, 41, 2, 64);
+        let camera = Camera::new(
+            Vector2::new(41, 41),
+            Vector3::new(0., 0., 0.),
+            Vector3::new(10., 0., 0.),
+        );
+        let mut scene = Scene::new(config, camera);
+        if sphere {
+            scene.objects.push(Object::new(
+                Box::new(Sphere),
+                Vector3::new(1., 1., 1.),
+                Box::new(Lambertian),
+                TransformBuilder::new()
+                    .translate_x(10.)
+                    .scale_uniform(3.)
+                    .build(),
+            ));
+        }
+        scene.environment = Some(environment);
+
+        while !scene.is_finished() {
+            scene.sample();
+        }
+        scene.render.get_pixel_averaged(20, 20)
+    };
+
+    // Missed rays see the environment directly
+    let gradient = Environment::Gradient {
+        bottom: Vector3::new(0., 0., 1.),
+        top: Vector3::new(1., 0., 0.),
+    };
+    assert!((render(gradient, false) - Vector3::new(0.5, 0., 0.5)).norm() < 0.01);
+
+    // A convex white surface reflects a constant environment unchanged,
+    // whether the directions are picked uniformly or from the image
+    let constant = render(Environment::Constant(Vector3::repeat(1.)), true);
+    assert!((constant.x - 1.).abs() < 0.05, "{constant:?}");
+    let map = EnvironmentMap::new(16, 8, vec![Vector3::repeat(1.); 128]).unwrap();
+    let mapped = render(Environment::Map(map), true);
+    assert!((mapped.x - 1.).abs() < 0.05, "{mapped:?}");
+
+    // Directions are picked in proportion to the brightness of the image,
+    // with densities that integrate to one over the sphere
+    let pixels = (0..128)
+        .map(|i| Vector3::repeat(if i == 37 { 100. } else { 0.1 + (i % 5) as f64 }))
+        .collect();
+    let map = Environment::Map(EnvironmentMap::new(16, 8, pixels).unwrap());
+    let samples = 100_000;
+    let mut integral = 0.;
+    let mut bright = 0;
+    for _ in 0..samples {
+        let u = Vector2::new(rand::random::<f64>(), rand::random::<f64>());
+        integral += map.pdf_li(Vector3::zeros(), uniform_sphere(u)) * 4. * PI;
+
+        let sample = map.sample_li(Vector3::zeros(), u).unwrap();
+        assert!((sample.pdf / map.pdf_li(Vector3::zeros(), sample.direction) - 1.).abs() < 1e-6);
+        if sample.radiance.x == 100. {
+            bright += 1;
+        }
+    }
+    assert!((integral / samples as f64 - 1.).abs() < 0.02);
+    // The single bright pixel holds a large share of the total power
+    assert!(bright > samples / 10);
+
+    // Maps are loaded from HDR and EXR files
+    let mut config = Config::new(4, 2, 1, 1);
+    config.filter = FilterKind::Box;
+    config.filter_radius = 0.5;
+    let mut image = Render::new(config);
+    for x in 0..4 {
+        for y in 0..2 {
+            image.add_sample(
+                Vector2::new(x as f64 + 0.5, y as f64 + 0.5),
+                Vector3::new(x as f64 * 4., 0.5, y as f64),
+            );
+        }
+    }
+    let from_exr = EnvironmentMap::load(&image.to_exr(ExrPrecision::Float).unwrap()).unwrap();
+    let from_hdr = EnvironmentMap::load(&image.to_hdr()).unwrap();
+    // Looking along +X at the horizon shows the first pixel of the second row
+    let direction = Vector3::new(1., -0.1, 0.01);
+    assert_eq!(from_exr.radiance(direction), Vector3::new(0., 0.5, 1.));
+    assert!((from_hdr.radiance(direction) - Vector3::new(0., 0.5, 1.)).norm() < 0.01);
+    assert!(EnvironmentMap::load(b"not an image").is_err());
+
+    // Images without pixels, or too many to allocate, are rejected
+    for resolution in ["-Y 0 +X 0", "-Y 1 +X 0", "-Y 65536 +X 65536"] {
+        let header = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{resolution}\n");
+        assert!(matches!(
+            EnvironmentMap::load(header.as_bytes()),
+            Err(ImageError::InvalidSize { .. })
+        ));
+    }
+    assert!(matches!(
+        EnvironmentMap::new(0, 8, Vec::new()),
+        Err(ImageError::InvalidSize { .. })
+    ));
+    assert!(EnvironmentMap::new(2, 2, vec![Vector3::zeros(); 3]).is_err());
+
+    // Run length encoded scanline, with every component stored as a single run
+    let mut hdr = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 8\n".to_vec();
+    hdr.extend_from_slice(&[
+        2, 2, 0, 8, 136, 128, 136, 64, 4, 0, 0, 0, 0, 132, 0, 136, 129,
+    ]);
+    let (width, height, pixels) = read_hdr(&hdr).unwrap();
+    assert_eq!((width, height), (8, 1));
+    assert!((pixels[7] - Vector3::new(128.5, 64.5, 0.5) / 128.).norm() < 1e-9);
+}
+
+#[test]
+// The sun stands where it should for the date and place, the sky and sun give
+// daylight illuminance that changes with haze, and the disk shows in mirrors
+fn physical_sky() {
+    use std::f64::consts::PI;
+
+    // At the equinox the noon sun stands at 90° minus the latitude, in the south
+    let (elevation, azimuth) = sun_position(45f64.to_radians(), 0., 80., 12.12);
+    assert!(
+        (elevation.to_degrees() - 45.).abs() < 1.5,
+        "{}",
+        elevation.to_degrees()
+    );
+    assert!(
+        (azimuth.to_degrees() - 180.).abs() < 3.,
+        "{}",
+        azimuth.to_degrees()
+    );
+    // and rises in the east
+    let (elevation, azimuth) = sun_position(45f64.to_radians(), 0., 80., 8.);
+    assert!(elevation > 0. && azimuth.to_degrees() > 90. && azimuth.to_degrees() < 135.);
+
+    let sky = Sky::new(60f64.to_radians(), PI, 3.);
+    assert!((sky.sun_direction() - Vector3::new(0., 0.75f64.sqrt(), 0.5)).norm() < 1e-9);
+    assert_eq!(sky.radiance(Vector3::new(0., -1., 0.)), Vector3::zeros());
+    let towards_sun = Vector3::new(0., 0.5, 1.);
+    assert!(luminance(sky.radiance(towards_sun)) > luminance(sky.radiance(-towards_sun)) * 2.);
+
+    // Illuminance of a horizontal surface, in lux, by the sky and the sun
+    let samples = 20_000;
+    let mut sky_illuminance = 0.;
+    for _ in 0..samples {
+        let u = Vector2::new(rand::random::<f64>(), rand::random::<f64>());
+        let direction = uniform_sphere(u);
+        sky_illuminance += luminance(sky.radiance(direction)) * direction.y.max(0.) * 4. * PI;
+    }
+    let sky_illuminance = sky_illuminance / samples as f64 * 683.;
+    let sun = sky.sun();
+    let sun_illuminance = luminance(sun.irradiance) * sky.sun_direction().y * 683.;
+    assert!((5_000. ..40_000.).contains(&sky_illuminance));
+    assert!((50_000. ..120_000.).contains(&sun_illuminance));
+
+    // A hazy sky is brighter and less blue, with a dimmer and redder sun
+    let hazy = Sky::new(60f64.to_radians(), PI, 8.);
+    let blueness = |c: Vector3<f64>| c.z / c.x;
+    assert!(blueness(hazy.radiance(Vector3::y())) < blueness(sky.radiance(Vector3::y())));
+    assert!(luminance(hazy.sun().irradiance) < luminance(sun.irradiance));
+    assert!(blueness(hazy.sun().irradiance) < blueness(sun.irradiance));
+
+    // The sun sets along with the sky
+    assert_eq!(Sky::new(-0.1, 0., 3.).sun().irradiance, Vector3::zeros());
+
+    // The sun disk shows up in front of the sky, both seen straight on and in a
+    // mirror below the camera, although both lights are infinitely far away
+    let sky_brightness = luminance(sky.radiance(sky.sun_direction()));
+    let sun_brightness = |looking_at: Vector3<f64>| {
+        // The disk covers a small part of a pixel, so it takes many samples to hit it
+        let mut config = Config::new(41, 41, 2, 1024);
+        config.set_crop(19, 19, 3, 3);
+        let camera = Camera::new(Vector2::new(41, 41), Vector3::zeros(), looking_at);
+        let mut scene = Scene::new(config, camera);
+        scene.environment = Some(Environment::Sky(sky.clone()));
+        scene.lights.push(Box::new(sky.sun()));
+        scene.objects.push(Object::new(
+            Box::new(Sphere),
+            Vector3::repeat(1.),
+            Box::new(Conductor::new(ComplexIor::SILVER, 0.)),
+            TransformBuilder::new()
+                .translate_y(-1001.)
+                .scale_uniform(1000.)
+                .build(),
+        ));
+        while !scene.is_finished() {
+            scene.sample();
+        }
+        luminance(scene.render.get_pixel_averaged(20, 20))
+    };
+    let direct = sun_brightness(sky.sun_direction());
+    assert!(direct > sky_brightness * 100., "{direct} {sky_brightness}");
+    let mirrored = sun_brightness(
+        sky.sun_direction()
+            .component_mul(&Vector3::new(1., -1., 1.)),
+    );
+    assert!(
+        mirrored > sky_brightness * 100.,
+        "{mirrored} {sky_brightness}"
+    );
+}
+
+#[test]
+// Black bodies have the color of their temperature at unit luminance, and
+// emitters set by luminous power give off exactly that many lumens
+fn blackbody_emission() {
+    use std::f64::consts::PI;
+
+    // The matching functions integrate to about 106.9 over the visible range
+    let y = spectrum_to_xyz(|_| 1.).y;
+    assert!((y - 106.86).abs() < 1.5, "{y}");
+    assert!(cie_xyz(555.).y > 0.99);
+
+    // D65 is close to a black body at 6504 K, and white in sRGB
+    let white = blackbody(6504.);
+    assert!((luminance(white) - 1.).abs() < 1e-9);
+    assert!((white - Vector3::repeat(1.)).amax() < 0.1, "{white:?}");
+    // Candle light is orange, hotter bodies turn blue
+    let warm = blackbody(1900.);
+    assert!(warm.x > warm.y && warm.y > warm.z);
+    let cold = blackbody(12000.);
+    assert!(cold.z > cold.y && cold.y > cold.x);
+
+    // An emitter of known luminous power, at any color temperature
+    let mut bulb = Object::new(
+        Box::new(Sphere),
+        Vector3::new(1., 1., 1.),
+        Box::new(Lambertian),
+        TransformBuilder::new().scale_uniform(2.).build(),
+    );
+    assert!((bulb.area() - 16. * PI).abs() < 1e-9);
+    bulb.set_emission_temperature(2700., 1.);
+    bulb.set_luminous_power(800.);
+    assert!((bulb.luminous_power() - 800.).abs() < 1e-9);
+    assert_eq!(bulb.emission_color, blackbody(2700.));
+    // 800 lm spread over the surface of the sphere, 683 lm/W, from a Lambertian emitter
+    let expected = 800. / (683. * PI * 16. * PI);
+    assert!((bulb.emission_strength - expected).abs() < 1e-12);
+}
+
+#[test]
+// Colors and black bodies survive the trip through sampled spectra, and a
+// spectral render of a scene with white in it matches the RGB one
+fn spectral_rendering() {
+    use std::f64::consts::PI;
+
+    // Color of a spectrum, averaged over evenly spaced hero wavelengths
+    let to_rgb = |spectrum: &dyn Fn(&Wavelengths) -> SampledSpectrum| {
+        let steps = 500;
+        let xyz: Vector3<f64> = (0..steps)
+            .map(|i| {
+                let wavelengths = Wavelengths::sample((i as f64 + 0.5) / steps as f64);
+                wavelengths.to_color(spectrum(&wavelengths))
+            })
+            .sum();
+        xyz_to_linear_srgb(xyz / steps as f64)
+    };
+
+    // White light has the spectrum of daylight, and comes out white
+    let white = Vector3::repeat(1.);
+    assert!((to_rgb(&|w| w.illuminant(white)) - white).amax() < 1e-3);
+
+    // Colors survive the round trip through reflectance and light spectra
+    for color in [
+        Vector3::new(0.8, 0.4, 0.2),
+        Vector3::new(0.2, 0.5, 0.8),
+        Vector3::new(0.1, 0.7, 0.3),
+        Vector3::repeat(0.5),
+    ] {
+        let reflected = to_rgb(&|w| w.illuminant(white).component_mul(&w.reflectance(color)));
+        assert!((reflected - color).amax() < 0.01, "{color:?} {reflected:?}");
+    }
+    let light = Vector3::new(3., 1., 0.5);
+    let emitted = to_rgb(&|w| w.illuminant(light));
+    assert!((emitted - light).amax() < 0.03, "{emitted:?}");
+
+    // Black bodies keep their luminance
+    let warm = to_rgb(&|w| w.blackbody(2700., 2., blackbody(2700.) * 2.));
+    assert!((luminance(warm) - 2.).abs() < 0.01);
+    assert!(((warm / 2.) - blackbody(2700.)).amax() < 0.02, "{warm:?}");
+
+    // The same scene rendered in both modes agrees, since the colors of the
+    // light and the surface are only ever multiplied with white. Only the middle
+    // pixel is rendered, with enough samples to tell the modes apart
+    let render = |spectral: bool, light: Vector3<f64>, color: Vector3<f64>| {
+        let mut config = Config::new(41, 41, 2, 1024);
+        config.spectral = spectral;
+        config.set_crop(18, 18, 5, 5);
+        let camera = Camera::new(
+            Vector2::new(41, 41),
+            Vector3::new(0., 0., 0.),
+            Vector3::new(10., 0., 0.),
+        );
+        let mut scene = Scene::new(config, camera);
+        scene.objects.push(Object::new(
+            Box::new(Sphere),
+            color,
+            Box::new(Lambertian),
+            TransformBuilder::new()
+                .translate_x(10.)
+                .scale_uniform(3.)
+                .build(),
+        ));
+        scene
+            .lights
+            .push(Box::new(PointLight::new(Vector3::zeros(), light * 49.)));
+
+        while !scene.is_finished() {
+            scene.sample();
+        }
+        scene.render.get_pixel_averaged(20, 20)
+    };
+
+    let orange = Vector3::new(1., 0.6, 0.3);
+    for (light, color) in [(orange, white), (white, orange)] {
+        let rgb = render(false, light, color);
+        let spectral = render(true, light, color);
+        assert!((rgb - orange / PI).amax() < 0.01, "{rgb:?}");
+        assert!((spectral - rgb).amax() < 0.02, "{spectral:?} {rgb:?}");
+    }
+}
+
+#[test]
+// Dispersive glass bends short wavelengths more, following Snell's law for the
+// hero wavelength, and neither creates nor destroys light
+fn dispersive_glass() {
+    // Presets match the indices quoted at the d-line, and bend blue light more than red
+    for (ior, expected) in [(Ior::BK7, 1.5168), (Ior::FLINT, 1.62), (Ior::WATER, 1.333)] {
+        assert!((ior.at(Ior::REFERENCE_WAVELENGTH) - expected).abs() < 1e-3);
+        assert!(ior.at(450.) > ior.at(650.));
+    }
+    assert!(Ior::FLINT.at(450.) - Ior::FLINT.at(650.) > Ior::BK7.at(450.) - Ior::BK7.at(650.));
+
+    // A ray entering flint glass at 45° is refracted by Snell's law, following
+    // the hero wavelength, which is the only one left to carry light
+    let refract = |wavelength: f64| loop {
+        let glass = Dielectric::new(Ior::FLINT);
+        let mut ray = Ray {
+            origin: Vector3::new(-1., 1., 0.),
+            direction: Vector3::new(1., -1., 0.).normalize(),
+        };
+        let mut wavelengths = Wavelengths::Sampled([wavelength, 500., 600., 700.]);
+        let weight = glass.scatter(
+            &mut ray,
+            Vector3::zeros(),
+            Vector3::y(),
+            &TextureCoordinates::default(),
+            &mut wavelengths,
+        );
+        assert_eq!(weight, SampledSpectrum::new(4., 0., 0., 0.));
+        assert_eq!(
+            wavelengths,
+            Wavelengths::Hero([wavelength, 500., 600., 700.])
+        );
+
+        if ray.direction.y < 0. {
+            let sin = ray.direction.normalize().x;
+            assert!((sin * Ior::FLINT.at(wavelength) - 0.5f64.sqrt()).abs() < 1e-9);
+            return sin;
+        }
+    };
+    assert!(refract(450.) < refract(650.));
+
+    // Glass neither creates nor destroys light: a glass ball surrounded by
+    // a constant environment is invisible, in both modes
+    for (spectral, ior) in [
+        (false, Ior::BK7),
+        (true, Ior::FLINT),
+        (true, Ior::Constant(1.5)),
+    ] {
+        let mut config = Config::new(21, 21, 32, 256);
+        config.spectral = spectral;
+        let camera = Camera::new(
+            Vector2::new(21, 21),
+            Vector3::new(0., 0., 0.),
+            Vector3::new(10., 0., 0.),
+        );
+        let mut scene = Scene::new(config, camera);
+        scene.objects.push(Object::new(
+            Box::new(Sphere),
+            Vector3::new(1., 1., 1.),
+            Box::new(Dielectric::new(ior)),
+            TransformBuilder::new()
+                .translate_x(10.)
+                .scale_uniform(3.)
+                .build(),
+        ));
+        scene.environment = Some(Environment::Constant(Vector3::repeat(1.)));
+
+        while !scene.is_finished() {
+            scene.sample();
+        }
+        // Only the hero wavelength makes it through dispersive glass, which is
+        // very noisy in color, so average the middle of the ball
+        let mut pixel = Vector3::zeros();
+        for x in 8..13 {
+            for y in 8..13 {
+                pixel += scene.render.get_pixel_averaged(x, y) / 25.;
+            }
+        }
+        assert!((luminance(pixel) - 1.).abs() < 0.05, "{ior:?} {pixel:?}");
+        assert!(
+            (pixel - Vector3::repeat(1.)).amax() < 0.15,
+            "{ior:?} {pixel:?}"
+        );
+    }
+}
+
+#[test]
+// Rough metal and glass never reflect more light than arrives, with sampled
+// weights matching eval and pdf, and gold turns white at grazing angles
+fn microfacet_furnace() {
+    use std::f64::consts::PI;
+
+    // Directions spread evenly over the sphere, jittered in a grid of cells, which
+    // is much less noisy than picking them independently
+    let cells = 200;
+    let samples = cells * cells;
+    let uniform = |k: usize| {
+        let cell = Vector2::new((k % cells) as f64, (k / cells) as f64);
+        uniform_sphere((cell + Vector2::new(rand::random(), rand::random())) / cells as f64)
+    };
+
+    // Weak white furnace: the visible normals cover exactly the projected area
+    for roughness in [0.5, 0.75, 1.] {
+        let distribution = TrowbridgeReitz::new(roughness);
+        for o in [Vector3::z(), Vector3::new(0.8, 0., 0.6)] {
+            let total: f64 = (0..samples)
+                .map(|k| distribution.visible_distribution(o, uniform(k)) * 4. * PI)
+                .sum();
+            assert!(
+                (total / samples as f64 - 1.).abs() < 0.02,
+                "{roughness} {total}"
+            );
+        }
+    }
+
+    // Sampled normals follow the visible distribution: the mean of the sampled
+    // normals matches the mean under the density
+    let distribution = TrowbridgeReitz::new(0.6);
+    let o = Vector3::new(0.6, 0., 0.8);
+    let sampled: Vector3<f64> = (0..samples)
+        .map(|_| {
+            distribution.sample_visible_normal(o, Vector2::new(rand::random(), rand::random()))
+        })
+        .sum::<Vector3<f64>>()
+        / samples as f64;
+    let expected: Vector3<f64> = (0..samples)
+        .map(|k| {
+            let h = uniform(k);
+            h * distribution.visible_distribution(o, h) * 4. * PI
+        })
+        .sum::<Vector3<f64>>()
+        / samples as f64;
+    assert!(
+        (sampled - expected).amax() < 0.02,
+        "{sampled:?} {expected:?}"
+    );
+
+    // White furnace: the fraction of the light arriving from `outgoing` which
+    // leaves in any direction, following scattered rays. Their weights have to
+    // match eval and pdf, and pdf has to integrate to the fraction of rays which
+    // scatter to the right side of the surface
+    let furnace = |material: &dyn Material, outgoing: Vector3<f64>| {
+        let mut albedo = 0.;
+        let mut kept = 0.;
+        let mut pdf_total = 0.;
+        for k in 0..samples {
+            let mut ray = Ray {
+                origin: outgoing,
+                direction: -outgoing,
+            };
+            let weight = material
+                .scatter(
+                    &mut ray,
+                    Vector3::zeros(),
+                    Vector3::z(),
+                    &TextureCoordinates::default(),
+                    &mut Wavelengths::Rgb,
+                )
+                .x;
+            if weight > 0. {
+                let incoming = ray.direction;
+                let eval = material.eval(
+                    outgoing,
+                    incoming,
+                    Vector3::z(),
+                    &TextureCoordinates::default(),
+                    &Wavelengths::Rgb,
+                );
+                let pdf = material.pdf(
+                    outgoing,
+                    incoming,
+                    Vector3::z(),
+                    &TextureCoordinates::default(),
+                );
+                let expected = eval.x * incoming.z.abs() / pdf;
+                assert!((weight - expected).abs() < 1e-6, "{weight} {expected}");
+                albedo += weight;
+                kept += 1.;
+            }
+
+            pdf_total += material.pdf(
+                outgoing,
+                uniform(k),
+                Vector3::z(),
+                &TextureCoordinates::default(),
+            ) * 4.
+                * PI;
+        }
+        let n = samples as f64;
+        (albedo / n, kept / n, pdf_total / n)
+    };
+
+    // A metal which reflects everything only loses the light which single
+    // scattering leaves out, when microfacets shadow each other. Rough glass
+    // splits the light between reflection and refraction, from both sides
+    let mirror = ComplexIor::new([1.; 3], [1e4; 3]);
+    let outside = Vector3::new(0.6, 0., 0.8);
+    let inside = Vector3::new(0.3, 0., -0.95).normalize();
+    for roughness in [0.1, 0.6, 1.] {
+        let conductor = Conductor::new(mirror, roughness);
+        let glass = RoughDielectric::new(1.5, roughness);
+        for (material, outgoing) in [
+            (&conductor as &dyn Material, outside),
+            (&glass, outside),
+            (&glass, inside),
+        ] {
+            let (albedo, kept, pdf) = furnace(material, outgoing);
+            assert!(albedo <= 1., "{material:?} {albedo}");
+            if roughness < 0.2 {
+                assert!(albedo > 0.99, "{material:?} {albedo}");
+            } else {
+                assert!((pdf - kept).abs() < 0.02, "{material:?} {pdf} {kept}");
+            }
+        }
+    }
+
+    // Gold is yellow, and gets white at grazing angles
+    let gold = Conductor::new(ComplexIor::GOLD, 0.2);
+    let color = |outgoing: Vector3<f64>| {
+        let incoming = Vector3::new(-outgoing.x, 0., outgoing.z);
+        gold.eval(
+            outgoing,
+            incoming,
+            Vector3::z(),
+            &TextureCoordinates::default(),
+            &Wavelengths::Rgb,
+        )
+    };
+    let head_on = color(Vector3::new(0.01, 0., 1.).normalize());
+    assert!(
+        head_on.x > head_on.y && head_on.y > 2. * head_on.z,
+        "{head_on:?}"
+    );
+    let grazing = color(Vector3::new(1., 0., 0.05).normalize());
+    assert!(grazing.z / grazing.x > 0.8, "{grazing:?}");
+
+    // In a white furnace, a rough glass ball only loses the little light the
+    // microfacets shadow, through the light sampling the environment and the
+    // scattered rays weighted together
+    let mut config = Config::new(21, 21, 32, 64);
+    config.set_crop(8, 8, 5, 5);
+    let camera = Camera::new(
+        Vector2::new(21, 21),
+        Vector3::new(0., 0., 0.),
+        Vector3::new(10., 0., 0.),
+    );
+    let mut scene = Scene::new(config, camera);
+    scene.objects.push(Object::new(
+        Box::new(Sphere),
+        Vector3::new(1., 1., 1.),
+        Box::new(RoughDielectric::new(1.5, 0.3)),
+        TransformBuilder::new()
+            .translate_x(10.)
+            .scale_uniform(3.)
+            .build(),
+    ));
+    scene.environment = Some(Environment::Constant(Vector3::repeat(1.)));
+    while !scene.is_finished() {
+        scene.sample();
+    }
+    let mut pixel = Vector3::zeros();
+    for x in 8..13 {
+        for y in 8..13 {
+            pixel += scene.render.get_pixel_averaged(x, y) / 25.;
+        }
+    }
+    assert!(pixel.max() < 1.02 && pixel.min() > 0.93, "{pixel:?}");
+}
+
+#[test]
+// Every mix of the principled lobes keeps energy, metals take on the base color,
+// and glTF and MTL materials convert to their principled counterparts
+fn principled_material() {
+    // Scattered rays are weighted by eval and pdf, and never reflect more
+    // light than arrives, whichever lobes are mixed
+    let white = Principled::new(Vector3::repeat(1.));
+    let materials = [
+        white.clone(),
+        Principled {
+            metallic: 1.,
+            roughness: 0.3,
+            ..white.clone()
+        },
+        Principled {
+            transmission: 1.,
+            roughness: 0.2,
+            ..white.clone()
+        },
+        Principled {
+            clearcoat: 1.,
+            sheen: 1.,
+            roughness: 0.8,
+            ..white.clone()
+        },
+        Principled {
+            metallic: 0.5,
+            transmission: 0.5,
+            specular: 1.,
+            ..white.clone()
+        },
+    ];
+    let samples = 20000;
+    for material in &materials {
+        for outgoing in [Vector3::new(0.6, 0., 0.8), Vector3::new(0.95, 0., 0.1)] {
+            let outgoing = outgoing.normalize();
+            let mut albedo = 0.;
+            for _ in 0..samples {
+                let mut ray = Ray {
+                    origin: outgoing,
+                    direction: -outgoing,
+                };
+                let weight = material
+                    .scatter(
+                        &mut ray,
+                        Vector3::zeros(),
+                        Vector3::z(),
+                        &TextureCoordinates::default(),
+                        &mut Wavelengths::Rgb,
+                    )
+                    .x;
+                if weight > 0. {
+                    let incoming = ray.direction;
+                    let eval = material.eval(
+                        outgoing,
+                        incoming,
+                        Vector3::z(),
+                        &TextureCoordinates::default(),
+                        &Wavelengths::Rgb,
+                    );
+                    let pdf = material.pdf(
+                        outgoing,
+                        incoming,
+                        Vector3::z(),
+                        &TextureCoordinates::default(),
+                    );
+                    let expected = eval.x * incoming.z.abs() / pdf;
+                    assert!((weight - expected).abs() < 1e-6, "{weight} {expected}");
+                }
+                albedo += weight;
+            }
+            let albedo = albedo / samples as f64;
+            assert!(albedo > 0.5 && albedo < 1.03, "{material:?} {albedo}");
+        }
+    }
+
+    // Metals take on the base color, dielectrics keep their highlights white
+    let gold = Vector3::new(1., 0.78, 0.34);
+    let outgoing = Vector3::new(0.1, 0., 1.).normalize();
+    let incoming = Vector3::new(-outgoing.x, 0., outgoing.z);
+    let metal = Principled {
+        metallic: 1.,
+        roughness: 0.2,
+        ..Principled::new(gold)
+    };
+    let color = metal.eval(
+        outgoing,
+        incoming,
+        Vector3::z(),
+        &TextureCoordinates::default(),
+        &Wavelengths::Rgb,
+    );
+    assert!((color.xyz() / color.x - gold).amax() < 1e-6, "{color:?}");
+    let plastic = Principled {
+        roughness: 0.2,
+        ..Principled::new(Vector3::new(0., 0., 0.5))
+    };
+    let color = plastic.eval(
+        outgoing,
+        incoming,
+        Vector3::z(),
+        &TextureCoordinates::default(),
+        &Wavelengths::Rgb,
+    );
+    assert!(color.x > 0. && color.x == color.y, "{color:?}");
+
+    // glTF dielectrics reflect 4%, which is the default specular
+    let gltf = Principled::from(&GltfMaterial {
+        base_color_factor: [0.5, 0.25, 1., 1.],
+        metallic_factor: 0.,
+        emissive_factor: [1., 0.5, 0.],
+        emissive_strength: 2.,
+        ..Default::default()
+    });
+    assert!((gltf.specular - 0.5).abs() < 1e-9);
+    assert!((gltf.ior() - 1.5).abs() < 1e-9);
+    assert_eq!(
+        gltf.base_color.evaluate(&TextureCoordinates::default()),
+        Vector3::new(0.5, 0.25, 1.)
+    );
+    assert_eq!(gltf.emission, Vector3::new(2., 1., 0.));
+    assert_eq!(Principled::from(&GltfMaterial::default()).metallic, 1.);
+
+    // MTL materials without a specular color have no highlights
+    let mut plain = MtlMaterial::new("plain");
+    plain.specular = Vector3::zeros();
+    assert_eq!(Principled::from(&plain).specular, 0.);
+
+    // Emission of the material adds to the one of the object
+    let object = Object::new(
+        Box::new(Sphere),
+        Vector3::repeat(1.),
+        Box::new(Principled {
+            emission: Vector3::new(1., 2., 3.),
+            ..Default::default()
+        }),
+        TransformBuilder::new().build(),
+    );
+    assert!(object.is_emissive());
+    assert_eq!(
+        object.emission(&Wavelengths::Rgb).xyz(),
+        Vector3::new(1., 2., 3.)
+    );
+}
+
+#[test]
+// Procedural and image textures give the colors they should, wrapping outside
+// of the image, and follow the object they are mapped onto
+fn textures() {
+    let at = |u: f64, v: f64, point: Vector3<f64>| TextureCoordinates {
+        point,
+        uv: Vector2::new(u, v),
+    };
+    let (black, white) = (Vector3::zeros(), Vector3::repeat(1.));
+
+    // Squares on the surface, and cubes in space, also on the negative side
+    let checker = Checker::new(white, black, 10., CheckerSpace::Uv);
+    assert_eq!(checker.evaluate(&at(0.05, 0.05, white)), white);
+    assert_eq!(checker.evaluate(&at(0.15, 0.05, white)), black);
+    assert_eq!(checker.evaluate(&at(0.15, 0.15, white)), white);
+    let checker = Checker::new(white, black, 1., CheckerSpace::Object);
+    assert_eq!(checker.evaluate(&at(0., 0., Vector3::repeat(0.5))), white);
+    assert_eq!(
+        checker.evaluate(&at(0., 0., Vector3::new(0.5, 0.5, -0.5))),
+        black
+    );
+    assert_eq!(checker.evaluate(&at(0., 0., Vector3::repeat(-0.5))), black);
+
+    // Noise is 0 at the integer points, continuous, and the same for the same seed
+    let perlin = Perlin::new(7);
+    assert_eq!(perlin.noise(Vector3::new(3., -2., 5.)), 0.);
+    let mut differs = false;
+    for _ in 0..1000 {
+        let point = Vector3::new(rand::random(), rand::random(), rand::random()) * 20.;
+        let noise = perlin.noise(point);
+        assert!(noise.abs() <= 1.1, "{noise}");
+        assert!((noise - perlin.noise(point + Vector3::repeat(1e-6))).abs() < 1e-4);
+        assert_eq!(noise, Perlin::new(7).noise(point));
+        differs |= noise != Perlin::new(8).noise(point);
+    }
+    assert!(differs);
+
+    // Procedural textures stay between their colors, and vary
+    let (low, high) = (Vector3::new(0.1, 0.2, 0.3), Vector3::new(0.9, 0.8, 0.7));
+    let procedural: [&dyn Texture; 3] = [
+        &Noise::new(low, high, 0.5),
+        &Marble::new(low, high, 0.5),
+        &Wood::new(low, high, 0.1),
+    ];
+    for texture in procedural {
+        let colors: Vec<Vector3<f64>> = (0..200)
+            .map(|_| {
+                let point = Vector3::new(rand::random(), rand::random(), rand::random()) * 4.;
+                texture.evaluate(&at(0., 0., point))
+            })
+            .collect();
+        for color in &colors {
+            assert!(
+                color
+                    .iter()
+                    .zip(low.iter().zip(high.iter()))
+                    .all(|(c, (l, h))| { c >= &(l.min(*h) - 1e-9) && c <= &(l.max(*h) + 1e-9) }),
+                "{texture:?} {color:?}"
+            );
+        }
+        let spread = colors.iter().map(|c| c.x).fold(f64::NAN, f64::max)
+            - colors.iter().map(|c| c.x).fold(f64::NAN, f64::min);
+        assert!(spread > 0.2, "{texture:?} {spread}");
+    }
+
+    // Pixel centers get their own color, halfway between pixels gets the average
+    let pixels = vec![
+        Vector3::new(1., 0., 0.),
+        Vector3::new(0., 1., 0.),
+        Vector3::new(0., 0., 1.),
+        Vector3::new(1., 1., 1.),
+    ];
+    let mut image = ImageTexture::new(2, 2, pixels.clone());
+    let sample = |image: &ImageTexture, u: f64, v: f64| image.evaluate(&at(u, v, black));
+    assert_eq!(sample(&image, 0.25, 0.75), pixels[0]);
+    assert_eq!(sample(&image, 0.75, 0.25), pixels[3]);
+    assert!((sample(&image, 0.5, 0.75) - Vector3::new(0.5, 0.5, 0.)).amax() < 1e-12);
+
+    // Outside of the image, by the wrap mode
+    assert!((sample(&image, 1.25, -0.25) - pixels[0]).amax() < 1e-12);
+    assert!((sample(&image, 0., 0.75) - Vector3::new(0.5, 0.5, 0.)).amax() < 1e-12);
+    image.wrap = WrapMode::MirroredRepeat;
+    assert!((sample(&image, 1.25, 0.75) - pixels[1]).amax() < 1e-12);
+    assert!((sample(&image, 0., 0.75) - pixels[0]).amax() < 1e-12);
+    image.wrap = WrapMode::ClampToEdge;
+    assert!((sample(&image, 7., 0.25) - pixels[3]).amax() < 1e-12);
+    assert!((sample(&image, -3., 5.) - pixels[0]).amax() < 1e-12);
+
+    // PNG and JPEG files hold sRGB encoded colors
+    let encode = |format| {
+        let image = image::RgbImage::from_fn(8, 8, |x, _| {
+            if x < 4 {
+                image::Rgb([255, 128, 0])
+            } else {
+                image::Rgb([0, 0, 255])
+            }
+        });
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut bytes, format).unwrap();
+        bytes.into_inner()
+    };
+    let orange = Vector3::new(1., srgb_decode(128. / 255.), 0.);
+    let png = ImageTexture::load(&encode(image::ImageFormat::Png)).unwrap();
+    assert!((sample(&png, 0.2, 0.5) - orange).amax() < 1e-6);
+    assert!((sample(&png, 0.8, 0.5) - Vector3::z()).amax() < 1e-6);
+    let jpeg = ImageTexture::load(&encode(image::ImageFormat::Jpeg)).unwrap();
+    assert!((sample(&jpeg, 0.1, 0.5) - orange).amax() < 0.1);
+    assert!(matches!(
+        ImageTexture::load(b"GIF89a"),
+        Err(ImageError::UnknownFormat)
+    ));
+    assert!(matches!(
+        ImageTexture::load(b"\x89PNG broken"),
+        Err(ImageError::Decode(_))
+    ));
+
+    // Spheres are mapped like the world map, and solid textures move with the object
+    let object = Object::new(
+        Box::new(Sphere),
+        white,
+        Box::new(Lambertian),
+        TransformBuilder::new()
+            .translate(Vector3::new(10., 0., 0.))
+            .scale_uniform(2.)
+            .build(),
+    );
+    let coordinates = object.texture_coordinates(Vector3::new(12., 0., 0.));
+    assert!((coordinates.point - Vector3::x()).amax() < 1e-12);
+    assert!((coordinates.uv - Vector2::new(0., 0.5)).amax() < 1e-12);
+    let uv = object.texture_coordinates(Vector3::new(10., 0., -2.)).uv;
+    assert!((uv - Vector2::new(0.25, 0.5)).amax() < 1e-12);
+    let uv = object.texture_coordinates(Vector3::new(10., 2., 0.)).uv;
+    assert!((uv.y - 1.).abs() < 1e-12);
+
+    // Textured colors reach the albedo buffer
+    let checker = Arc::new(Checker::new(white, black, 1., CheckerSpace::Object));
+    // Pixels are small enough to stay within one cube
+    let mut config = Config::new(201, 201, 2, 1);
+    config.set_crop(100, 100, 1, 1);
+    config.aovs = true;
+    let material = Principled {
+        base_color: checker.clone(),
+        ..Principled::new(white)
+    };
+    // The camera sees the point (-0.7, -offset, 0.5) of the sphere
+    for (offset, expected) in [(0.5, white), (-0.5, black)] {
+        let camera = Camera::new(Vector2::new(201, 201), Vector3::zeros(), Vector3::x());
+        let mut scene = Scene::new(config.clone(), camera);
+        let mut object = Object::new(
+            Box::new(Sphere),
+            white,
+            Box::new(material.clone()),
+            TransformBuilder::new()
+                .translate(Vector3::new(10., offset, -0.5))
+                .build(),
+        );
+        object.color = checker.clone();
+        scene.objects.push(object);
+        scene.sample();
+        let albedo = scene.render.get_aov(Aov::Albedo).unwrap()[100 * 201 + 100];
+        assert!((albedo - expected).amax() < 1e-9, "{albedo:?}");
+    }
+}
+
+#[test]
+// Sphere UVs and tangents agree with each other everywhere, including the seam
+// and the poles, and follow the object's transform
+fn uv_parameterization() {
+    use std::f64::consts::PI;
+
+    let sphere = Sphere::new();
+    let normalize_u = |du: f64| du - du.round();
+
+    // Moving a little along dp/du or dp/dv moves the UVs by as much, and the
+    // tangents are perpendicular to the normal, with their cross product facing out
+    let epsilon = 1e-6;
+    for _ in 0..1000 {
+        let point = uniform_sphere(Vector2::new(rand::random(), rand::random()));
+        let uv = sphere.uv(point);
+        let (dpdu, dpdv) = sphere.tangents(point);
+        let normal = sphere.normal(point);
+        assert!(dpdu.dot(&normal).abs() < 1e-9 && dpdv.dot(&normal).abs() < 1e-9);
+        assert!(dpdu.cross(&dpdv).dot(&normal) >= 0.);
+
+        let along_u = sphere.uv((point + dpdu * epsilon).normalize()) - uv;
+        let along_v = sphere.uv((point + dpdv * epsilon).normalize()) - uv;
+        let tolerance = epsilon * 1e-2;
+        assert!(
+            (normalize_u(along_u.x) - epsilon).abs() < tolerance,
+            "{point:?}"
+        );
+        assert!(along_u.y.abs() < tolerance, "{point:?}");
+        assert!(normalize_u(along_v.x).abs() < tolerance, "{point:?}");
+        assert!((along_v.y - epsilon).abs() < tolerance, "{point:?}");
+    }
+
+    // u wraps around at the seam, where the tangents stay the same on both sides
+    let before = Vector3::new(1., 0.3, 1e-9).normalize();
+    let after = Vector3::new(1., 0.3, -1e-9).normalize();
+    let (u_before, u_after) = (sphere.uv(before).x, sphere.uv(after).x);
+    assert!(
+        u_before > 0.999_999 && u_after < 1e-6,
+        "{u_before} {u_after}"
+    );
+    let (dpdu_before, dpdv_before) = sphere.tangents(before);
+    let (dpdu_after, dpdv_after) = sphere.tangents(after);
+    assert!((dpdu_before - dpdu_after).amax() < 1e-6);
+    assert!((dpdv_before - dpdv_after).amax() < 1e-6);
+
+    // At the poles, v reaches 1 and 0, u stays finite, dp/du vanishes and dp/dv
+    // still points along the surface, towards the seam
+    for (pole, v) in [(Vector3::y(), 1.), (-Vector3::y(), 0.)] {
+        let uv = sphere.uv(pole);
+        assert!(uv.x.is_finite() && (uv.y - v).abs() < 1e-12, "{uv:?}");
+        let (dpdu, dpdv) = sphere.tangents(pole);
+        assert!(dpdu.norm() < 1e-12, "{dpdu:?}");
+        assert!(
+            (dpdv.norm() - PI).abs() < 1e-9 && dpdv.y.abs() < 1e-9,
+            "{dpdv:?}"
+        );
+        let seam = (pole + dpdv.normalize() * (1. - v * 2.).signum() * 1e-3).normalize();
+        assert!(sphere.uv(seam).x.abs() < 1e-9, "{seam:?}");
+    }
+
+    // Tangents follow the object as it moves, turns and stretches
+    let object = Object::new(
+        Box::new(Sphere),
+        Vector3::repeat(1.),
+        Box::new(Lambertian),
+        TransformBuilder::new()
+            .translate(Vector3::new(5., 0., 0.))
+            .scale(Vector3::new(2., 1., 1.))
+            .rotate_y(0.3)
+            .build(),
+    );
+    let point = object
+        .transform
+        .apply(Vector3::new(0.6, 0.8, 0.).normalize());
+    let (dpdu, dpdv) = object.tangents(point);
+    let normal = object.normal(point);
+    assert!(dpdu.dot(&normal).abs() < 1e-9 && dpdv.dot(&normal).abs() < 1e-9);
+    assert!(dpdu.cross(&dpdv).dot(&normal) > 0.);
+    let uv = object.texture_coordinates(point).uv;
+    let moved = object.texture_coordinates(point + dpdv * epsilon).uv;
+    assert!(((moved - uv).y - epsilon).abs() < epsilon * 1e-2);
+}
+
+#[test]
+// Normal and bump maps tilt the shading normal by as much as they should, also
+// at the poles, and never let light leak through the surface
+fn normal_mapping() {
+    // Heights growing along u
+    #[derive(Debug)]
+    struct Ramp;
+    impl Texture for Ramp {
+        fn evaluate(&self, coordinates: &TextureCoordinates) -> Vector3<f64> {
+            Vector3::repeat(coordinates.uv.x)
+        }
+    }
+
+    let with_map = |normal_map: NormalMap| Object {
+        normal_map: Some(normal_map),
+        transform: TransformBuilder::new().build(),
+        ..Default::default()
+    };
+    let tangent = |color: Vector3<f64>, strength: f64| NormalMap::Tangent {
+        texture: Arc::new(SolidColor(color)),
+        strength,
+    };
+    let bump = |height: Arc<dyn Texture>, scale: f64| NormalMap::Bump { height, scale };
+
+    // On the equator at +X, the normal is +X and dp/du points towards -Z
+    let point = Vector3::x();
+    let flat = [
+        tangent(Vector3::new(0.5, 0.5, 1.), 1.),
+        bump(Arc::new(SolidColor(Vector3::repeat(0.3))), 5.),
+        bump(Arc::new(Ramp), 0.),
+    ];
+    for normal_map in flat {
+        let surface = with_map(normal_map).surface(point);
+        assert!((surface.shading_normal - Vector3::x()).norm() < 1e-9);
+        assert_eq!(surface.normal, Vector3::x());
+    }
+
+    // Tilted 30° towards dp/du, and twice as far with twice the strength
+    let encoded = Vector3::new(0.5, 0., 0.75f64.sqrt()).map(|c| c * 0.5 + 0.5);
+    let surface = with_map(tangent(encoded, 1.)).surface(point);
+    let expected = Vector3::new(0.75f64.sqrt(), 0., -0.5);
+    assert!((surface.shading_normal - expected).norm() < 1e-9);
+    let stronger = with_map(tangent(encoded, 2.)).surface(point).shading_normal;
+    assert!((stronger.z.atan2(stronger.x) - (-1f64).atan2(0.75f64.sqrt())).abs() < 1e-9);
+
+    // Heights rising along u tilt the normal away from dp/du, which is 2π long,
+    // so rising by 2π per unit of u tilts it by 45°
+    let surface = with_map(bump(Arc::new(Ramp), 2. * std::f64::consts::PI)).surface(point);
+    let expected = Vector3::new(1., 0., 1.).normalize();
+    assert!(
+        (surface.shading_normal - expected).norm() < 1e-3,
+        "{surface:?}"
+    );
+
+    // The poles, where dp/du vanishes, still get a tilt, and stay on the outside
+    for pole in [Vector3::y(), -Vector3::y()] {
+        let normal = with_map(tangent(encoded, 1.)).surface(pole).shading_normal;
+        assert!(
+            (normal.dot(&pole) - 0.75f64.sqrt()).abs() < 1e-9,
+            "{normal:?}"
+        );
+        let normal = with_map(bump(
+            Arc::new(Noise::new(Vector3::zeros(), Vector3::repeat(1.), 0.1)),
+            0.1,
+        ))
+        .surface(pole)
+        .shading_normal;
+        assert!(normal.iter().all(|c| c.is_finite()) && normal.dot(&pole) > 0.);
+    }
+
+    // Maps are applied in object space and follow the object around
+    let mut object = with_map(tangent(encoded, 1.));
+    object.transform = TransformBuilder::new()
+        .translate(Vector3::new(3., 0., 0.))
+        .rotate_y(std::f64::consts::FRAC_PI_2)
+        .build();
+    let world_point = object.transform.apply(point);
+    let surface = object.surface(world_point);
+    let rotated = object
+        .transform
+        .apply_normal(Vector3::new(0.75f64.sqrt(), 0., -0.5));
+    assert!((surface.shading_normal - rotated).norm() < 1e-9);
+
+    // Directions which the shading normal puts on the other side than the surface does
+    let surface = with_map(tangent(Vector3::new(1., 0.5, 0.55), 20.)).surface(point);
+    let outgoing = Vector3::new(1., 0., 0.2).normalize();
+    assert!(surface.is_consistent(outgoing, Vector3::new(1., 0., 0.5).normalize()));
+    assert!(!surface.is_consistent(outgoing, Vector3::new(-0.3, 0., 0.5).normalize()));
+
+    // Inside of a closed room, whose walls are tilted so much that the material
+    // scatters a lot of light towards them, nothing of the bright outside gets in
+    let mut config = Config::new(9, 9, 4, 8);
+    config.set_crop(2, 2, 5, 5);
+    let camera = Camera::new(Vector2::new(9, 9), Vector3::zeros(), Vector3::x());
+    let mut scene = Scene::new(config, camera);
+    let mut room = Object::new(
+        Box::new(Sphere),
+        Vector3::repeat(1.),
+        Box::new(Principled {
+            roughness: 1.,
+            ..Principled::new(Vector3::repeat(1.))
+        }),
+        TransformBuilder::new().scale_uniform(20.).build(),
+    );
+    room.normal_map = Some(NormalMap::Tangent {
+        texture: Arc::new(Checker::new(
+            Vector3::new(1., 0.5, 0.55),
+            Vector3::new(0.5, 0., 0.55),
+            20.,
+            CheckerSpace::Uv,
+        )),
+        strength: 20.,
+    });
+    scene.objects.push(room);
+    scene.environment = Some(Environment::Constant(Vector3::repeat(1.)));
+    while !scene.is_finished() {
+        scene.sample();
+    }
+    for x in 2..7 {
+        for y in 2..7 {
+            assert_eq!(scene.render.get_pixel_averaged(x, y), Vector3::zeros());
+        }
+    }
+}
+
+#[test]
+// Media fade light by Beer-Lambert and scatter it without losing any beyond
+// their albedo, both for camera paths and for shadow rays
+fn participating_media() {
+    use std::f64::consts::PI;
+
+    // The phase function integrates to one over the sphere, and sampling it
+    // follows it exactly, with the mean cosine being the asymmetry
+    let direction = Vector3::new(1., 2., -0.5).normalize();
+    for asymmetry in [0., 0.7, -0.3] {
+        let medium = Medium::homogeneous(Vector3::zeros(), Vector3::repeat(1.), asymmetry);
+        let samples = 100_000;
+        let mut integral = 0.;
+        let mut mean_cosine = 0.;
+        for _ in 0..samples {
+            let u = Vector2::new(rand::random::<f64>(), rand::random::<f64>());
+            integral += medium.phase(direction, uniform_sphere(u)) * 4. * PI;
+
+            let scattered = medium.sample_phase(direction, u);
+            assert!((scattered.norm() - 1.).abs() < 1e-9);
+            mean_cosine += scattered.dot(&direction);
+        }
+        assert!((integral / samples as f64 - 1.).abs() < 0.03, "{asymmetry}");
+        assert!(
+            (mean_cosine / samples as f64 - asymmetry).abs() < 0.01,
+            "{asymmetry}"
+        );
+    }
+
+    // Light fades with the distance as given by Beer-Lambert, exactly through
+    // homogeneous media, and on average through heterogeneous ones
+    let mut rng = rand::rng();
+    let ray = Ray {
+        origin: Vector3::zeros(),
+        direction: Vector3::x(),
+    };
+    let extinction = Vector3::new(0.1, 0.2, 0.4);
+    let homogeneous = Medium::homogeneous(extinction * 0.5, extinction * 0.5, 0.);
+    let passed = homogeneous.transmittance(&ray, 3., None, &Wavelengths::Rgb, &mut rng);
+    assert!((passed.xyz() - extinction.map(|e| (-e * 3.).exp())).norm() < 1e-12);
+    assert_eq!(
+        homogeneous.transmittance(&ray, f64::INFINITY, None, &Wavelengths::Rgb, &mut rng),
+        SampledSpectrum::zeros()
+    );
+    let heterogeneous = Medium::heterogeneous(
+        extinction * 0.5,
+        extinction * 0.5,
+        0.,
+        Arc::new(SolidColor(Vector3::repeat(0.5))),
+        2.,
+    );
+    let samples = 20_000;
+    let mut average = Vector3::zeros();
+    for _ in 0..samples {
+        let passed = heterogeneous.transmittance(&ray, 3., None, &Wavelengths::Rgb, &mut rng);
+        average += passed.xyz() / samples as f64;
+    }
+    assert!((average - extinction.map(|e| (-e * 1.5).exp())).norm() < 0.01);
+
+    // Light gets scattered before passing through as often as it fades, and
+    // every scattered path carries the albedo of the medium
+    let gray = Medium::homogeneous(Vector3::repeat(0.1), Vector3::repeat(0.3), 0.);
+    let mut passed = 0;
+    for _ in 0..samples {
+        let (distance, weight) = gray.sample_distance(&ray, 3., None, &Wavelengths::Rgb, &mut rng);
+        match distance {
+            Some(distance) => {
+                assert!(distance < 3.);
+                assert!((weight.xyz() - Vector3::repeat(0.75)).norm() < 1e-12);
+            }
+            None => passed += 1,
+        }
+    }
+    assert!((passed as f64 / samples as f64 - (-1.2f64).exp()).abs() < 0.01);
+
+    // A sphere of medium in front of the camera, 6 units across
+    let render = |medium: Medium, fog: Option<Medium>, bounces: usize, samples: usize| {
+        let mut config = Config::new(41, 41, bounces, samples);
+        config.set_crop(18, 18, 5, 5);
+        let camera = Camera::new(Vector2::new(41, 41), Vector3::zeros(), Vector3::x());
+        let mut scene = Scene::new(config, camera);
+        let mut sphere = Object::new(
+            Box::new(Sphere),
+            Vector3::repeat(1.),
+            Box::new(Interface),
+            TransformBuilder::new()
+                .translate_x(10.)
+                .scale_uniform(3.)
+                .build(),
+        );
+        sphere.medium = Some(medium);
+        scene.objects.push(sphere);
+        scene.environment = Some(Environment::Constant(Vector3::repeat(1.)));
+        scene.fog = fog;
+
+        while !scene.is_finished() {
+            scene.sample();
+        }
+        scene.render.get_pixel_averaged(20, 20)
+    };
+
+    // The environment seen through an absorbing medium, or through a medium with
+    // a density texture, whose tracking runs into null collisions
+    let absorbing = Medium::homogeneous(extinction, Vector3::zeros(), 0.);
+    let expected = extinction.map(|e| (-e * 6.).exp());
+    let seen = render(absorbing.clone(), None, 4, 4096);
+    assert!((seen - expected).norm() < 0.03, "{seen:?}");
+    let half = Arc::new(SolidColor(Vector3::repeat(0.5)));
+    let thinner = Medium::heterogeneous(extinction, Vector3::zeros(), 0., half, 2.);
+    let seen = render(thinner, None, 4, 4096);
+    let expected = extinction.map(|e| (-e * 3.).exp());
+    assert!((seen - expected).norm() < 0.03, "{seen:?}");
+    let noise = Arc::new(Noise::new(Vector3::repeat(0.5), Vector3::repeat(1.5), 0.5));
+    let varying = Medium::heterogeneous(extinction, Vector3::zeros(), 0., noise, 1.5);
+    let seen = render(varying, None, 4, 64);
+    assert!(seen.iter().all(|c| c.is_finite() && *c > 0.), "{seen:?}");
+
+    // Light from the environment never makes it through fog reaching out endlessly
+    let fog = Medium::homogeneous(Vector3::repeat(0.05), Vector3::zeros(), 0.);
+    assert_eq!(render(absorbing, Some(fog.clone()), 4, 4), Vector3::zeros());
+
+    // Media which only scatter lose no light, so in a constant environment
+    // everything looks as bright as the environment itself
+    let scattering = Medium::homogeneous(Vector3::zeros(), Vector3::repeat(0.5), 0.5);
+    let seen = render(scattering, None, 64, 512);
+    assert!((seen - Vector3::repeat(1.)).norm() < 0.05, "{seen:?}");
+
+    // Shadow rays to a point light fade in fog on the way to a white wall
+    // and back, the light and camera being 7 units away from it
+    let wall = |fog: Option<Medium>| {
+        let mut config = Config::new(41, 41, 2, 4096);
+        config.set_crop(18, 18, 5, 5);
+        let camera = Camera::new(Vector2::new(41, 41), Vector3::zeros(), Vector3::x());
+        let mut scene = Scene::new(config, camera);
+        scene.objects.push(Object::new(
+            Box::new(Sphere),
+            Vector3::repeat(1.),
+            Box::new(Lambertian),
+            TransformBuilder::new()
+                .translate_x(10.)
+                .scale_uniform(3.)
+                .build(),
+        ));
+        scene.lights.push(Box::new(PointLight::new(
+            Vector3::zeros(),
+            Vector3::repeat(49.),
+        )));
+        scene.fog = fog;
+
+        while !scene.is_finished() {
+            scene.sample();
+        }
+        scene.render.get_pixel_averaged(20, 20).x
+    };
+    let clear = wall(None);
+    let foggy = wall(Some(fog));
+    assert!(
+        (foggy / clear - (-0.05 * 14f64).exp()).abs() < 0.01,
+        "{foggy} {clear}"
+    );
+}
@@ -0,0 +1,414 @@
+use std::{f64::consts::PI, fmt};
+
+use nalgebra::{Vector2, Vector3};
+use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
+
+use super::{Fnv1a, ImageError, srgb_decode};
+
+/// Where a texture gets looked up, on the surface of an object
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TextureCoordinates {
+    /// Position in the coordinates of the object, so that solid textures
+    /// move, turn and scale along with it
+    pub point: Vector3<f64>,
+    /// Position on the surface, with (0, 0) at the bottom left of an image
+    pub uv: Vector2<f64>,
+}
+
+/// Color varying across a surface, which materials and objects get tinted by
+pub trait Texture: fmt::Debug {
+    /// Linear color at the coordinates
+    fn evaluate(&self, coordinates: &TextureCoordinates) -> Vector3<f64>;
+}
+
+/// The same color everywhere
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SolidColor(pub Vector3<f64>);
+
+impl Texture for SolidColor {
+    fn evaluate(&self, _coordinates: &TextureCoordinates) -> Vector3<f64> {
+        self.0
+    }
+}
+
+/// Which coordinates a [Checker] is laid out in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheckerSpace {
+    /// Squares on the surface
+    Uv,
+    /// Cubes filling the object, which the surface cuts through
+    Object,
+}
+
+/// Alternating squares or cubes of two colors
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checker {
+    pub even: Vector3<f64>,
+    pub odd: Vector3<f64>,
+    /// Number of squares per unit of UV, or cubes per unit of object space
+    pub frequency: f64,
+    pub space: CheckerSpace,
+}
+
+impl Checker {
+    pub fn new(even: Vector3<f64>, odd: Vector3<f64>, frequency: f64, space: CheckerSpace) -> Self {
+        Self {
+            even,
+            odd,
+            frequency,
+            space,
+        }
+    }
+}
+
+impl Texture for Checker {
+    fn evaluate(&self, coordinates: &TextureCoordinates) -> Vector3<f64> {
+        let cells = match self.space {
+            CheckerSpace::Uv => coordinates.uv.push(0.),
+            CheckerSpace::Object => coordinates.point,
+        } * self.frequency;
+        let sum: f64 = cells.map(f64::floor).sum();
+
+        if sum.rem_euclid(2.) == 0. {
+            self.even
+        } else {
+            self.odd
+        }
+    }
+}
+
+/// Gradient noise by Perlin 2002, smooth and random looking, but the same at
+/// the same point every time. The building block of the procedural textures
+#[derive(Clone)]
+pub struct Perlin {
+    seed: u64,
+    /// Shuffled numbers from 0 to 255, repeated twice, so that hashing the
+    /// corners of a cell never runs past the end
+    permutation: Vec<u8>,
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut permutation: Vec<u8> = (0..=255).collect();
+        permutation.shuffle(&mut StdRng::seed_from_u64(seed));
+        permutation.extend_from_within(..);
+
+        Self { seed, permutation }
+    }
+
+    /// Noise from about -1 to 1, which is 0 at every integer point
+    pub fn noise(&self, point: Vector3<f64>) -> f64 {
+        let cell = point.map(f64::floor);
+        let f = point - cell;
+        let [x, y, z] = [cell.x, cell.y, cell.z].map(|c| (c as i64 & 255) as usize);
+        let p = &self.permutation;
+        let hash = |i: usize, j: usize, k: usize| p[p[p[x + i] as usize + y + j] as usize + z + k];
+
+        // Every corner has a gradient pointing towards one of the 12 edges of a
+        // cube, which gets dotted with the offset from the corner
+        let gradient = |hash: u8, x: f64, y: f64, z: f64| {
+            let h = hash & 15;
+            let u = if h < 8 { x } else { y };
+            let v = match h {
+                0..4 => y,
+                12 | 14 => x,
+                _ => z,
+            };
+            (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+        };
+
+        // Quintic, so that the noise has continuous second derivatives
+        let fade = f.map(|t| t * t * t * (t * (t * 6. - 15.) + 10.));
+        let lerp = |t: f64, a: f64, b: f64| a + t * (b - a);
+        let corner = |i: usize, j: usize, k: usize| {
+            gradient(
+                hash(i, j, k),
+                f.x - i as f64,
+                f.y - j as f64,
+                f.z - k as f64,
+            )
+        };
+
+        lerp(
+            fade.z,
+            lerp(
+                fade.y,
+                lerp(fade.x, corner(0, 0, 0), corner(1, 0, 0)),
+                lerp(fade.x, corner(0, 1, 0), corner(1, 1, 0)),
+            ),
+            lerp(
+                fade.y,
+                lerp(fade.x, corner(0, 0, 1), corner(1, 0, 1)),
+                lerp(fade.x, corner(0, 1, 1), corner(1, 1, 1)),
+            ),
+        )
+    }
+
+    /// Fractional Brownian motion: octaves of noise, each twice as detailed
+    /// and half as strong as the one before
+    pub fn fbm(&self, point: Vector3<f64>, octaves: u32) -> f64 {
+        (0..octaves)
+            .map(|octave| {
+                let frequency = 2f64.powi(octave as i32);
+                self.noise(point * frequency) / frequency
+            })
+            .sum()
+    }
+
+    /// Like [Perlin::fbm], but adding up the magnitude of every octave,
+    /// which gives sharp creases where the noise crosses 0
+    pub fn turbulence(&self, point: Vector3<f64>, octaves: u32) -> f64 {
+        (0..octaves)
+            .map(|octave| {
+                let frequency = 2f64.powi(octave as i32);
+                self.noise(point * frequency).abs() / frequency
+            })
+            .sum()
+    }
+}
+
+impl fmt::Debug for Perlin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Perlin").field("seed", &self.seed).finish()
+    }
+}
+
+/// Blend from one color to another, following [Perlin::fbm] noise
+#[derive(Clone, Debug)]
+pub struct Noise {
+    pub perlin: Perlin,
+    pub low: Vector3<f64>,
+    pub high: Vector3<f64>,
+    /// Size of the largest features, in units of object space
+    pub size: f64,
+    pub octaves: u32,
+}
+
+impl Noise {
+    pub fn new(low: Vector3<f64>, high: Vector3<f64>, size: f64) -> Self {
+        Self {
+            perlin: Perlin::new(0),
+            low,
+            high,
+            size,
+            octaves: 5,
+        }
+    }
+}
+
+impl Texture for Noise {
+    fn evaluate(&self, coordinates: &TextureCoordinates) -> Vector3<f64> {
+        let noise = self.perlin.fbm(coordinates.point / self.size, self.octaves);
+        self.low.lerp(&self.high, (0.5 + 0.5 * noise).clamp(0., 1.))
+    }
+}
+
+/// Veins along the X axis, bent by [Perlin::turbulence]
+#[derive(Clone, Debug)]
+pub struct Marble {
+    pub perlin: Perlin,
+    pub base: Vector3<f64>,
+    pub vein: Vector3<f64>,
+    /// Distance from one vein to the next, in units of object space
+    pub spacing: f64,
+    /// How far the veins get pushed around, in units of the spacing
+    pub turbulence: f64,
+    pub octaves: u32,
+}
+
+impl Marble {
+    pub fn new(base: Vector3<f64>, vein: Vector3<f64>, spacing: f64) -> Self {
+        Self {
+            perlin: Perlin::new(0),
+            base,
+            vein,
+            spacing,
+            turbulence: 2.,
+            octaves: 6,
+        }
+    }
+}
+
+impl Texture for Marble {
+    fn evaluate(&self, coordinates: &TextureCoordinates) -> Vector3<f64> {
+        let point = coordinates.point / self.spacing;
+        let offset = self.turbulence * self.perlin.turbulence(point, self.octaves);
+        // The veins are thin, where the sine wave peaks
+        let wave = 0.5 + 0.5 * (2. * PI * (point.x + offset)).sin();
+        self.base.lerp(&self.vein, wave.powi(4))
+    }
+}
+
+/// Growth rings around the Y axis, made a little irregular by [Perlin::noise]
+#[derive(Clone, Debug)]
+pub struct Wood {
+    pub perlin: Perlin,
+    /// Color of the wood growing in spring, at the inside of every ring
+    pub early: Vector3<f64>,
+    /// Darker color of the wood growing late in the year, at the outside of every ring
+    pub late: Vector3<f64>,
+    /// Distance from one ring to the next, in units of object space
+    pub spacing: f64,
+    /// How far the rings wander, in units of the spacing
+    pub turbulence: f64,
+}
+
+impl Wood {
+    pub fn new(early: Vector3<f64>, late: Vector3<f64>, spacing: f64) -> Self {
+        Self {
+            perlin: Perlin::new(0),
+            early,
+            late,
+            spacing,
+            turbulence: 0.5,
+        }
+    }
+}
+
+impl Texture for Wood {
+    fn evaluate(&self, coordinates: &TextureCoordinates) -> Vector3<f64> {
+        let point = coordinates.point / self.spacing;
+        // Rings are stretched along the trunk, so the noise varies slowly along Y
+        let wobble = self
+            .perlin
+            .noise(Vector3::new(point.x, point.y / 8., point.z));
+        let ring = (point.xz().norm() + self.turbulence * wobble).rem_euclid(1.);
+        // Early wood fades into late wood, which ends sharply at the next ring
+        self.early.lerp(&self.late, ring * ring)
+    }
+}
+
+/// What an [ImageTexture] shows outside of the UV range from 0 to 1
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum WrapMode {
+    /// Tile the image
+    #[default]
+    Repeat,
+    /// Tile the image, flipping every other copy, so that the edges meet seamlessly
+    MirroredRepeat,
+    /// Stretch the pixels at the edges
+    ClampToEdge,
+}
+
+impl WrapMode {
+    /// Turn a pixel index outside of the image into one inside of it
+    fn wrap(self, index: i64, size: usize) -> usize {
+        let size = size as i64;
+        let index = match self {
+            WrapMode::Repeat => index.rem_euclid(size),
+            WrapMode::MirroredRepeat => {
+                let index = index.rem_euclid(2 * size);
+                if index < size {
+                    index
+                } else {
+                    2 * size - 1 - index
+                }
+            }
+            WrapMode::ClampToEdge => index.clamp(0, size - 1),
+        };
+        index as usize
+    }
+}
+
+/// Picture mapped onto the surface by its UV coordinates, blending the four
+/// nearest pixels together
+#[derive(Clone)]
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vector3<f64>>,
+    pub wrap: WrapMode,
+    // Fingerprint of the pixels, so that the image can be told apart in [super::Scene::hash]
+    checksum: u64,
+}
+
+impl ImageTexture {
+    /// Create an image from linear colors stored row by row, starting from the top
+    pub fn new(width: usize, height: usize, pixels: Vec<Vector3<f64>>) -> Self {
+        assert!(width > 0 && height > 0);
+        assert_eq!(pixels.len(), width * height);
+
+        let mut hasher = Fnv1a::new();
+        for component in pixels.iter().flat_map(|p| p.iter()) {
+            hasher.write_bytes(&component.to_le_bytes());
+        }
+
+        Self {
+            width,
+            height,
+            pixels,
+            wrap: WrapMode::default(),
+            checksum: hasher.finish(),
+        }
+    }
+
+    /// Decode a PNG or JPEG image, whose colors are sRGB encoded. Transparency
+    /// is dropped
+    pub fn load(bytes: &[u8]) -> Result<Self, ImageError> {
+        Self::decode(bytes, srgb_decode)
+    }
+
+    /// Decode a PNG or JPEG image holding data rather than colors, like a
+    /// normal map, taking the values as they are
+    pub fn load_linear(bytes: &[u8]) -> Result<Self, ImageError> {
+        Self::decode(bytes, |value| value)
+    }
+
+    fn decode(bytes: &[u8], transfer: impl Fn(f64) -> f64) -> Result<Self, ImageError> {
+        let format = if bytes.starts_with(b"\x89PNG") {
+            image::ImageFormat::Png
+        } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+            image::ImageFormat::Jpeg
+        } else {
+            return Err(ImageError::UnknownFormat);
+        };
+
+        let image = image::load_from_memory_with_format(bytes, format)
+            .map_err(ImageError::Decode)?
+            .into_rgb32f();
+        let pixels = image
+            .pixels()
+            .map(|p| Vector3::new(p[0], p[1], p[2]).map(|c| transfer(c as f64)))
+            .collect();
+
+        Ok(Self::new(
+            image.width() as usize,
+            image.height() as usize,
+            pixels,
+        ))
+    }
+
+    fn pixel(&self, x: i64, y: i64) -> Vector3<f64> {
+        let x = self.wrap.wrap(x, self.width);
+        let y = self.wrap.wrap(y, self.height);
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn evaluate(&self, coordinates: &TextureCoordinates) -> Vector3<f64> {
+        // Pixel centers sit at half integers, and the rows start from the top
+        let x = coordinates.uv.x * self.width as f64 - 0.5;
+        let y = (1. - coordinates.uv.y) * self.height as f64 - 0.5;
+        let (left, top) = (x.floor(), y.floor());
+        let (tx, ty) = (x - left, y - top);
+        let (left, top) = (left as i64, top as i64);
+
+        let upper = self.pixel(left, top).lerp(&self.pixel(left + 1, top), tx);
+        let lower = self
+            .pixel(left, top + 1)
+            .lerp(&self.pixel(left + 1, top + 1), tx);
+        upper.lerp(&lower, ty)
+    }
+}
+
+impl fmt::Debug for ImageTexture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ImageTexture")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("wrap", &self.wrap)
+            .field("checksum", &self.checksum)
+            .finish()
+    }
+}
@@ -0,0 +1,109 @@
+/// Rectangular region of the screen, in pixels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Tile {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Grow the tile by `margin` pixels in every direction, without
+    /// leaving a screen of the specified size
+    pub fn expand(&self, margin: usize, screen_width: usize, screen_height: usize) -> Tile {
+        let x = self.x.saturating_sub(margin);
+        let y = self.y.saturating_sub(margin);
+
+        Tile {
+            x,
+            y,
+            width: (self.x + self.width + margin).min(screen_width) - x,
+            height: (self.y + self.height + margin).min(screen_height) - y,
+        }
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    /// Number of pixels inside the tile
+    pub fn area(&self) -> usize {
+        self.width * self.height
+    }
+}
+
+/// [TileScheduler] splits the screen into tiles, and hands them out one by one,
+/// starting in the middle of the screen and spiraling outwards, so the most
+/// interesting part of the image shows up first.
+///
+/// After handing out the last tile, it starts over from the first one.
+#[derive(Clone, Debug)]
+pub struct TileScheduler {
+    tiles: Vec<Tile>,
+    next: usize,
+}
+
+impl TileScheduler {
+    /// Split the `region` into tiles of `tile_size` pixels
+    pub fn new(region: Tile, tile_size: usize) -> Self {
+        let tile_size = tile_size.max(1);
+        let columns = region.width.div_ceil(tile_size);
+        let rows = region.height.div_ceil(tile_size);
+
+        let mut tiles = Vec::with_capacity(columns * rows);
+        for row in 0..rows {
+            for column in 0..columns {
+                let x = region.x + column * tile_size;
+                let y = region.y + row * tile_size;
+
+                tiles.push(Tile::new(
+                    x,
+                    y,
+                    tile_size.min(region.x + region.width - x),
+                    tile_size.min(region.y + region.height - y),
+                ));
+            }
+        }
+
+        // Order the tiles by the ring around the center they belong to,
+        // and by the angle within the ring, which forms a spiral
+        let center_x = (region.x * 2 + region.width) as f64 / 2.;
+        let center_y = (region.y * 2 + region.height) as f64 / 2.;
+        let key = |tile: &Tile| {
+            let dx = (tile.x * 2 + tile.width) as f64 / 2. - center_x;
+            let dy = (tile.y * 2 + tile.height) as f64 / 2. - center_y;
+            let ring = (dx.abs().max(dy.abs()) / tile_size as f64).round();
+
+            (ring, dy.atan2(dx))
+        };
+        tiles.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+
+        Self { tiles, next: 0 }
+    }
+
+    /// Get the next tile to render, and whether it completes a pass over the whole region
+    pub fn next_tile(&mut self) -> Option<(Tile, bool)> {
+        let tile = *self.tiles.get(self.next)?;
+        self.next = (self.next + 1) % self.tiles.len();
+
+        Some((tile, self.next == 0))
+    }
+
+    /// Number of tiles in one pass
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+}
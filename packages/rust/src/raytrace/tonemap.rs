@@ -0,0 +1,161 @@
+use nalgebra::{Matrix3, Vector3};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// Curve used to compress high dynamic range values into the displayable 0..1 range
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneMapOperator {
+    /// Values above 1 are simply clipped
+    Linear,
+    /// `c / (1 + c)`, never reaches pure white
+    Reinhard,
+    /// Reinhard curve which maps the white point to 1
+    ExtendedReinhard,
+    /// Stephen Hill's fit of the ACES reference rendering transform
+    Aces,
+    /// Troy Sobotka's AgX, desaturates bright colors instead of skewing their hue
+    AgX,
+}
+
+/// [ToneMapping] describes how linear radiance stored in the [super::Render]
+/// is turned into display colors. It is applied when reading the render out,
+/// so it can be changed at any time without re-rendering.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    /// Exposure compensation in stops (EV). Each stop doubles the brightness
+    pub exposure: f64,
+    /// Smallest value which is mapped to pure white by [ToneMapOperator::ExtendedReinhard]
+    pub white_point: f64,
+}
+
+#[wasm_bindgen]
+impl ToneMapping {
+    #[wasm_bindgen(constructor)]
+    pub fn new(operator: ToneMapOperator, exposure: f64) -> Self {
+        Self {
+            operator,
+            exposure,
+            white_point: 4.,
+        }
+    }
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self::new(ToneMapOperator::Linear, 0.)
+    }
+}
+
+impl ToneMapping {
+    /// Convert a linear color into an sRGB encoded display color in the 0..1 range
+    pub fn apply(&self, color: Vector3<f64>) -> Vector3<f64> {
+        let exposed = color.map(|c| c.max(0.)) * 2f64.powf(self.exposure);
+
+        let mapped = match self.operator {
+            ToneMapOperator::Linear => exposed,
+            ToneMapOperator::Reinhard => exposed.map(|c| c / (1. + c)),
+            ToneMapOperator::ExtendedReinhard => {
+                let white_squared = self.white_point * self.white_point;
+                exposed.map(|c| c * (1. + c / white_squared) / (1. + c))
+            }
+            ToneMapOperator::Aces => aces(exposed),
+            ToneMapOperator::AgX => agx(exposed),
+        };
+
+        mapped.map(|c| srgb_encode(c.clamp(0., 1.)))
+    }
+
+    /// Convert linear colors into an 8-bit RGBA image
+    pub fn to_rgba8(&self, pixels: &[Vector3<f64>]) -> Vec<u8> {
+        let mut v = Vec::with_capacity(pixels.len() * 4);
+
+        for &pixel in pixels {
+            let color = self.apply(pixel);
+
+            v.push((color.x * 255.).round() as u8);
+            v.push((color.y * 255.).round() as u8);
+            v.push((color.z * 255.).round() as u8);
+            v.push(255); // alpha
+        }
+
+        v
+    }
+}
+
+/// Exact sRGB transfer function (linear to encoded)
+pub fn srgb_encode(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// Inverse of [srgb_encode] (encoded to linear)
+pub fn srgb_decode(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn aces(color: Vector3<f64>) -> Vector3<f64> {
+    // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+    #[rustfmt::skip]
+    let input = Matrix3::new(
+        0.59719, 0.35458, 0.04823,
+        0.07600, 0.90834, 0.01566,
+        0.02840, 0.13383, 0.83777,
+    );
+    // ODT_SAT => XYZ => D60_2_D65 => sRGB
+    #[rustfmt::skip]
+    let output = Matrix3::new(
+        1.60475, -0.53108, -0.07367,
+        -0.10208, 1.10813, -0.00605,
+        -0.00327, -0.07276, 1.07602,
+    );
+
+    let v = input * color;
+    let a = v
+        .component_mul(&v.add_scalar(0.0245786))
+        .add_scalar(-0.000090537);
+    let b = v
+        .component_mul(&(v * 0.983729).add_scalar(0.4329510))
+        .add_scalar(0.238081);
+
+    output * a.component_div(&b)
+}
+
+fn agx(color: Vector3<f64>) -> Vector3<f64> {
+    const MIN_EV: f64 = -12.47393;
+    const MAX_EV: f64 = 4.026069;
+
+    #[rustfmt::skip]
+    let inset = Matrix3::new(
+        0.842479062253094, 0.0784335999999992, 0.0792237451477643,
+        0.0423282422610123, 0.878468636469772, 0.0791661274605434,
+        0.0423756549057051, 0.0784336, 0.879142973793104,
+    );
+    #[rustfmt::skip]
+    let outset = Matrix3::new(
+        1.19687900512017, -0.0980208811401368, -0.0990297440797205,
+        -0.0528968517574562, 1.15190312990417, -0.0989611768448433,
+        -0.0529716355144438, -0.0980434501171241, 1.15107367264116,
+    );
+
+    // Encode the color logarithmically, and run it through the sigmoid curve
+    let encoded = (inset * color).map(|c| {
+        let x = (c.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    });
+
+    // The curve outputs display encoded values, bring them back to linear
+    (outset * encoded).map(|c| c.max(0.).powf(2.2))
+}
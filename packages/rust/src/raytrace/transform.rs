@@ -89,6 +89,36 @@ impl Transform {
             transformed_vector.z,
         )
     }
+
+    /// Transform a direction or a tangent from object space to world space,
+    /// which unlike a point isn't moved by the translation
+    pub fn apply_vector(&self, vector: Vector3<f64>) -> Vector3<f64> {
+        let vec4 = Vector4::new(vector.x, vector.y, vector.z, 0.0);
+        let transformed_vector = self.transform * vec4;
+
+        Vector3::new(
+            transformed_vector.x,
+            transformed_vector.y,
+            transformed_vector.z,
+        )
+    }
+
+    /// Transform a normal vector from object space to world space.
+    ///
+    /// Normals can't be transformed like points: with non-uniform scaling
+    /// they would stop being perpendicular to the surface. Instead, they are
+    /// multiplied by the inverse transpose of the transform
+    pub fn apply_normal(&self, normal: Vector3<f64>) -> Vector3<f64> {
+        let vec4 = Vector4::new(normal.x, normal.y, normal.z, 0.0);
+        let transformed_normal = self.transform_inverse.transpose() * vec4;
+
+        Vector3::new(
+            transformed_normal.x,
+            transformed_normal.y,
+            transformed_normal.z,
+        )
+        .normalize()
+    }
 }
 
 #[derive(Clone, Copy, Debug, Default)]
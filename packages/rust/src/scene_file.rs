@@ -0,0 +1,288 @@
+//! Every line holds a keyword followed by its arguments, `#` starts a comment:
+//!
+//! ```text
+//! resolution 640 360
+//! bounces 8
+//! samples 16
+//! # trace sampled wavelengths instead of red, green and blue
+//! spectral
+//! # relative error threshold, then the minimal sample count
+//! adaptive 0.01 8
+//! filter gaussian 1.5
+//! # maximal sample brightness, optionally only for indirect light
+//! clamp 10 indirect
+//! # darken pixels brighter than their neighbours by this many standard deviations
+//! outliers 3
+//! # only render the pixels inside of the rectangle: x y width height
+//! crop 160 90 320 180
+//! # color and strength of the light coming from everywhere, or a gradient
+//! # from the color straight down to the one straight up
+//! environment 255 255 255 0.5
+//! environment gradient 80 80 80 120 170 255 1
+//! # daylight with the sun at an elevation and azimuth in degrees, turbidity and strength
+//! sky 30 135 3 0.01
+//! # position, then the point the camera is looking at
+//! camera 0 10 -10 0 0 0
+//! # x y z radius r g b [emission]
+//! sphere 0 0 0 3 255 255 255 1
+//! ```
+
+use std::{fmt, str::FromStr};
+
+use nalgebra::{Vector2, Vector3};
+
+use crate::{
+    raytrace::{Camera, Config, Environment, FilterKind, Scene, Sky, filter::MIN_RADIUS},
+    wasm::SceneObject,
+};
+
+/// Error found while parsing a scene file
+#[derive(Debug)]
+pub struct ParseError {
+    /// Line number, starting from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parsed scene file, which can be turned into any number of [Scene]s
+#[derive(Clone)]
+pub struct SceneFile {
+    pub config: Config,
+    pub camera_position: Vector3<f64>,
+    pub looking_at: Vector3<f64>,
+    pub objects: Vec<SceneObject>,
+    pub environment: Option<Environment>,
+}
+
+impl SceneFile {
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut file = SceneFile {
+            config: Config::new(640, 360, 8, 16),
+            camera_position: Vector3::new(0., 10., -10.),
+            looking_at: Vector3::zeros(),
+            objects: Vec::new(),
+            environment: None,
+        };
+
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| ParseError {
+                line: index + 1,
+                message,
+            };
+
+            // Strip the comments
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            let Some(keyword) = words.next() else {
+                continue;
+            };
+            let args: Vec<&str> = words.collect();
+
+            match keyword {
+                "resolution" => {
+                    let [width, height] = parse_args(&args).map_err(error)?;
+                    file.config.width = width;
+                    file.config.height = height;
+                }
+                "bounces" => {
+                    let [bounces] = parse_args(&args).map_err(error)?;
+                    file.config.max_bounce_count = bounces;
+                }
+                "samples" => {
+                    let [samples] = parse_args(&args).map_err(error)?;
+                    file.config.samples_per_pixel = samples;
+                }
+                "spectral" => {
+                    let []: [f64; 0] = parse_args(&args).map_err(error)?;
+                    file.config.spectral = true;
+                }
+                "adaptive" => {
+                    let [threshold, min_samples] = args[..] else {
+                        return Err(error(
+                            "expected a threshold and minimal sample count".to_string(),
+                        ));
+                    };
+                    let [threshold] = parse_args(&[threshold]).map_err(error)?;
+                    let [min_samples] = parse_args(&[min_samples]).map_err(error)?;
+                    file.config.adaptive_threshold = threshold;
+                    file.config.adaptive_min_samples = min_samples;
+                }
+                "clamp" => {
+                    let (max, indirect_only) = match args[..] {
+                        [max] => (max, false),
+                        [max, "indirect"] => (max, true),
+                        _ => {
+                            return Err(error(
+                                "expected a maximal brightness, optionally followed by \"indirect\""
+                                    .to_string(),
+                            ));
+                        }
+                    };
+                    let [max] = parse_args(&[max]).map_err(error)?;
+                    file.config.clamp_radiance = max;
+                    file.config.clamp_indirect_only = indirect_only;
+                }
+                "outliers" => {
+                    let [deviations] = parse_args(&args).map_err(error)?;
+                    file.config.outlier_rejection = deviations;
+                }
+                "crop" => {
+                    let [x, y, width, height] = parse_args(&args).map_err(error)?;
+                    file.config.set_crop(x, y, width, height);
+                }
+                "filter" => {
+                    let [kind, radius] = args[..] else {
+                        return Err(error("expected a filter kind and radius".to_string()));
+                    };
+                    file.config.filter = match kind {
+                        "box" => FilterKind::Box,
+                        "tent" => FilterKind::Tent,
+                        "gaussian" => FilterKind::Gaussian,
+                        "mitchell" => FilterKind::Mitchell,
+                        _ => return Err(error(format!("unknown filter \"{kind}\""))),
+                    };
+                    let [radius]: [f64; 1] = parse_args(&[radius]).map_err(error)?;
+                    if radius.is_nan() || radius < MIN_RADIUS {
+                        return Err(error(format!(
+                            "the filter radius must be at least half a pixel, {MIN_RADIUS}"
+                        )));
+                    }
+                    file.config.filter_radius = radius;
+                }
+                "environment" => {
+                    let color = |rgb: [u8; 3]| {
+                        Vector3::new(rgb[0] as f64, rgb[1] as f64, rgb[2] as f64) / 255.
+                    };
+
+                    file.environment = Some(match args[..] {
+                        ["gradient", r1, g1, b1, r2, g2, b2, strength] => {
+                            let bottom = parse_args(&[r1, g1, b1]).map_err(error)?;
+                            let top = parse_args(&[r2, g2, b2]).map_err(error)?;
+                            let [strength]: [f64; 1] = parse_args(&[strength]).map_err(error)?;
+                            Environment::Gradient {
+                                bottom: color(bottom) * strength,
+                                top: color(top) * strength,
+                            }
+                        }
+                        [r, g, b, strength] => {
+                            let rgb = parse_args(&[r, g, b]).map_err(error)?;
+                            let [strength]: [f64; 1] = parse_args(&[strength]).map_err(error)?;
+                            Environment::Constant(color(rgb) * strength)
+                        }
+                        _ => {
+                            return Err(error(
+                                "expected a color and strength, or \"gradient\" followed by two colors and a strength"
+                                    .to_string(),
+                            ));
+                        }
+                    });
+                }
+                "sky" => {
+                    let [elevation, azimuth, turbidity, strength]: [f64; 4] =
+                        parse_args(&args).map_err(error)?;
+                    let mut sky = Sky::new(elevation.to_radians(), azimuth.to_radians(), turbidity);
+                    sky.strength = strength;
+                    file.environment = Some(Environment::Sky(sky));
+                }
+                "camera" => {
+                    let [x, y, z, lx, ly, lz] = parse_args(&args).map_err(error)?;
+                    file.camera_position = Vector3::new(x, y, z);
+                    file.looking_at = Vector3::new(lx, ly, lz);
+                }
+                "sphere" => {
+                    if !(7..=8).contains(&args.len()) {
+                        return Err(error(format!(
+                            "expected 7 or 8 arguments, got {}",
+                            args.len()
+                        )));
+                    }
+                    let [x, y, z, radius] = parse_args(&args[0..4]).map_err(error)?;
+                    let [r, g, b] = parse_args(&args[4..7]).map_err(error)?;
+                    // Emission is optional
+                    let [emission] = match args.get(7) {
+                        Some(emission) => parse_args(&[emission]).map_err(error)?,
+                        None => [0.],
+                    };
+
+                    file.objects
+                        .push(SceneObject::new(x, y, z, r, g, b, radius, emission));
+                }
+                _ => return Err(error(format!("unknown keyword \"{keyword}\""))),
+            }
+        }
+
+        Ok(file)
+    }
+
+    /// Create a fresh [Scene] with an empty render
+    pub fn build(&self) -> Scene {
+        let camera = Camera::new(
+            Vector2::new(self.config.width, self.config.height),
+            self.camera_position,
+            self.looking_at,
+        );
+
+        let mut scene = Scene::new(self.config.clone(), camera);
+        scene.environment = self.environment.clone();
+        if let Some(Environment::Sky(sky)) = &self.environment {
+            scene.lights.push(Box::new(sky.sun()));
+        }
+        for object in &self.objects {
+            scene.objects.push(object.clone().into());
+        }
+
+        scene
+    }
+}
+
+impl FromStr for SceneFile {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// Parse exactly `N` arguments of the same type
+pub(crate) fn parse_args<T: FromStr, const N: usize>(args: &[&str]) -> Result<[T; N], String> {
+    if args.len() != N {
+        return Err(format!("expected {N} arguments, got {}", args.len()));
+    }
+
+    let mut values = Vec::with_capacity(N);
+    for arg in args {
+        values.push(
+            arg.parse::<T>()
+                .map_err(|_| format!("invalid argument \"{arg}\""))?,
+        );
+    }
+
+    // The length has been checked above
+    values.try_into().map_err(|_| unreachable!())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SceneFile;
+
+    #[test]
+    // Filter radii below half a pixel must be rejected, rather than quietly raised
+    fn filter_radius() {
+        for radius in ["0", "-1", "0.1", "NaN"] {
+            let Err(error) = SceneFile::parse(&format!("filter tent {radius}")) else {
+                panic!("accepted a radius of {radius}");
+            };
+            assert!(error.message.contains("half a pixel"), "{error}");
+        }
+        let file = SceneFile::parse("filter box 0.5").unwrap();
+        assert_eq!(file.config.filter_radius, 0.5);
+    }
+}
@@ -2,12 +2,31 @@ use nalgebra::{Vector2, Vector3};
 use wasm_bindgen::prelude::*;
 
 use crate::raytrace::{
-    Camera, Config, Lambertian, Object, Scene as InternalScene, Sphere, TransformBuilder,
+    Aov, Camera, Config, DenoiseSettings, DirectionalLight, Environment, EnvironmentMap,
+    ExrPrecision, Medium, Object, PointLight, Principled, Scene as InternalScene, Sky, Sphere,
+    SpotLight, Tile, ToneMapping, TransformBuilder,
 };
 
+#[wasm_bindgen]
+extern "C" {
+    // `std::time::Instant` is not available in the browser
+    #[wasm_bindgen(js_namespace = Date)]
+    fn now() -> f64;
+}
+
+/// Flatten regions into `[x, y, width, height, x, y, ...]`, which is easy to use from JavaScript
+fn flatten_tiles(tiles: Vec<Tile>) -> Vec<u32> {
+    tiles
+        .iter()
+        .flat_map(|t| [t.x, t.y, t.width, t.height])
+        .map(|v| v as u32)
+        .collect()
+}
+
 /// Facade that abstracts away the object creation
 #[wasm_bindgen]
-struct SceneObject {
+#[derive(Clone)]
+pub struct SceneObject {
     x: f64,
     y: f64,
     z: f64,
@@ -16,12 +35,25 @@ struct SceneObject {
     b: u8,
     radius: f64,
     emission: f64,
+    temperature: Option<f64>,
+    luminous_power: Option<f64>,
+    /// Parameters of the [Principled] material, besides the base color,
+    /// starting out as those of [Principled::default]
+    pub metallic: f64,
+    pub roughness: f64,
+    pub specular: f64,
+    pub specular_tint: f64,
+    pub sheen: f64,
+    pub clearcoat: f64,
+    pub transmission: f64,
 }
 
 #[wasm_bindgen]
 impl SceneObject {
     #[wasm_bindgen(constructor)]
+    #[allow(clippy::too_many_arguments)]
     pub fn new(x: f64, y: f64, z: f64, r: u8, g: u8, b: u8, radius: f64, emission: f64) -> Self {
+        let material = Principled::default();
         Self {
             x,
             y,
@@ -31,27 +63,61 @@ impl SceneObject {
             b,
             radius,
             emission,
+            temperature: None,
+            luminous_power: None,
+            metallic: material.metallic,
+            roughness: material.roughness,
+            specular: material.specular,
+            specular_tint: material.specular_tint,
+            sheen: material.sheen,
+            clearcoat: material.clearcoat,
+            transmission: material.transmission,
         }
     }
+
+    /// Emit the color of a black body at the temperature in kelvin, instead of
+    /// the RGB color. The emission is then the luminance, 1 being 683 candela
+    /// per square meter
+    pub fn set_temperature(&mut self, kelvin: f64) {
+        self.temperature = Some(kelvin);
+    }
+
+    /// Scale the emission, so that the object gives off the luminous power in lumens
+    pub fn set_luminous_power(&mut self, lumens: f64) {
+        self.luminous_power = Some(lumens);
+    }
+}
+
+/// Convert 8-bit color components into a linear color from 0 to 1
+fn color(r: u8, g: u8, b: u8) -> Vector3<f64> {
+    Vector3::new(r as f64 / 255., g as f64 / 255., b as f64 / 255.)
 }
 
 impl From<SceneObject> for Object {
     fn from(obj: SceneObject) -> Self {
-        let color = Vector3::new(
-            obj.r as f64 / 255.,
-            obj.g as f64 / 255.,
-            obj.b as f64 / 255.,
-        );
+        let color = color(obj.r, obj.g, obj.b);
         let transform = TransformBuilder::new()
             .translate(Vector3::new(obj.x, obj.y, obj.z))
             .scale_uniform(obj.radius)
             .build();
 
-        return if obj.emission == 0. {
+        // The color goes into the material, so that the object stays white
+        // and doesn't tint the specular reflection
+        let material = Box::new(Principled {
+            metallic: obj.metallic,
+            roughness: obj.roughness,
+            specular: obj.specular,
+            specular_tint: obj.specular_tint,
+            sheen: obj.sheen,
+            clearcoat: obj.clearcoat,
+            transmission: obj.transmission,
+            ..Principled::new(color)
+        });
+        let mut object = if obj.emission == 0. && obj.luminous_power.is_none() {
             Object::new(
                 Box::new(Sphere::new()),
-                color,
-                Box::new(Lambertian::new()),
+                Vector3::repeat(1.),
+                material,
                 transform,
             )
         } else {
@@ -59,15 +125,24 @@ impl From<SceneObject> for Object {
                 Box::new(Sphere::new()),
                 color,
                 obj.emission,
-                Box::new(Lambertian::new()),
+                material,
                 transform,
             )
         };
+
+        if let Some(temperature) = obj.temperature {
+            object.set_emission_temperature(temperature, obj.emission);
+        }
+        if let Some(lumens) = obj.luminous_power {
+            object.set_luminous_power(lumens);
+        }
+
+        object
     }
 }
 
 #[wasm_bindgen]
-struct Position {
+pub struct Position {
     x: f64,
     y: f64,
     z: f64,
@@ -89,30 +164,11 @@ impl From<Position> for Vector3<f64> {
 
 /// Facade that abstracts the internal scene structure away for JavaScript code
 #[wasm_bindgen]
-struct Scene {
-    config: Config,
+pub struct Scene {
     scene: InternalScene,
-}
-
-impl From<&Scene> for Vec<u8> {
-    fn from(facade: &Scene) -> Self {
-        let cfg = &facade.config;
-
-        let mut v = Vec::with_capacity(cfg.width * cfg.height * 4);
-
-        for y in 0..cfg.height {
-            for x in 0..cfg.width {
-                let mat = facade.scene.render.get_pixel_corrected(x, y);
-
-                v.push((mat.x as f64 * 255.) as u8);
-                v.push((mat.y as f64 * 255.) as u8);
-                v.push((mat.z as f64 * 255.) as u8);
-                v.push(255); // alpha
-            }
-        }
-
-        v
-    }
+    tone_mapping: ToneMapping,
+    // Index of the light added along with the sky, replaced when the environment changes
+    sun: Option<usize>,
 }
 
 #[wasm_bindgen]
@@ -130,20 +186,338 @@ impl Scene {
             looking_at.into(),
         );
 
-        let mut scene = InternalScene::new(config.clone(), cam.clone());
+        let mut scene = InternalScene::new(config, cam);
 
         for obj in objects {
             scene.objects.push(obj.into());
         }
 
-        return Scene { config, scene };
+        Scene {
+            scene,
+            tone_mapping: ToneMapping::default(),
+            sun: None,
+        }
+    }
+
+    /// Add a light shining equally in all directions from a single point.
+    /// The intensity is given in watts per steradian
+    pub fn add_point_light(&mut self, position: Position, r: u8, g: u8, b: u8, intensity: f64) {
+        self.scene.lights.push(Box::new(PointLight::new(
+            position.into(),
+            color(r, g, b) * intensity,
+        )));
+    }
+
+    /// Add a light shining from a point into a cone around the `direction`.
+    /// The light fades out between the inner and outer angles, given in degrees
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_spot_light(
+        &mut self,
+        position: Position,
+        direction: Position,
+        r: u8,
+        g: u8,
+        b: u8,
+        intensity: f64,
+        inner_angle: f64,
+        outer_angle: f64,
+    ) {
+        self.scene.lights.push(Box::new(SpotLight::new(
+            position.into(),
+            direction.into(),
+            color(r, g, b) * intensity,
+            inner_angle.to_radians(),
+            outer_angle.to_radians(),
+        )));
+    }
+
+    /// Add light coming from infinitely far away, travelling along the `direction`,
+    /// like sunlight. The irradiance is given in watts per square meter, the angular
+    /// diameter of the light source in degrees (0.53 for the sun, 0 for sharp shadows)
+    pub fn add_directional_light(
+        &mut self,
+        direction: Position,
+        r: u8,
+        g: u8,
+        b: u8,
+        irradiance: f64,
+        angular_diameter: f64,
+    ) {
+        self.scene.lights.push(Box::new(DirectionalLight::new(
+            direction.into(),
+            color(r, g, b) * irradiance,
+            angular_diameter.to_radians(),
+        )));
+    }
+
+    /// Let rays which miss every object see the same color from every direction
+    pub fn set_environment_color(&mut self, r: u8, g: u8, b: u8, strength: f64) {
+        self.set_environment(Environment::Constant(color(r, g, b) * strength));
+    }
+
+    /// Let rays which miss every object see a blend from the bottom color
+    /// straight down to the top color straight up
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_environment_gradient(
+        &mut self,
+        bottom_r: u8,
+        bottom_g: u8,
+        bottom_b: u8,
+        top_r: u8,
+        top_g: u8,
+        top_b: u8,
+        strength: f64,
+    ) {
+        self.set_environment(Environment::Gradient {
+            bottom: color(bottom_r, bottom_g, bottom_b) * strength,
+            top: color(top_r, top_g, top_b) * strength,
+        });
+    }
+
+    /// Surround the scene with an equirectangular panorama, from the bytes of
+    /// a Radiance HDR or OpenEXR file. Throws if the image can't be decoded
+    pub fn set_environment_map(&mut self, image: &[u8], strength: f64) -> Result<(), JsError> {
+        let map = EnvironmentMap::load(image)?;
+        let map = if strength == 1. {
+            map
+        } else {
+            map.scaled(strength)
+        };
+
+        self.set_environment(Environment::Map(map));
+        Ok(())
+    }
+
+    /// Light the scene by a clear sky and the sun, at an elevation above the
+    /// horizon and an azimuth clockwise from north (-Z) in degrees. The turbidity
+    /// goes from 2 for a very clear sky to 10 for a hazy one. At a strength of 1,
+    /// a luminance of 683 candela per square meter has a radiance of 1
+    pub fn set_sky(&mut self, elevation: f64, azimuth: f64, turbidity: f64, strength: f64) {
+        let mut sky = Sky::new(elevation.to_radians(), azimuth.to_radians(), turbidity);
+        sky.strength = strength;
+        self.set_environment(Environment::Sky(sky));
+    }
+
+    /// Light the scene by a clear sky and the sun, where it stands at a place and
+    /// time. The latitude and longitude are given in degrees, north and east being
+    /// positive, the day of the year starts at 1, and the time is given in hours UTC
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_sky_at_time(
+        &mut self,
+        latitude: f64,
+        longitude: f64,
+        day_of_year: f64,
+        hours: f64,
+        turbidity: f64,
+        strength: f64,
+    ) {
+        let mut sky = Sky::at_time(
+            latitude.to_radians(),
+            longitude.to_radians(),
+            day_of_year,
+            hours,
+            turbidity,
+        );
+        sky.strength = strength;
+        self.set_environment(Environment::Sky(sky));
+    }
+
+    /// Make rays which miss every object black again
+    pub fn clear_environment(&mut self) {
+        self.scene.environment = None;
+        self.set_sun(None);
+    }
+
+    /// Fill the space around the objects with fog of the color, which is the
+    /// fraction of the light it scatters rather than absorbs. The density is the
+    /// fraction of the light fading per unit of distance, and the asymmetry goes
+    /// from -1 scattering light back to 1 scattering it forward
+    pub fn set_fog(&mut self, r: u8, g: u8, b: u8, density: f64, asymmetry: f64) {
+        let albedo = color(r, g, b);
+        self.scene.fog = Some(Medium::homogeneous(
+            (Vector3::repeat(1.) - albedo) * density,
+            albedo * density,
+            asymmetry.clamp(-0.99, 0.99),
+        ));
+    }
+
+    /// Clear the air of fog again
+    pub fn clear_fog(&mut self) {
+        self.scene.fog = None;
     }
 
     pub fn sample(&mut self) {
         self.scene.sample();
     }
 
+    /// Render tiles of the frame until the time budget runs out, continuing
+    /// where the previous call left off. At least one tile is always rendered.
+    ///
+    /// Returns the changed regions as `[x, y, width, height, ...]`
+    pub fn sample_for(&mut self, budget_ms: f64) -> Vec<u32> {
+        let start = now();
+        let mut first = true;
+
+        flatten_tiles(self.scene.sample_while(|| {
+            let keep_going = first || now() - start < budget_ms;
+            first = false;
+            keep_going
+        }))
+    }
+
+    /// Render the next `count` tiles of the frame.
+    ///
+    /// Returns the changed regions as `[x, y, width, height, ...]`
+    pub fn sample_tiles(&mut self, count: usize) -> Vec<u32> {
+        flatten_tiles(self.scene.sample_tiles(count))
+    }
+
+    /// Only render the pixels inside of the rectangle from now on.
+    ///
+    /// With `keep_previous`, the rest of the image keeps what has been rendered
+    /// so far, otherwise the render starts over
+    pub fn set_crop(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        keep_previous: bool,
+    ) {
+        self.scene
+            .set_crop(Some(Tile::new(x, y, width, height)), keep_previous);
+    }
+
+    /// Render the whole frame again, see [Scene::set_crop]
+    pub fn clear_crop(&mut self, keep_previous: bool) {
+        self.scene.set_crop(None, keep_previous);
+    }
+
+    /// Number of full passes over the frame rendered so far
+    pub fn sample_count(&self) -> usize {
+        self.scene.render.sample_count()
+    }
+
+    /// How far along the render is, from 0 to 1. With adaptive sampling
+    /// enabled, converged pixels count as finished
+    pub fn convergence(&self) -> f64 {
+        self.scene.render.convergence()
+    }
+
+    /// Change how the image is tone mapped. Takes effect on the next
+    /// [Scene::get_image] call, without having to re-render anything
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) {
+        self.tone_mapping = tone_mapping;
+    }
+
+    /// Get the rendered image as 8-bit RGBA pixels
     pub fn get_image(&self) -> Vec<u8> {
-        return self.into();
+        self.scene.render.to_rgba8(&self.tone_mapping)
+    }
+
+    /// Get the rendered image with the noise filtered out, as 8-bit RGBA pixels.
+    ///
+    /// Meant for low sample count previews. Enable `config.aovs` for the
+    /// denoiser to keep the edges sharp
+    pub fn get_image_denoised(&self) -> Vec<u8> {
+        let pixels = self.scene.render.denoised(&DenoiseSettings::default());
+        self.tone_mapping.to_rgba8(&pixels)
+    }
+
+    /// Get the averaged values of an auxiliary buffer, row by row, with
+    /// as many floats per pixel as the AOV has channels.
+    ///
+    /// Returns `undefined` unless `config.aovs` was enabled
+    pub fn get_aov(&self, aov: Aov) -> Option<Vec<f32>> {
+        let channels = aov.channels().len();
+        let pixels = self.scene.render.get_aov(aov)?;
+
+        Some(
+            pixels
+                .iter()
+                .flat_map(|p| p.iter().take(channels).map(|&c| c as f32))
+                .collect(),
+        )
+    }
+
+    /// Get the raw linear render as an OpenEXR file
+    pub fn get_exr(&self, precision: ExrPrecision) -> Result<Vec<u8>, JsError> {
+        Ok(self.scene.render.to_exr(precision)?)
+    }
+
+    /// Save the render so far, so that it can be continued later with [Scene::resume]
+    pub fn get_checkpoint(&self) -> Vec<u8> {
+        self.scene.checkpoint()
+    }
+
+    /// Continue rendering from a checkpoint. Throws if it was taken from a different scene
+    pub fn resume(&mut self, checkpoint: &[u8]) -> Result<(), JsError> {
+        Ok(self.scene.resume(checkpoint)?)
+    }
+
+    /// Add the samples of a checkpoint taken from another render of the same
+    /// scene, e.g. one running in a different tab. Throws if the scene doesn't match
+    pub fn merge_checkpoint(&mut self, checkpoint: &[u8]) -> Result<(), JsError> {
+        Ok(self.scene.merge_checkpoint(checkpoint)?)
+    }
+
+    /// Get the raw linear render as a Radiance HDR file
+    pub fn get_hdr(&self) -> Vec<u8> {
+        self.scene.render.to_hdr()
+    }
+}
+
+impl Scene {
+    /// Replace the environment, along with the sun of a sky
+    fn set_environment(&mut self, environment: Environment) {
+        let sun = match &environment {
+            Environment::Sky(sky) => Some(sky.sun()),
+            _ => None,
+        };
+
+        self.scene.environment = Some(environment);
+        self.set_sun(sun);
+    }
+
+    fn set_sun(&mut self, sun: Option<DirectionalLight>) {
+        match (self.sun, sun) {
+            (Some(index), Some(sun)) => self.scene.lights[index] = Box::new(sun),
+            (Some(index), None) => {
+                self.scene.lights.remove(index);
+                self.sun = None;
+            }
+            (None, Some(sun)) => {
+                self.sun = Some(self.scene.lights.len());
+                self.scene.lights.push(Box::new(sun));
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raytrace::{Wavelengths, blackbody};
+
+    #[test]
+    // Objects given a temperature emit the spectrum of a black body
+    fn emission_temperature() {
+        let mut lamp = SceneObject::new(0., 0., 0., 255, 255, 255, 1., 2.);
+        lamp.set_temperature(2700.);
+        let lamp = Object::from(lamp);
+        assert_eq!(lamp.emission_temperature, Some(2700.));
+        let wavelengths = Wavelengths::sample(0.3);
+        assert_eq!(
+            lamp.emission(&wavelengths),
+            wavelengths.blackbody(2700., 2., blackbody(2700.) * 2.)
+        );
+    }
+
+    #[test]
+    // Objects start out with the default material
+    fn default_material() {
+        let object = SceneObject::new(0., 0., 0., 255, 255, 255, 1., 0.);
+        assert_eq!(object.roughness, Principled::default().roughness);
     }
 }
@@ -11,9 +11,21 @@ emit({
   type: "loaded",
 });
 
+// How long to render between frames sent to the main thread
+const FRAME_BUDGET_MS = 100;
+
+// Incremented on every message, so that a running render
+// notices it has been cancelled or replaced by a new one
+let currentRender = 0;
+
 addEventListener("message", async (event: MessageEvent<MessageToWorker>) => {
   console.debug("[worker] - message from main thread", event.data);
 
+  const renderId = ++currentRender;
+  if (event.data.type === "cancel") {
+    return;
+  }
+
   const cfg = event.data.settings;
   const camPos = cfg.cameraPosition;
   const lookAt = cfg.lookingAt;
@@ -42,6 +54,9 @@ addEventListener("message", async (event: MessageEvent<MessageToWorker>) => {
       cfg.maxBounceCount,
       cfg.samplesPerPixel,
     );
+    // The denoiser relies on the albedo, normal and depth buffers
+    config.aovs = cfg.denoise;
+    config.adaptive_threshold = cfg.adaptiveThreshold;
 
     const scene = new Scene(
       new Position(camPos.x, camPos.y, camPos.z),
@@ -51,29 +66,45 @@ addEventListener("message", async (event: MessageEvent<MessageToWorker>) => {
     );
     const start = performance.now();
 
-    for (let i = 0; i < cfg.samplesPerPixel; i++) {
-      scene.sample();
+    let finished = false;
+
+    // With adaptive sampling the render may converge before
+    // reaching the requested number of samples
+    while (!finished) {
+      // Render tiles for a while, then give the other messages a chance to arrive
+      scene.sample_for(FRAME_BUDGET_MS);
+      await new Promise((resolve) => setTimeout(resolve, 0));
+
+      if (renderId !== currentRender) {
+        scene.free();
+        return;
+      }
+
+      const progress = scene.convergence();
+      finished = progress >= 1;
 
       const image = await globalThis.createImageBitmap(
         new ImageData(
-          new Uint8ClampedArray(scene.get_image()),
+          new Uint8ClampedArray(
+            cfg.denoise ? scene.get_image_denoised() : scene.get_image(),
+          ),
           cfg.width,
           cfg.height,
         ),
       );
 
-      if (i != cfg.samplesPerPixel - 1) {
+      if (!finished) {
         emit(
           {
             type: "frame",
-            progress: (i + 1) / cfg.samplesPerPixel,
+            progress,
             image: image,
           },
           [image],
         );
       } else {
         const totalRenderTime = (performance.now() - start) / 1000;
-        const samplesPerSecond = cfg.samplesPerPixel / totalRenderTime;
+        const samplesPerSecond = scene.sample_count() / totalRenderTime;
         const megapixelsPerSecond =
           (samplesPerSecond * cfg.width * cfg.height) / 1_000_000;
         const stats = {